@@ -0,0 +1,210 @@
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+};
+
+use clap::{CommandFactory, ErrorKind, Parser};
+use toml::{value::Table, Value};
+
+use crate::{
+    arguments::{ArgumentParser, Mode, SaveArguments},
+    defaults,
+};
+
+/// The table in the defaults file which saved commands are kept in, as `[alias.NAME]` tables.
+pub(crate) const SECTION: &str = "alias";
+
+/// Save a command under a name by appending an `[alias.NAME]` table to the defaults file, leaving
+/// the rest of the file as it was. The arguments are checked before they're saved, and a name
+/// which is already taken is an error, rather than quietly replacing what was saved before.
+pub(crate) fn save(args: &SaveArguments) -> Result<(), io::Error> {
+    let path = defaults::default_path();
+    let mut arguments = vec![String::from("attempt")];
+    arguments.extend(args.arguments.iter().cloned());
+    match ArgumentParser::try_parse_from(&arguments) {
+        Ok(ArgumentParser {
+            mode: Mode::Save(_) | Mode::Run(_),
+            ..
+        }) => fail(String::from("a saved command can't save or run another")),
+        Ok(parsed) => parsed.validate().unwrap_or_else(|e| e.exit()),
+        Err(e) => e.exit(),
+    }
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if aliases(&text)
+        .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)))
+        .contains_key(&args.name)
+    {
+        fail(format!(
+            "{} is already saved in {}, edit or remove it there to change it",
+            args.name,
+            path.display()
+        ));
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    write!(
+        file,
+        "{}{}",
+        separator(&text),
+        to_toml(&args.name, &args.arguments)
+    )?;
+
+    Ok(())
+}
+
+/// What to write before a saved command to set it apart from the text already in the file.
+fn separator(text: &str) -> &'static str {
+    if text.is_empty() {
+        ""
+    } else if text.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    }
+}
+
+/// The arguments a saved command stands for, following the program name. A `--` before the
+/// command is kept, so the expanded arguments parse the same as if they had been typed.
+pub(crate) fn expand(name: &str) -> Result<Vec<OsString>, String> {
+    let path = defaults::default_path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    lookup(&text, name).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn lookup(text: &str, name: &str) -> Result<Vec<OsString>, String> {
+    let aliases = aliases(text)?;
+    let arguments = aliases
+        .get(name)
+        .ok_or_else(|| format!("no command is saved as {}", name))?
+        .get("args")
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("{} has no array of args", name))?;
+    let arguments = arguments
+        .iter()
+        .map(|argument| argument.as_str().map(OsString::from))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| format!("the args of {} must all be strings", name))?;
+    match arguments.first().and_then(|argument| argument.to_str()) {
+        Some("save" | "run") => Err(format!("{} can't save or run another command", name)),
+        _ => Ok(arguments),
+    }
+}
+
+/// The saved commands in the text of a defaults file.
+fn aliases(text: &str) -> Result<Table, String> {
+    match text.parse::<Value>().map_err(|e| e.to_string())? {
+        Value::Table(mut table) => match table.remove(SECTION) {
+            Some(Value::Table(aliases)) => Ok(aliases),
+            Some(_) => Err(format!("{} must be a table", SECTION)),
+            None => Ok(Table::new()),
+        },
+        _ => Err(String::from("expected a table of options")),
+    }
+}
+
+/// The `[alias.NAME]` table for a saved command.
+fn to_toml(name: &str, arguments: &[String]) -> String {
+    let mut alias = Table::new();
+    alias.insert(
+        String::from("args"),
+        Value::Array(arguments.iter().cloned().map(Value::String).collect()),
+    );
+    let mut aliases = Table::new();
+    aliases.insert(name.to_string(), Value::Table(alias));
+    let mut table = Table::new();
+    table.insert(SECTION.to_string(), Value::Table(aliases));
+    toml::to_string(&Value::Table(table)).unwrap()
+}
+
+fn fail(message: String) -> ! {
+    ArgumentParser::command()
+        .error(ErrorKind::InvalidValue, message)
+        .exit()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let arguments = [
+            "exponential",
+            "-a",
+            "5",
+            "--",
+            "./deploy.sh",
+            "it's \"prod\"",
+        ]
+        .map(String::from);
+        let text = format!(
+            "attempts = 3\n\n{}\n{}",
+            to_toml("deploy", &arguments),
+            to_toml("sync", &arguments[..1])
+        );
+        assert_eq!(
+            lookup(&text, "deploy").unwrap(),
+            arguments.map(OsString::from)
+        );
+        assert_eq!(
+            lookup(&text, "sync").unwrap(),
+            [OsString::from("exponential")]
+        );
+        assert!(lookup(&text, "build").is_err());
+    }
+
+    #[test]
+    fn test_separator() {
+        assert_eq!(separator(""), "");
+        assert_eq!(separator("attempts = 3\n"), "\n");
+        assert_eq!(separator("attempts = 3"), "\n\n");
+    }
+
+    #[test]
+    fn test_expands_like_typed() {
+        let text = to_toml(
+            "t",
+            &["fixed", "-a", "1", "--", "echo", "--wait", "hi"].map(String::from),
+        );
+        let mut arguments = vec![OsString::from("attempt")];
+        arguments.extend(lookup(&text, "t").unwrap());
+        assert!(defaults::command_follows_separator(&arguments));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(lookup("[alias.a]\nargs = \"fixed\"\n", "a").is_err());
+        assert!(lookup("[alias.a]\nargs = [\"fixed\", 1]\n", "a").is_err());
+        assert!(lookup("[alias.a]\nargs = [\"run\", \"a\"]\n", "a").is_err());
+        assert!(lookup("alias = 1\n", "a").is_err());
+    }
+
+    #[test]
+    fn test_keeps_separator() {
+        let arguments = ["fixed", "--", "make"].map(String::from);
+        let parsed = ArgumentParser::try_parse_from(
+            ["attempt", "save", "build"]
+                .into_iter()
+                .chain(arguments.iter().map(String::as_str)),
+        )
+        .unwrap();
+        let Mode::Save(args) = parsed.mode else {
+            panic!("expected save mode");
+        };
+        assert_eq!(args.name, "build");
+        assert_eq!(args.arguments, arguments);
+    }
+}
@@ -0,0 +1,113 @@
+use std::{ffi::OsString, fs};
+
+use crate::defaults;
+
+/// Replace each `@FILE` argument with the arguments in FILE, one per line, so that long lists of
+/// options needn't fit on the command line. Only `attempt`'s own arguments are expanded, up to
+/// `--` or the start of the command, so that the command's arguments and the values of options
+/// are passed on verbatim, and the program name is never expanded. The arguments read from a file
+/// aren't expanded themselves.
+pub(crate) fn expand(mut args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let mut from = 1;
+    while let Some((at, path)) = defaults::standalone_arguments(&args)
+        .into_iter()
+        .filter(|&at| at >= from)
+        .find_map(|at| Some((at, named_file(&args[at])?.to_string())))
+    {
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read arguments from {}: {}", path, e))?;
+        let inserted: Vec<OsString> = parse(&text).map(OsString::from).collect();
+        from = at + inserted.len();
+        args.splice(at..at + 1, inserted);
+    }
+
+    Ok(args)
+}
+
+/// The file an `@FILE` argument names.
+fn named_file(arg: &OsString) -> Option<&str> {
+    arg.to_str()
+        .and_then(|arg| arg.strip_prefix('@'))
+        .filter(|path| !path.is_empty())
+}
+
+/// The arguments in an argument file: each line is one argument, taken as it is without any
+/// quoting, and blank lines are skipped.
+fn parse(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.trim().is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("fixed\r\n--attempts=5\n\n--retry-if-output-matches=timed out\n")
+                .collect::<Vec<_>>(),
+            [
+                "fixed",
+                "--attempts=5",
+                "--retry-if-output-matches=timed out"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let path = env::temp_dir().join(format!("attempt-args-{}.txt", std::process::id()));
+        fs::write(&path, "fixed\n-a\n5\n").unwrap();
+        let file = format!("@{}", path.display());
+
+        assert_eq!(
+            expand(args(&["attempt", &file, "--", "curl", "-d", "@data.json"])).unwrap(),
+            args(&[
+                "attempt",
+                "fixed",
+                "-a",
+                "5",
+                "--",
+                "curl",
+                "-d",
+                "@data.json"
+            ])
+        );
+        assert_eq!(
+            expand(args(&["attempt", &file, "make"])).unwrap(),
+            args(&["attempt", "fixed", "-a", "5", "make"])
+        );
+        // The command's own arguments are left alone, even before --.
+        assert_eq!(
+            expand(args(&["attempt", "fixed", "mytool", &file, "--", "x"])).unwrap(),
+            args(&["attempt", "fixed", "mytool", &file, "--", "x"])
+        );
+        // As is the value of an option.
+        assert_eq!(
+            expand(args(&["attempt", "fixed", "--log-prefix", &file, "make"])).unwrap(),
+            args(&["attempt", "fixed", "--log-prefix", &file, "make"])
+        );
+        assert!(expand(args(&["attempt", "@/nonexistent", "--", "make"])).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_expand_separator() {
+        let path = env::temp_dir().join(format!("attempt-options-{}.txt", std::process::id()));
+        fs::write(&path, "-a\n5\n--\n").unwrap();
+        let file = format!("@{}", path.display());
+
+        assert_eq!(
+            expand(args(&["attempt", "fixed", &file, "echo", "@hi"])).unwrap(),
+            args(&["attempt", "fixed", "-a", "5", "--", "echo", "@hi"])
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
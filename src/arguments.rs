@@ -1,30 +1,806 @@
-use std::{process::Command, time::Duration};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgAction, Args, CommandFactory, ErrorKind, Parser, Subcommand, ValueEnum};
+use rand::{rngs::StdRng, SeedableRng};
+use regex::bytes::{Regex, RegexSet};
 
-use crate::util::create_duration;
+use crate::{
+    budget::{Budget, Rate},
+    checksum::FileChecksum,
+    child::Written,
+    code_pattern::CodePattern,
+    cores::CoreDumps,
+    gate::{parse_free_disk, parse_free_memory, Requirement},
+    health::HealthCheck,
+    http::{HttpUrl, StatusPattern},
+    probe::Probe,
+    sandbox::{c_path, load_seccomp_filter, Sandbox},
+    start_limit::StartLimit,
+    util::{
+        build_command, create_duration, duration_from_f64, find_bytes, format_hex, hostname,
+        parse_duration, parse_hex, parse_size, parse_timestamp, stagger_offset, EX_TEMPFAIL,
+    },
+    window::TimeWindow,
+};
 
 #[derive(Parser, Debug)]
 pub(crate) struct ArgumentParser {
+    /// Follow a schedule from the defaults file in place of the strategy, as in `attempt
+    /// --schedule slow-api -- curl https://api.example.com/`. It can be used wherever fixed or
+    /// exponential can, such as after the target of wait-for, and options given with it take
+    /// precedence over the schedule's.
+    // Replaced by the schedule's strategy and options before the arguments are parsed, so it's
+    // only here to be documented.
+    #[allow(dead_code)]
+    #[clap(long, value_name("NAME"))]
+    schedule: Option<String>,
     #[clap(subcommand)]
-    pub backoff: BackoffStrategy,
+    pub mode: Mode,
 }
 
 impl ArgumentParser {
     #[allow(unused)]
     pub(crate) fn new(backoff: BackoffStrategy) -> Self {
-        Self { backoff }
+        Self {
+            schedule: None,
+            mode: Mode::Retry(backoff),
+        }
+    }
+
+    /// Check the options which depend on each other in ways their definitions can't express.
+    pub(crate) fn validate(&self) -> Result<(), clap::Error> {
+        let strategy = match &self.mode {
+            Mode::Retry(backoff) => backoff,
+            Mode::WaitFor(args) => &args.strategy,
+            Mode::Http(args) => &args.strategy,
+            Mode::Pipeline(args) => &args.strategy,
+            Mode::Batch(args) => &args.strategy,
+            Mode::Bench(_) | Mode::History(_) | Mode::Save(_) | Mode::Run(_) => return Ok(()),
+        };
+        let common = strategy.common();
+        if let (Some(max), Some(min)) = (common.stagger_by_host, common.stagger_min) {
+            if min > max {
+                return Err(Self::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "--stagger-min can't be longer than --stagger-by-host",
+                ));
+            }
+        }
+        // The other modes give the strategy a command of their own, and refuse one given here.
+        if matches!(self.mode, Mode::Retry(_)) && common.command.is_empty() {
+            return Err(Self::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "a command to attempt is required, as in `attempt fixed -- make test`",
+            ));
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Args, Debug, Default)]
+#[derive(Subcommand, Debug)]
+// Parsed once at startup, so there's nothing to gain from boxing the larger variant.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Mode {
+    #[clap(flatten)]
+    Retry(BackoffStrategy),
+
+    /// Run a command repeatedly without retrying, and report how often it succeeds and how long
+    /// it takes.
+    #[clap(trailing_var_arg = true)]
+    Bench(BenchArguments),
+
+    /// Wait for a service to become ready, retrying a built-in check in place of a command.
+    WaitFor(WaitForArguments),
+
+    /// Make an HTTP request until it gets the expected response, retrying server errors and
+    /// failed connections, and print the response body.
+    Http(HttpArguments),
+
+    /// Run a shell pipeline one stage at a time, retrying only the stage which failed.
+    Pipeline(PipelineArguments),
+
+    /// Attempt each of a list of commands with the same schedule, and report how each one went.
+    Batch(BatchArguments),
+
+    /// Show how often each command recorded with --history-db succeeded, and how long it took.
+    History(HistoryArguments),
+
+    /// Save a command and the options to attempt it with under a name, in the defaults file, so
+    /// that `attempt run NAME` can attempt it the same way later.
+    #[clap(trailing_var_arg = true)]
+    Save(SaveArguments),
+
+    /// Attempt a command saved with `attempt save`.
+    Run(RunArguments),
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct BenchArguments {
+    /// The number of times to run the command.
+    #[clap(long, short, default_value("10"))]
+    pub runs: usize,
+    /// The command to run. Everything from the command onwards is passed to it verbatim.
+    #[clap(required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct WaitForArguments {
+    /// The service to wait for, as tcp://HOST:PORT, unix://PATH or http://HOST[:PORT][/PATH].
+    /// HTTP services are ready once they respond with a 2xx or 3xx status.
+    pub target: Probe,
+    #[clap(subcommand)]
+    pub strategy: BackoffStrategy,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct HttpArguments {
+    /// The request method, such as GET or POST.
+    #[clap(value_parser = method_value)]
+    pub method: String,
+    /// The URL to request, as http://HOST[:PORT][/PATH]. HTTPS isn't supported.
+    pub url: HttpUrl,
+    /// The response statuses which count as success, such as "2xx" or "200,304". Other responses
+    /// are retried if they're server errors (5xx) or 429 Too Many Requests, waiting as long as
+    /// their Retry-After header asks, and otherwise end the run.
+    #[clap(long, value_name("STATUSES"), default_value("2xx"))]
+    pub expect: StatusPattern,
+    /// Send this header with the request, given as "Name: value". May be repeated.
+    #[clap(long, short('H'), value_name("HEADER"), value_parser = header_value)]
+    pub header: Vec<String>,
+    /// Send this as the body of the request.
+    #[clap(long, short, value_name("BODY"))]
+    pub data: Option<String>,
+    #[clap(subcommand)]
+    pub strategy: BackoffStrategy,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct PipelineArguments {
+    /// The pipeline to run, with its stages separated by |, such as 'make dist | ./upload.sh'.
+    /// Quote it so that the shell passes it to attempt whole.
+    pub pipeline: String,
+    /// Make up to N attempts at the given stage, numbered from 1, rather than --attempts. Given
+    /// as STAGE=N, such as 3=10. May be repeated.
+    #[clap(long, value_name("STAGE=N"), value_parser = stage_attempts_value)]
+    pub stage_attempts: Vec<(usize, usize)>,
+    /// Wait a fixed time between attempts at the given stage rather than following the schedule.
+    /// Given as STAGE=DURATION, such as 2=30s. May be repeated.
+    #[clap(long, value_name("STAGE=DURATION"), value_parser = stage_wait_value)]
+    pub stage_wait: Vec<(usize, Duration)>,
+    #[clap(subcommand)]
+    pub strategy: BackoffStrategy,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct BatchArguments {
+    /// Read the commands from this file, one shell command per line, or from stdin if it's "-".
+    /// Blank lines and lines starting with # are skipped.
+    #[clap(long, value_name("FILE"))]
+    pub commands: PathBuf,
+    /// The number of commands to attempt at once.
+    #[clap(long, short, default_value("1"))]
+    pub jobs: usize,
+    #[clap(subcommand)]
+    pub strategy: BackoffStrategy,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct SaveArguments {
+    /// The name to save the command under, made of letters, digits, `-` and `_`.
+    #[clap(value_parser = alias_name_value)]
+    pub name: String,
+    /// The arguments `attempt run NAME` stands for: a strategy, its options, and the command
+    /// (e.g. `exponential -a 5 -- ./deploy.sh`).
+    #[clap(required = true, allow_hyphen_values = true)]
+    pub arguments: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RunArguments {
+    /// The name the command was saved under.
+    pub name: String,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct HistoryArguments {
+    /// The history database to read, rather than the default one.
+    #[clap(long, value_name("PATH"))]
+    pub history_db: Option<PathBuf>,
+    /// Only show commands matching this SQL LIKE pattern, where % matches any text
+    /// (e.g. "cargo %").
+    #[clap(long, value_name("PATTERN"))]
+    pub command_like: Option<String>,
+    /// Only include runs which started within this long ago (e.g. "7d").
+    #[clap(long, value_name("DURATION"), value_parser = duration_value)]
+    pub since: Option<Duration>,
+    /// Rather than summarizing the runs, list the most recent failed attempts (20 unless a number
+    /// is given): when each started, how long it ran, how it exited and why it was retried or
+    /// ended the run.
+    #[clap(
+        long,
+        value_name("N"),
+        require_equals(true),
+        min_values(0),
+        default_missing_value("20")
+    )]
+    pub why: Option<usize>,
+}
+
+/// Parse a duration given on the command line, such as "30s" or "5m".
+fn duration_value(text: &str) -> Result<Duration, String> {
+    parse_duration(text).ok_or_else(|| format!("{} is not a duration", text))
+}
+
+/// Parse a duration given on the command line as a number of seconds, for the options of the
+/// schedule, which are kept in seconds.
+fn seconds_value(text: &str) -> Result<f64, String> {
+    duration_value(text).map(|duration| duration.as_secs_f64())
+}
+
+/// Parse the name of a saved command, which is kept as a key in the defaults file.
+fn alias_name_value(text: &str) -> Result<String, String> {
+    if !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(text.to_string())
+    } else {
+        Err(format!(
+            "{} is not a name made of letters, digits, - and _",
+            text
+        ))
+    }
+}
+
+/// Parse an HTTP request method, such as GET.
+fn method_value(text: &str) -> Result<String, String> {
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(text.to_ascii_uppercase())
+    } else {
+        Err(format!("{} is not a request method such as GET", text))
+    }
+}
+
+/// Parse an HTTP header given as "Name: value".
+fn header_value(text: &str) -> Result<String, String> {
+    match text.split_once(':') {
+        Some((name, value))
+            if !name.is_empty()
+                && !name.contains(char::is_whitespace)
+                && !value.contains(['\r', '\n']) =>
+        {
+            Ok(format!("{}: {}", name, value.trim()))
+        }
+        _ => Err(format!(
+            "{} is not a header of the form 'Name: value'",
+            text
+        )),
+    }
+}
+
+/// Parse a signal given on the command line by name, with or without its SIG prefix, or number.
+fn signal_value(text: &str) -> Result<i32, String> {
+    if let Ok(number) = text.parse() {
+        return Ok(number);
+    }
+    #[cfg(unix)]
+    let signal = match text.to_ascii_uppercase().trim_start_matches("SIG") {
+        "HUP" => Some(libc::SIGHUP),
+        "INT" => Some(libc::SIGINT),
+        "QUIT" => Some(libc::SIGQUIT),
+        "TERM" => Some(libc::SIGTERM),
+        "USR1" => Some(libc::SIGUSR1),
+        "USR2" => Some(libc::SIGUSR2),
+        "ALRM" => Some(libc::SIGALRM),
+        "WINCH" => Some(libc::SIGWINCH),
+        "CONT" => Some(libc::SIGCONT),
+        _ => None,
+    };
+    #[cfg(not(unix))]
+    let signal = None;
+
+    signal.ok_or_else(|| format!("{} is not a signal name or number", text))
+}
+
+/// Split a per-stage setting given as STAGE=VALUE.
+fn stage_setting<'a>(text: &'a str, value_name: &str) -> Result<(usize, &'a str), String> {
+    let (stage, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("{} is not of the form STAGE={}", text, value_name))?;
+    match stage.trim().parse() {
+        Ok(stage) if stage > 0 => Ok((stage, value.trim())),
+        _ => Err(format!("{} is not a stage number, counting from 1", stage)),
+    }
+}
+
+/// Parse the attempts for a pipeline stage, given as STAGE=N.
+fn stage_attempts_value(text: &str) -> Result<(usize, usize), String> {
+    let (stage, attempts) = stage_setting(text, "N")?;
+    let attempts = attempts
+        .parse()
+        .map_err(|_| format!("{} is not a number of attempts", attempts))?;
+    Ok((stage, attempts))
+}
+
+/// Parse the wait for a pipeline stage, given as STAGE=DURATION.
+fn stage_wait_value(text: &str) -> Result<(usize, Duration), String> {
+    let (stage, wait) = stage_setting(text, "DURATION")?;
+    let wait = parse_duration(wait).ok_or_else(|| format!("{} is not a duration", wait))?;
+    Ok((stage, wait))
+}
+
+/// Parse the signal which skips the current wait. Signals which can't be caught, and those which
+/// attempt already handles itself, are rejected.
+fn skip_signal_value(text: &str) -> Result<i32, String> {
+    let signal = signal_value(text)?;
+    if signal <= 0 {
+        return Err(format!("{} is not a signal name or number", text));
+    }
+    #[cfg(unix)]
+    if signal_hook::consts::FORBIDDEN.contains(&signal) {
+        return Err(format!("{} can't be caught, so it can't skip waits", text));
+    }
+    #[cfg(unix)]
+    if [
+        libc::SIGINT,
+        libc::SIGTERM,
+        libc::SIGHUP,
+        libc::SIGTSTP,
+        libc::SIGUSR1,
+    ]
+    .contains(&signal)
+    {
+        return Err(format!(
+            "{} already has a meaning to attempt, so it can't skip waits",
+            text
+        ));
+    }
+
+    Ok(signal)
+}
+
+/// Parse a wait for particular exit codes, given as CODE_PATTERN=DURATION.
+fn delay_for_status_value(text: &str) -> Result<(CodePattern, Duration), String> {
+    let (pattern, duration) = text
+        .rsplit_once('=')
+        .ok_or_else(|| format!("{} is not of the form CODE_PATTERN=DURATION", text))?;
+    Ok((pattern.parse()?, duration_value(duration)?))
+}
+
+/// Parse a point in time given on the command line, such as "2022-09-04T18:30:00Z".
+fn timestamp_value(text: &str) -> Result<SystemTime, String> {
+    parse_timestamp(text).ok_or_else(|| format!("{} is not a timestamp", text))
+}
+
+/// Parse a byte string given in hex, such as "1b5b".
+fn hex_value(text: &str) -> Result<Vec<u8>, String> {
+    parse_hex(text).ok_or_else(|| format!("{} is not an even number of hex digits", text))
+}
+
+/// Parse a size given on the command line, such as "512K" or "1G".
+fn size_value(text: &str) -> Result<u64, String> {
+    parse_size(text).ok_or_else(|| format!("{} is not a size", text))
+}
+
+/// Parse a simulation speedup, which must be a positive number.
+fn speedup_value(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(speedup) if speedup > 0.0 && speedup.is_finite() => Ok(speedup),
+        _ => Err(format!("{} is not a positive number", text)),
+    }
+}
+
+/// Arguments to the command which look like they were meant for `attempt` instead, because they
+/// match one of the long options of the strategy subcommand they follow. Everything after the
+/// start of the command is passed to the command verbatim, so options like these are easily
+/// misplaced.
+pub(crate) fn misplaced_options<'a>(strategy: &str, command: &'a [String]) -> Vec<&'a str> {
+    let parser = ArgumentParser::command();
+    let options = parser
+        .find_subcommand(strategy)
+        .into_iter()
+        .flat_map(|subcommand| subcommand.get_arguments())
+        .filter_map(|argument| argument.get_long())
+        .filter(|long| !matches!(*long, "help" | "version"))
+        .collect::<Vec<_>>();
+
+    command
+        .iter()
+        .skip(1)
+        .map(String::as_str)
+        .filter(
+            |argument| match argument.split('=').next().unwrap().strip_prefix("--") {
+                Some(long) => options.contains(&long),
+                None => false,
+            },
+        )
+        .collect()
+}
+
+#[derive(Args, Debug, Clone, Default)]
 pub(crate) struct CommonArguments {
     /// The maximum number of attempts.
     #[clap(long, short, default_value("3"))]
     pub attempts: usize,
+    /// Run the command exactly N times whatever the outcome, waiting between runs as the
+    /// schedule says, then summarize how the runs went.
+    #[clap(
+        long,
+        value_name("N"),
+        conflicts_with_all(&["attempts", "until-failure", "exec-last"])
+    )]
+    pub count: Option<usize>,
     #[clap(flatten)]
     pub wait_params: WaitParameters,
+    /// Delay the first attempt by exactly this long, then follow the schedule as usual.
+    #[clap(long, value_name("DURATION"), value_parser = duration_value, conflicts_with("at"))]
+    pub initial_delay: Option<Duration>,
+    /// Delay the first attempt until this time, given as an RFC 3339 timestamp such as
+    /// "2022-09-04T18:30:00Z" (local time without a timezone), or in seconds of Unix time. A
+    /// time in the past doesn't delay the first attempt.
+    #[clap(long, value_name("TIMESTAMP"), value_parser = timestamp_value)]
+    pub at: Option<SystemTime>,
+    /// Delay the first attempt by an offset within [0, DURATION] derived from the hostname, so
+    /// that a fleet of hosts spreads out while each host keeps the same offset every time.
+    #[clap(long, value_name("DURATION"), value_parser = duration_value)]
+    pub stagger_by_host: Option<Duration>,
+    /// Stagger the first attempt by at least this long, so the offset falls within
+    /// [DURATION, --stagger-by-host] instead.
+    #[clap(long, value_name("DURATION"), value_parser = duration_value, requires("stagger-by-host"))]
+    pub stagger_min: Option<Duration>,
+    /// Derive the stagger offset from this key instead of the hostname.
+    #[clap(long, value_name("KEY"), requires("stagger-by-host"))]
+    pub stagger_key: Option<String>,
+    /// Speed up waits by SPEEDUP (3600 by default) to try out a schedule quickly, given as
+    /// --simulate=SPEEDUP. The command is still run for real, and the simulated time is logged
+    /// as each attempt starts.
+    #[clap(
+        long,
+        value_name("SPEEDUP"),
+        require_equals(true),
+        min_values(0),
+        default_missing_value("3600"),
+        value_parser = speedup_value
+    )]
+    pub simulate: Option<f64>,
+    /// Seed the random number generator used for jitter, so that the same seed always gives the
+    /// same schedule.
+    #[clap(long, value_name("N"))]
+    pub random_seed: Option<u64>,
+    /// Invert the objective: keep running the command while it succeeds, on the same schedule,
+    /// and stop successfully once it fails. Useful for reproducing flaky failures.
+    #[clap(long, conflicts_with("success-if-file-matches"))]
+    pub until_failure: bool,
+    /// Only retry if the command exits with code 75 (EX_TEMPFAIL), and stop on any other failure.
+    #[clap(long)]
+    pub tempfail: bool,
+    /// Only retry if the command's stdout and stderr, interleaved in the order they were received,
+    /// match this regex. May be repeated to retry if any of them match.
+    #[clap(long, value_name("REGEX"))]
+    pub retry_if_output_matches: Vec<Regex>,
+    /// --retry-if-output-matches compiled into a single set, built when it's first needed.
+    #[clap(skip)]
+    retry_if_output_set: OnceLock<RegexSet>,
+    /// Only retry if the command's raw output contains these bytes, given in hex (e.g. "1b5b32"),
+    /// which works even when the output isn't text. May be repeated to retry if any of them are
+    /// found.
+    #[clap(long, value_name("HEX"), value_parser = hex_value)]
+    pub retry_if_bytes: Vec<Vec<u8>>,
+    /// Stop retrying if the command's raw output contains these bytes, given in hex, whatever
+    /// the retry conditions say. May be repeated.
+    #[clap(long, value_name("HEX"), value_parser = hex_value)]
+    pub stop_if_bytes: Vec<Vec<u8>>,
+    /// End the run at once if the command's output matches this regex, e.g. for unrecoverable
+    /// errors like "permission denied", exiting with code 4 whatever the other conditions say.
+    /// May be repeated.
+    #[clap(long, value_name("REGEX"))]
+    pub abort_if_matches: Vec<Regex>,
+    /// End the run at once if the command exits with a code matching this pattern, exiting with
+    /// code 4 whatever the other conditions say. Takes the same patterns as --delay-for-status.
+    #[clap(long, value_name("CODE_PATTERN"))]
+    pub abort_if_status: Option<CodePattern>,
+    /// Give up if the command fails N times within DURATION, given as N:DURATION, exiting with
+    /// code 5. Keeps a command which is broken for good from being retried in a tight loop, such
+    /// as with --cap-becomes-fixed.
+    #[clap(long, value_name("N:DURATION"), conflicts_with("count"))]
+    pub start_limit: Option<StartLimit>,
+    /// Retry if the command wrote nothing to STREAM, given as --retry-if-output-empty=STREAM,
+    /// even if it exited successfully. STREAM is stdout, stderr or both (the default).
+    #[clap(
+        long,
+        value_name("STREAM"),
+        value_enum,
+        require_equals(true),
+        min_values(0),
+        default_missing_value("both")
+    )]
+    pub retry_if_output_empty: Option<OutputStream>,
+    /// Stop retrying if the command wrote nothing to STREAM, given as
+    /// --stop-if-output-empty=STREAM, whatever the retry conditions say. STREAM is stdout, stderr
+    /// or both (the default).
+    #[clap(
+        long,
+        value_name("STREAM"),
+        value_enum,
+        require_equals(true),
+        min_values(0),
+        default_missing_value("both")
+    )]
+    pub stop_if_output_empty: Option<OutputStream>,
+    /// Retry if the command wrote fewer than N lines to stdout, even if it exited successfully.
+    #[clap(long, value_name("N"))]
+    pub retry_if_lines_lt: Option<usize>,
+    /// Stop retrying if the command wrote more than N lines to stdout, whatever the retry
+    /// conditions say.
+    #[clap(long, value_name("N"))]
+    pub stop_if_lines_gt: Option<usize>,
+    /// When the command's output is examined, hold at most SIZE of it in memory, moving it to a
+    /// temporary file once it grows larger.
+    #[clap(long, value_name("SIZE"), default_value("64M"), value_parser = size_value)]
+    pub spill_output_over: u64,
+    /// Take the time to wait before the next attempt from the command's output, using the first
+    /// capture group of this regex (e.g. "retry after (\d+) seconds"). The schedule is used if
+    /// there's no match, and --wait-max still applies.
+    #[clap(long, value_name("REGEX"))]
+    pub wait_from_output: Option<Regex>,
+    /// Wait DURATION instead of following the schedule when the command exits with a code
+    /// matching CODE_PATTERN, a comma-separated list of codes and ranges such as "1,3..5" or
+    /// "75..=78". `*` matches any code, and a leading `!` negates the pattern. May be repeated,
+    /// and the first match is used.
+    #[clap(long, value_name("CODE_PATTERN=DURATION"), value_parser = delay_for_status_value)]
+    pub delay_for_status: Vec<(CodePattern, Duration)>,
+    /// Count exit codes matching this pattern as success rather than only 0, e.g. "0,1" for
+    /// diff-like commands which exit with 1 when they find a difference. Takes the same patterns
+    /// as --delay-for-status.
+    #[clap(long, value_name("CODE_PATTERN"))]
+    pub success_codes: Option<CodePattern>,
+    /// Share a budget of attempts with every other process using this file, waiting before an
+    /// attempt until the budget allows it. Requires --budget.
+    #[clap(long, value_name("PATH"), requires("budget"))]
+    pub budget_file: Option<PathBuf>,
+    /// How many attempts the budget in --budget-file allows, given as "N per DURATION" (e.g.
+    /// "100 per 1m") or "N/DURATION".
+    #[clap(long, value_name("RATE"), requires("budget-file"))]
+    pub budget: Option<Rate>,
+    /// Stop without making any more attempts once this file no longer exists, e.g. when an
+    /// operator removes a deploy token or maintenance flag. Checked before each attempt. May be
+    /// repeated to require several files.
+    #[clap(long, value_name("PATH"))]
+    pub require_file: Vec<PathBuf>,
+    /// Only count an attempt as successful if the file at PATH has this SHA-256 checksum, and
+    /// retry otherwise. May be repeated to check several files.
+    #[clap(long, value_name("PATH=SHA256"))]
+    pub success_if_file_matches: Vec<FileChecksum>,
+    /// Only count an attempt as successful if what it wrote to stdout is the same as the contents
+    /// of this file, and retry otherwise.
+    #[clap(long, value_name("PATH"))]
+    pub expect_output_file: Option<PathBuf>,
+    /// Compare stdout with --expect-output-file line by line, ignoring differences in line
+    /// endings and trailing whitespace.
+    #[clap(long, requires("expect-output-file"))]
+    pub expect_output_linewise: bool,
+    /// Substitute {attempt}, {elapsed} (in seconds), {last_status} (the previous attempt's exit
+    /// code) and {timestamp} (in Unix time) in the command's arguments before each attempt. Write
+    /// {{ and }} for literal braces.
+    #[clap(long)]
+    pub template: bool,
+    /// Feed the contents of this file to the command's stdin on each attempt, substituting the
+    /// same placeholders as --template.
+    #[clap(long, value_name("FILE"), conflicts_with("exec-last"))]
+    pub stdin_template: Option<PathBuf>,
+    /// Retry immediately, rather than waiting out the schedule, when PATH or anything under it
+    /// changes. May be repeated to watch several paths.
+    #[clap(long, value_name("PATH"))]
+    pub watch: Vec<PathBuf>,
+    /// Defer each attempt until at least SIZE is free on the filesystem holding PATH (the current
+    /// directory by default), without using up an attempt. May be repeated.
+    #[clap(long, value_name("SIZE[:PATH]"), value_parser = parse_free_disk)]
+    pub require_free_disk: Vec<Requirement>,
+    /// Defer each attempt until at least SIZE of memory is available, without using up an
+    /// attempt.
+    #[clap(long, value_name("SIZE"), value_parser = parse_free_memory)]
+    pub require_free_memory: Option<Requirement>,
+    /// Run this shell command before each attempt, and defer the attempt until it succeeds
+    /// without using up an attempt.
+    #[clap(long, value_name("COMMAND"))]
+    pub precheck: Option<String>,
+    /// While each attempt is running, run this shell command every --health-interval, and stop
+    /// the attempt if it fails, so that a command which is stuck but still alive is retried. The
+    /// attempt is sent SIGTERM, then SIGKILL if it hasn't exited 5 seconds later. Only supported
+    /// on Unix.
+    #[clap(long, value_name("COMMAND"), conflicts_with("exec-last"))]
+    pub health_check: Option<String>,
+    /// How often to run --health-check, 30 seconds by default.
+    #[clap(
+        long,
+        value_name("DURATION"),
+        value_parser = duration_value,
+        requires("health-check")
+    )]
+    pub health_interval: Option<Duration>,
+    /// Defer each attempt while there's no default network route, without using up an attempt.
+    /// Only supported on Linux, and ignored elsewhere.
+    #[clap(long)]
+    pub wait_for_network: bool,
+    /// Defer each attempt while the 1-minute load average is above N, without using up an
+    /// attempt.
+    #[clap(long, value_name("N"))]
+    pub max_load: Option<f64>,
+    /// Only start attempts within this daily window, given as HH:MM-HH:MM (e.g. "22:00-06:00"),
+    /// deferring them until it opens without using up an attempt. Times are local unless
+    /// followed by a timezone, either "UTC" or an offset such as "+02:00".
+    #[clap(long, value_name("WINDOW"))]
+    pub between: Option<TimeWindow>,
+    /// Skip the rest of the current wait and retry immediately upon receiving this signal. Given
+    /// as a name such as USR2, or a number.
+    #[clap(long, value_name("SIGNAL"), value_parser = skip_signal_value)]
+    pub skip_signal: Option<i32>,
+    /// Prefix each line of the command's output with a label, given as --tag=LABEL. The label
+    /// defaults to the command's name and the attempt number, and {attempt} is filled in within
+    /// custom labels.
+    #[clap(long, value_name("LABEL"), require_equals(true))]
+    pub tag: Option<Option<String>>,
+    /// Prefix each line of the command's output with the time it arrived, given as
+    /// --timestamps=CLOCK. Defaults to the time since the attempt started.
+    #[clap(
+        long,
+        value_name("CLOCK"),
+        value_enum,
+        require_equals(true),
+        min_values(0),
+        default_missing_value("relative")
+    )]
+    pub timestamps: Option<Timestamps>,
+    /// Prefix each line of the command's output with the stream it was written to.
+    #[clap(long, conflicts_with("pty"))]
+    pub label_streams: bool,
+    /// Run the command with its stdout and stderr attached to a pseudo-terminal, as though it was
+    /// run interactively.
+    #[clap(long)]
+    pub pty: bool,
+    /// Run the command with no_new_privs set, so that it can't gain privileges through setuid
+    /// binaries or file capabilities. Only supported on Linux.
+    #[clap(long)]
+    pub no_new_privileges: bool,
+    /// Run the command in a mount namespace of its own, with the root filesystem mounted
+    /// read-only. Needs CAP_SYS_ADMIN, and is only supported on Linux.
+    #[clap(long)]
+    pub read_only_root: bool,
+    /// Give each attempt an empty /tmp of its own, in a mount namespace of its own, so that
+    /// attempts can't leave files behind for later ones. Needs CAP_SYS_ADMIN, and is only
+    /// supported on Linux.
+    #[clap(long)]
+    pub private_tmp: bool,
+    /// Run each attempt in a network namespace of its own, which only has a loopback interface.
+    /// Needs CAP_SYS_ADMIN, and is only supported on Linux.
+    #[clap(long)]
+    pub private_network: bool,
+    /// Run the command with this directory as its root. Needs CAP_SYS_CHROOT, and is only
+    /// supported on Linux.
+    #[clap(long, value_name("PATH"))]
+    pub chroot: Option<PathBuf>,
+    /// Start the command in this directory within the --chroot directory, rather than at its
+    /// root.
+    #[clap(long, value_name("PATH"), requires("chroot"))]
+    pub chdir: Option<PathBuf>,
+    /// Install this seccomp filter before running the command: a compiled BPF program, such as
+    /// one written by libseccomp's seccomp_export_bpf. Implies --no-new-privileges. Only
+    /// supported on Linux.
+    #[clap(long, value_name("PATH"))]
+    pub seccomp_filter: Option<PathBuf>,
+    /// Let the command dump core when it crashes, as far as the hard limit allows, and move each
+    /// core dump into this directory as core.attempt-N.PID. Cores which the system hands to a
+    /// program such as systemd-coredump can't be collected. Only supported on Unix.
+    #[clap(long, value_name("DIR"), conflicts_with("no-cores"))]
+    pub collect_cores: Option<PathBuf>,
+    /// Keep the command from dumping core at all. Only supported on Unix.
+    #[clap(long)]
+    pub no_cores: bool,
+    /// Don't show a countdown to the next attempt, even if stderr is a terminal.
+    #[clap(long)]
+    pub no_progress: bool,
+    /// Leave the command running if attempt is terminated by SIGTERM or SIGHUP, rather than
+    /// passing the signal on to it, which is the default.
+    #[clap(long, alias("detach"))]
+    pub no_kill_on_exit: bool,
+    /// On SIGHUP, send this signal to the running command so that it reloads its configuration,
+    /// rather than terminating it and exiting. Given as a name such as HUP or USR1, or a number.
+    /// Ignored by attempt batch.
+    #[clap(long, value_name("SIGNAL"), value_parser = signal_value)]
+    pub reload_signal: Option<i32>,
+    /// Replace attempt with the command on the last attempt, via exec, so that whatever started
+    /// attempt owns the command and its exit status directly. Output options, success checks
+    /// and the reason file don't apply to that attempt. Only supported on Unix.
+    #[clap(long, conflicts_with("pty"))]
+    pub exec_last: bool,
+    /// Write the PID of the running command to this file, removing it on exit.
+    #[clap(long)]
+    pub pid_file: Option<PathBuf>,
+    /// Listen on a Unix socket at this path for commands to the running attempt, one per line:
+    /// "status" to report progress, "skip" to retry immediately, "cancel" to stop retrying and
+    /// "extend N" to allow N more attempts. Each is answered with a line of JSON. Ignored by
+    /// attempt batch, and only supported on Unix.
+    #[clap(long, value_name("PATH"))]
+    pub control_socket: Option<PathBuf>,
+    /// Give each attempt a fresh, empty temporary directory, exported to the command as
+    /// ATTEMPT_TMPDIR and removed once the attempt is over.
+    #[clap(long, conflicts_with("exec-last"))]
+    pub temp_dir: bool,
+    /// Keep the temporary directory of each attempt which fails, for a post-mortem.
+    #[clap(long, requires("temp-dir"))]
+    pub keep_failed_temp_dir: bool,
+    /// Touch this file as each attempt starts and every second while waiting, so that external
+    /// watchdogs can tell that attempt is still alive.
+    #[clap(long, value_name("PATH"))]
+    pub heartbeat_file: Option<PathBuf>,
+    /// Write a JSON object describing how the run ended to this file on exit: the outcome, the
+    /// reason, the number of attempts used, the last exit code or signal and the resources the
+    /// last attempt used.
+    #[clap(long, value_name("PATH"))]
+    pub reason_file: Option<PathBuf>,
+    /// Emit GitHub Actions workflow commands: a log group for each attempt, a warning for each
+    /// retry and an error if the command finally fails.
+    #[clap(long)]
+    pub gha: bool,
+    /// Stay silent if the command finally succeeds. Otherwise, show attempt's log followed by
+    /// the output of the last attempt, so that cron only sends mail when something went wrong.
+    #[clap(long, conflicts_with("exec-last"))]
+    pub cronic: bool,
+    /// Record the run and each of its attempts in a SQLite database, given as
+    /// --history-db=PATH, for `attempt history` to summarize. Defaults to
+    /// $XDG_DATA_HOME/attempt/history.db.
+    #[clap(long, value_name("PATH"), require_equals(true), min_values(0))]
+    pub history_db: Option<Option<PathBuf>>,
+    /// Write a CSV file on exit with a row for each attempt: when it started, how long it ran,
+    /// its exit code or signal, and what was done next.
+    #[clap(long, value_name("PATH"))]
+    pub report_csv: Option<PathBuf>,
+    /// Write a JUnit XML report to this file on exit, with a test case for each attempt giving
+    /// its duration, exit status and output, so that CI systems can show retries natively.
+    #[clap(long, value_name("PATH"))]
+    pub report_junit: Option<PathBuf>,
+    /// Log more detail. May be repeated.
+    #[clap(long, short, action(ArgAction::Count))]
+    pub verbose: u8,
+    /// Log less detail. May be repeated.
+    #[clap(long, short, action(ArgAction::Count))]
+    pub quiet: u8,
+    /// When to color log messages.
+    #[clap(long, value_enum, default_value_t)]
+    pub color: ColorChoice,
+    /// Log which condition decided whether to retry after each attempt, without needing
+    /// --verbose.
+    #[clap(long)]
+    pub explain: bool,
+    /// Prefix log messages with this, filling in {attempt} and {attempts}. Pass an empty string
+    /// to leave log messages unprefixed.
+    #[clap(
+        long,
+        value_name("TEMPLATE"),
+        default_value("[attempt {attempt}/{attempts}]")
+    )]
+    pub log_prefix: String,
+    /// Write attempt's own messages to this file descriptor rather than stderr, leaving stderr to
+    /// the command. The descriptor must already be open, as with 3>attempt.log in the shell.
+    /// Only supported on Unix.
+    #[clap(long, value_name("FD"))]
+    pub log_fd: Option<i32>,
+    /// The command to run. Everything from the command onwards is passed to it verbatim.
     pub command: Vec<String>,
+    /// Whether the command followed `--`, so that none of its arguments can have been meant for
+    /// `attempt`. Worked out from the arguments once argument files, defaults and saved commands
+    /// have been expanded, as the parser doesn't say.
+    #[clap(skip)]
+    pub separated: bool,
 }
 
 impl CommonArguments {
@@ -34,8 +810,400 @@ impl CommonArguments {
             attempts,
             wait_params,
             command,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the command's output needs to be captured to decide whether to retry.
+    pub(crate) fn needs_output(&self) -> bool {
+        !self.retry_if_output_matches.is_empty()
+            || !self.retry_if_bytes.is_empty()
+            || !self.stop_if_bytes.is_empty()
+            || !self.abort_if_matches.is_empty()
+            || self.retry_if_output_empty.is_some()
+            || self.stop_if_output_empty.is_some()
+            || self.retry_if_lines_lt.is_some()
+            || self.stop_if_lines_gt.is_some()
+            || self.wait_from_output.is_some()
+    }
+
+    /// The fixed delay before the first attempt given by --initial-delay or --at, if any.
+    pub(crate) fn initial_delay(&self) -> Option<Duration> {
+        self.initial_delay.or_else(|| {
+            self.at?
+                .duration_since(SystemTime::now())
+                .ok()
+                .filter(|delay| !delay.is_zero())
+        })
+    }
+
+    /// How long to stagger the first attempt by, if at all.
+    pub(crate) fn stagger(&self) -> Option<Duration> {
+        let max = self.stagger_by_host?;
+        let min = self.stagger_min.unwrap_or_default();
+        let key = self
+            .stagger_key
+            .clone()
+            .or_else(hostname)
+            .unwrap_or_default();
+        Some(min + stagger_offset(&key, max.saturating_sub(min)))
+    }
+
+    /// What the host must provide before each attempt.
+    pub(crate) fn requirements(&self) -> Vec<Requirement> {
+        let precheck = self
+            .precheck
+            .clone()
+            .map(|command| Requirement::Precheck { command });
+        self.require_free_disk
+            .iter()
+            .chain(&self.require_free_memory)
+            .cloned()
+            .chain(precheck)
+            .chain(self.wait_for_network.then_some(Requirement::Network))
+            .chain(self.max_load.map(|load| Requirement::MaxLoad { load }))
+            .chain(self.between.map(Requirement::Window))
+            .collect()
+    }
+
+    /// The restrictions to run the command under.
+    pub(crate) fn sandbox(&self) -> Result<Sandbox, io::Error> {
+        Ok(Sandbox {
+            no_new_privileges: self.no_new_privileges,
+            read_only_root: self.read_only_root,
+            private_tmp: self.private_tmp,
+            private_network: self.private_network,
+            chroot: self.chroot.as_deref().map(c_path).transpose()?,
+            chdir: self.chdir.as_deref().map(c_path).transpose()?,
+            seccomp_filter: self
+                .seccomp_filter
+                .as_deref()
+                .map(load_seccomp_filter)
+                .transpose()?,
+        })
+    }
+
+    /// The health check to run while each attempt is running, if any.
+    pub(crate) fn health_check(&self) -> Option<HealthCheck> {
+        Some(HealthCheck::new(
+            self.health_check.clone()?,
+            self.health_interval,
+        ))
+    }
+
+    /// What to do about the command dumping core, if anything.
+    pub(crate) fn core_dumps(&self) -> Option<CoreDumps> {
+        if self.collect_cores.is_some() {
+            Some(CoreDumps::Collect)
+        } else {
+            self.no_cores.then_some(CoreDumps::Suppress)
+        }
+    }
+
+    /// The label to tag the command's output with during the given attempt, if any.
+    pub(crate) fn tag(&self, attempt: usize) -> Option<String> {
+        match self.tag.as_ref()? {
+            Some(label) => Some(label.replace("{attempt}", &attempt.to_string())),
+            None => {
+                let name = Path::new(&self.command[0])
+                    .file_name()
+                    .map_or(self.command[0].as_str(), |name| name.to_str().unwrap_or(""));
+                Some(format!("[{} #{}]", name, attempt))
+            }
+        }
+    }
+
+    /// The wait requested by the command's output, if --wait-from-output is in use and matched.
+    pub(crate) fn wait_from_output(&self, output: &[u8]) -> Option<Duration> {
+        let captures = self.wait_from_output.as_ref()?.captures(output)?;
+        let wait = parse_duration(std::str::from_utf8(captures.get(1)?.as_bytes()).ok()?)?;
+        match self.wait_params.wait_max.and_then(duration_from_f64) {
+            Some(wait_max) => Some(wait.min(wait_max)),
+            None => Some(wait),
+        }
+    }
+
+    /// The first file required by --require-file which no longer exists, if any.
+    pub(crate) fn missing_required_file(&self) -> Option<&Path> {
+        self.require_file
+            .iter()
+            .map(PathBuf::as_path)
+            .find(|path| !path.exists())
+    }
+
+    /// The number of attempts in the schedule, or of runs with --count.
+    pub(crate) fn attempt_count(&self) -> usize {
+        self.count.unwrap_or(self.attempts)
+    }
+
+    /// The shared budget of attempts given by --budget-file and --budget, if any.
+    pub(crate) fn budget(&self) -> Option<Budget> {
+        Some(Budget::new(self.budget_file.clone()?, self.budget?))
+    }
+
+    /// Whether the command exited successfully, taking --success-codes into account. A command
+    /// killed by a signal never succeeded.
+    pub(crate) fn succeeded(&self, exit_status: ExitStatus) -> bool {
+        match (&self.success_codes, exit_status.code()) {
+            (Some(pattern), Some(code)) => pattern.matches(code),
+            (Some(_), None) => false,
+            (None, _) => exit_status.success(),
+        }
+    }
+
+    /// The --abort-if-status or --abort-if-matches condition which holds for an attempt with the
+    /// given exit code and output, if any. These end the run whatever the other conditions say.
+    pub(crate) fn fatal_condition(&self, code: Option<i32>, output: &[u8]) -> Option<String> {
+        if let (Some(pattern), Some(code)) = (&self.abort_if_status, code) {
+            if pattern.matches(code) {
+                return Some(format!(
+                    "--abort-if-status {} matched exit code {}",
+                    pattern, code
+                ));
+            }
+        }
+        self.abort_if_matches.iter().find_map(|regex| {
+            regex.find(output).map(|m| {
+                format!(
+                    "--abort-if-matches '{}' matched {:?} at bytes {}..{}",
+                    regex,
+                    excerpt(m.as_bytes()),
+                    m.start(),
+                    m.end()
+                )
+            })
+        })
+    }
+
+    /// The wait given by --delay-for-status for the exit code, along with the pattern it matched.
+    pub(crate) fn delay_for_status(&self, code: Option<i32>) -> Option<(&CodePattern, Duration)> {
+        let code = code?;
+        self.delay_for_status
+            .iter()
+            .find(|(pattern, _)| pattern.matches(code))
+            .map(|(pattern, delay)| (pattern, *delay))
+    }
+
+    /// The condition on how much the command wrote which holds, if any, out of
+    /// --retry-if-output-empty and --retry-if-lines-lt. These are checked whatever the exit code.
+    pub(crate) fn output_too_short(&self, written: Written) -> Option<String> {
+        if let Some(stream) = self
+            .retry_if_output_empty
+            .filter(|&stream| written.is_empty(stream))
+        {
+            Some(format!("--retry-if-output-empty={}", stream))
+        } else {
+            self.retry_if_lines_lt
+                .filter(|&n| written.stdout_lines < n)
+                .map(|n| {
+                    format!(
+                        "--retry-if-lines-lt {} (stdout lines: {})",
+                        n, written.stdout_lines
+                    )
+                })
         }
     }
+
+    /// Whether a failed command with the given exit code and output should be retried, along with
+    /// which condition decided it. Failures are always retried unless a retry condition is given,
+    /// in which case at least one of them must hold, or a stop condition holds.
+    pub(crate) fn should_retry(
+        &self,
+        code: Option<i32>,
+        output: &[u8],
+        written: Written,
+    ) -> RetryDecision {
+        if let Some(stream) = self
+            .stop_if_output_empty
+            .filter(|&stream| written.is_empty(stream))
+        {
+            return RetryDecision::new(false, format!("--stop-if-output-empty={} held", stream));
+        }
+        if let Some(n) = self.stop_if_lines_gt.filter(|&n| written.stdout_lines > n) {
+            return RetryDecision::new(
+                false,
+                format!(
+                    "--stop-if-lines-gt {} held (stdout lines: {})",
+                    n, written.stdout_lines
+                ),
+            );
+        }
+        for needle in &self.stop_if_bytes {
+            if let Some(start) = find_bytes(output, needle) {
+                return RetryDecision::new(
+                    false,
+                    format!(
+                        "--stop-if-bytes {} matched at bytes {}..{}",
+                        format_hex(needle),
+                        start,
+                        start + needle.len()
+                    ),
+                );
+            }
+        }
+
+        if !self.tempfail
+            && self.retry_if_output_matches.is_empty()
+            && self.retry_if_bytes.is_empty()
+            && self.retry_if_output_empty.is_none()
+            && self.retry_if_lines_lt.is_none()
+        {
+            return RetryDecision::new(
+                true,
+                String::from("no retry conditions were given, so every failure is retried"),
+            );
+        }
+
+        if self.tempfail && code == Some(EX_TEMPFAIL) {
+            return RetryDecision::new(
+                true,
+                format!("--tempfail matched exit code {}", EX_TEMPFAIL),
+            );
+        }
+        if let Some(condition) = self.output_too_short(written) {
+            return RetryDecision::new(true, format!("{} held", condition));
+        }
+        // Scan the output once for all of the patterns, and only search again with the first
+        // pattern which matched to report where.
+        let matched = self
+            .retry_if_output_set
+            .get_or_init(|| {
+                RegexSet::new(self.retry_if_output_matches.iter().map(Regex::as_str))
+                    .expect("The patterns were already compiled individually")
+            })
+            .matches(output)
+            .into_iter()
+            .next();
+        if let Some(regex) = matched.map(|i| &self.retry_if_output_matches[i]) {
+            if let Some(captures) = regex.captures(output) {
+                let m = captures.get(0).unwrap();
+                return RetryDecision {
+                    groups: captures
+                        .iter()
+                        .skip(1)
+                        .map(|group| {
+                            group.map_or(String::new(), |group| {
+                                String::from_utf8_lossy(group.as_bytes()).into_owned()
+                            })
+                        })
+                        .collect(),
+                    ..RetryDecision::new(
+                        true,
+                        format!(
+                            "--retry-if-output-matches '{}' matched {:?} at bytes {}..{}",
+                            regex,
+                            excerpt(m.as_bytes()),
+                            m.start(),
+                            m.end()
+                        ),
+                    )
+                };
+            }
+        }
+
+        for needle in &self.retry_if_bytes {
+            if let Some(start) = find_bytes(output, needle) {
+                return RetryDecision::new(
+                    true,
+                    format!(
+                        "--retry-if-bytes {} matched at bytes {}..{}",
+                        format_hex(needle),
+                        start,
+                        start + needle.len()
+                    ),
+                );
+            }
+        }
+
+        let mut unmatched = Vec::new();
+        if self.tempfail {
+            unmatched.push(String::from("--tempfail"));
+        }
+        for regex in &self.retry_if_output_matches {
+            unmatched.push(format!("--retry-if-output-matches '{}'", regex));
+        }
+        for needle in &self.retry_if_bytes {
+            unmatched.push(format!("--retry-if-bytes {}", format_hex(needle)));
+        }
+        if let Some(stream) = self.retry_if_output_empty {
+            unmatched.push(format!("--retry-if-output-empty={}", stream));
+        }
+        if let Some(n) = self.retry_if_lines_lt {
+            unmatched.push(format!("--retry-if-lines-lt {}", n));
+        }
+        RetryDecision::new(
+            false,
+            format!("no retry condition matched ({})", unmatched.join(", ")),
+        )
+    }
+}
+
+/// Whether to retry a failed attempt, and why.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct RetryDecision {
+    pub retry: bool,
+    /// Which condition decided it.
+    pub reason: String,
+    /// The capture groups of the --retry-if-output-matches pattern which decided it, if any.
+    /// Groups which didn't participate in the match are empty.
+    pub groups: Vec<String>,
+}
+
+impl RetryDecision {
+    pub(crate) fn new(retry: bool, reason: String) -> Self {
+        Self {
+            retry,
+            reason,
+            groups: Vec::new(),
+        }
+    }
+}
+
+/// A short, printable excerpt of matched output.
+fn excerpt(bytes: &[u8]) -> String {
+    const MAX_CHARS: usize = 60;
+    let text = String::from_utf8_lossy(bytes);
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.into_owned(),
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ColorChoice {
+    /// Color log messages if stderr is a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_possible_value().unwrap().get_name())
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Timestamps {
+    /// The time since the attempt started.
+    Relative,
+    /// The local time of day.
+    Wall,
+}
+
+/// Which of the command's output streams an option applies to.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputStream {
+    Both,
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_possible_value().unwrap().get_name())
+    }
 }
 
 #[derive(Args, Debug, Clone, Copy, Default)]
@@ -43,12 +1211,17 @@ pub(crate) struct WaitParameters {
     /// Add random jitter to the wait time, in the interval [-n, n].
     #[clap(long, short)]
     pub jitter: Option<f64>,
-    /// The minimum amount of time to wait between attempts.
-    #[clap(long)]
+    /// The minimum amount of time to wait between attempts, such as 30s or 5m.
+    #[clap(long, value_name("DURATION"), value_parser = seconds_value)]
     pub wait_min: Option<f64>,
-    /// The maximum amount of time to wait between attempts.
-    #[clap(long)]
+    /// The maximum amount of time to wait between attempts, such as 30s or 5m.
+    #[clap(long, value_name("DURATION"), value_parser = seconds_value)]
     pub wait_max: Option<f64>,
+    /// Scale each wait by how long the failed attempt ran, waiting up to twice as long after a
+    /// quick failure and as little as half as long after one which ran for at least the wait.
+    /// --wait-min and --wait-max still apply.
+    #[clap(long)]
+    pub adaptive: bool,
 }
 
 impl WaitParameters {
@@ -58,16 +1231,37 @@ impl WaitParameters {
             jitter,
             wait_min,
             wait_max,
+            adaptive: false,
         }
     }
 }
 
-#[derive(Subcommand, Debug)]
+impl fmt::Display for WaitParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(jitter) = self.jitter {
+            write!(f, ", jitter of +/- {}s", jitter)?;
+        }
+        if let Some(wait_min) = self.wait_min {
+            write!(f, ", waiting at least {}s", wait_min)?;
+        }
+        if let Some(wait_max) = self.wait_max {
+            write!(f, ", waiting at most {}s", wait_max)?;
+        }
+        if self.adaptive {
+            write!(f, ", adapted to how long each attempt ran")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
 pub(crate) enum BackoffStrategy {
     /// Wait a fixed amount of time between attempts.
+    #[clap(trailing_var_arg = true)]
     Fixed {
-        /// The amount of time to wait between attempts.
-        #[clap(long, short, default_value("5.0"))]
+        /// The amount of time to wait between attempts, such as 30s or 5m.
+        #[clap(long, short, value_name("DURATION"), value_parser = seconds_value, default_value("5.0"))]
         wait: f64,
 
         #[clap(flatten)]
@@ -75,26 +1269,97 @@ pub(crate) enum BackoffStrategy {
     },
 
     /// Wait exponentially longer between attempts.
+    #[clap(trailing_var_arg = true)]
     Exponential {
         #[clap(long, short, default_value("2.0"))]
         base: f64,
         #[clap(long, short, default_value("1.0"))]
         multiplier: f64,
+        /// Once the wait reaches --wait-max, keep retrying at that interval until the command
+        /// succeeds, rather than stopping after a number of attempts.
+        #[clap(long, requires("wait-max"), conflicts_with_all(&["attempts", "count"]))]
+        cap_becomes_fixed: bool,
 
         #[clap(flatten)]
         common: CommonArguments,
     },
 }
 impl BackoffStrategy {
+    pub fn common(&self) -> &CommonArguments {
+        match self {
+            BackoffStrategy::Fixed { common, .. } => common,
+            BackoffStrategy::Exponential { common, .. } => common,
+        }
+    }
+
+    /// The name of the strategy's subcommand.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackoffStrategy::Fixed { .. } => "fixed",
+            BackoffStrategy::Exponential { .. } => "exponential",
+        }
+    }
+
+    pub fn common_mut(&mut self) -> &mut CommonArguments {
+        match self {
+            BackoffStrategy::Fixed { common, .. } => common,
+            BackoffStrategy::Exponential { common, .. } => common,
+        }
+    }
+
     pub fn command(&self) -> Command {
-        let command = match self {
-            BackoffStrategy::Fixed { common, .. } => &common.command,
-            BackoffStrategy::Exponential { common, .. } => &common.command,
+        build_command(&self.common().command)
+    }
+
+    /// The waits of the schedule without its limit on attempts, for runs whose limit can be
+    /// raised while they're running. The caller then decides when to stop.
+    pub fn unlimited(&self) -> Box<dyn Iterator<Item = Duration>> {
+        let mut unlimited = self.clone();
+        let common = unlimited.common_mut();
+        common.attempts = usize::MAX;
+        common.count = common.count.map(|_| usize::MAX);
+        unlimited.into_iter()
+    }
+
+    /// The number of attempts to make, or `None` to keep going until the command succeeds.
+    pub fn attempt_limit(&self) -> Option<usize> {
+        match self {
+            BackoffStrategy::Exponential {
+                cap_becomes_fixed: true,
+                ..
+            } => None,
+            _ => Some(self.common().attempt_count()),
+        }
+    }
+
+    /// A human readable summary of the schedule.
+    pub fn describe(&self) -> String {
+        let (wait, common) = match self {
+            BackoffStrategy::Fixed { wait, common } => (format!("fixed wait of {}s", wait), common),
+            BackoffStrategy::Exponential {
+                base,
+                multiplier,
+                common,
+                ..
+            } => (
+                format!("exponential wait of {} * {}^n seconds", multiplier, base),
+                common,
+            ),
         };
-        let mut c = Command::new(&command[0]);
-        c.args(&command[1..]);
 
-        c
+        match self.attempt_limit() {
+            Some(runs) if common.count.is_some() => {
+                format!("{}{}, {} runs", wait, common.wait_params, runs)
+            }
+            Some(attempts) => format!(
+                "{}{}, up to {} attempts",
+                wait, common.wait_params, attempts
+            ),
+            None => format!(
+                "{}{}, then fixed at the cap until success",
+                wait, common.wait_params
+            ),
+        }
     }
 }
 impl IntoIterator for BackoffStrategy {
@@ -102,17 +1367,35 @@ impl IntoIterator for BackoffStrategy {
     type IntoIter = Box<dyn Iterator<Item = Duration>>;
 
     fn into_iter(self) -> Self::IntoIter {
+        let mut rng = match self.common().random_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
         match self {
             BackoffStrategy::Fixed { wait, common } => Box::new(
-                (0..common.attempts).map(move |_| create_duration(wait, common.wait_params)),
+                (0..common.attempt_count())
+                    .map(move |_| create_duration(wait, common.wait_params, &mut rng)),
             ),
             BackoffStrategy::Exponential {
                 base,
                 multiplier,
+                cap_becomes_fixed,
                 common,
-            } => Box::new((0..common.attempts).map(move |n| {
-                create_duration(multiplier * base.powi(n as i32), common.wait_params)
-            })),
+            } => {
+                let attempts = if cap_becomes_fixed {
+                    usize::MAX
+                } else {
+                    common.attempt_count()
+                };
+                Box::new((0..attempts).map(move |n| {
+                    // Past i32::MAX attempts the wait has long since reached its cap.
+                    create_duration(
+                        multiplier * base.powi(n.try_into().unwrap_or(i32::MAX)),
+                        common.wait_params,
+                        &mut rng,
+                    )
+                }))
+            }
         }
     }
 }
@@ -121,13 +1404,22 @@ impl IntoIterator for BackoffStrategy {
 mod test {
     use super::*;
 
+    impl ArgumentParser {
+        fn backoff(self) -> BackoffStrategy {
+            match self.mode {
+                Mode::Retry(backoff) => backoff,
+                mode => panic!("expected a retry schedule, found {:?}", mode),
+            }
+        }
+    }
+
     #[test]
     fn test_fixed() {
         let fixed_args = ArgumentParser::new(BackoffStrategy::Fixed {
             wait: 1.0,
             common: CommonArguments::new(3, WaitParameters::default(), Vec::default()),
         });
-        let durations = fixed_args.backoff.into_iter().collect::<Vec<_>>();
+        let durations = fixed_args.backoff().into_iter().collect::<Vec<_>>();
         assert_eq!(durations.len(), 3);
         for duration in durations {
             assert_eq!(duration, Duration::from_secs(1))
@@ -144,22 +1436,40 @@ mod test {
                 Vec::default(),
             ),
         });
-        let durations = fixed_args.backoff.into_iter().collect::<Vec<_>>();
+        let durations = fixed_args.backoff().into_iter().collect::<Vec<_>>();
         assert_eq!(durations.len(), 3);
         for duration in durations {
             assert!(duration >= Duration::from_secs(4) && duration <= Duration::from_secs(6))
         }
     }
 
+    #[test]
+    fn test_random_seed() {
+        let schedule = |seed| {
+            let mut common = CommonArguments::new(
+                5,
+                WaitParameters::new(Some(1.0), None, None),
+                Vec::default(),
+            );
+            common.random_seed = Some(seed);
+            BackoffStrategy::Fixed { wait: 5.0, common }
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(schedule(7), schedule(7));
+        assert_ne!(schedule(7), schedule(8));
+    }
+
     #[test]
     fn test_exponential() {
         // Test base
         let exp_args = ArgumentParser::new(BackoffStrategy::Exponential {
             base: 2.0,
             multiplier: 1.0,
+            cap_becomes_fixed: false,
             common: CommonArguments::new(3, WaitParameters::default(), Vec::default()),
         });
-        let durations = exp_args.backoff.into_iter().collect::<Vec<_>>();
+        let durations = exp_args.backoff().into_iter().collect::<Vec<_>>();
         assert_eq!(durations.len(), 3);
         assert_eq!(durations[0], Duration::from_secs(1));
         assert_eq!(durations[1], Duration::from_secs(2));
@@ -169,30 +1479,782 @@ mod test {
         let exp_args = ArgumentParser::new(BackoffStrategy::Exponential {
             base: 2.0,
             multiplier: 2.0,
+            cap_becomes_fixed: false,
             common: CommonArguments::new(3, WaitParameters::default(), Vec::default()),
         });
-        let durations = exp_args.backoff.into_iter().collect::<Vec<_>>();
+        let durations = exp_args.backoff().into_iter().collect::<Vec<_>>();
         assert_eq!(durations.len(), 3);
         assert_eq!(durations[0], Duration::from_secs(2));
         assert_eq!(durations[1], Duration::from_secs(4));
         assert_eq!(durations[2], Duration::from_secs(8));
     }
 
+    #[test]
+    fn test_exponential_saturates() {
+        let exp_args = BackoffStrategy::Exponential {
+            base: 10.0,
+            multiplier: 1.0,
+            cap_becomes_fixed: false,
+            common: CommonArguments::new(400, WaitParameters::default(), Vec::default()),
+        };
+        let durations = exp_args.into_iter().collect::<Vec<_>>();
+        assert_eq!(durations[3], Duration::from_secs(1000));
+        // 10^400 overflows to infinity, and 10^10 is beyond the ceiling.
+        assert_eq!(durations[10], Duration::from_secs(1_000_000_000));
+        assert_eq!(durations[399], Duration::from_secs(1_000_000_000));
+
+        let exp_args = BackoffStrategy::Exponential {
+            base: 10.0,
+            multiplier: 1.0,
+            cap_becomes_fixed: false,
+            common: CommonArguments::new(
+                400,
+                WaitParameters::new(None, None, Some(60.0)),
+                Vec::default(),
+            ),
+        };
+        assert_eq!(exp_args.into_iter().last(), Some(Duration::from_secs(60)));
+    }
+
     #[test]
     fn test_exponential_with_jitter() {
         let exp_args = ArgumentParser::new(BackoffStrategy::Exponential {
             base: 2.0,
             multiplier: 1.0,
+            cap_becomes_fixed: false,
             common: CommonArguments::new(
                 3,
                 WaitParameters::new(Some(1.0), None, None),
                 Vec::default(),
             ),
         });
-        let durations = exp_args.backoff.into_iter().collect::<Vec<_>>();
+        let durations = exp_args.backoff().into_iter().collect::<Vec<_>>();
         assert_eq!(durations.len(), 3);
         assert!(durations[0] >= Duration::from_secs(0) && durations[0] <= Duration::from_secs(2));
         assert!(durations[1] >= Duration::from_secs(1) && durations[1] <= Duration::from_secs(3));
         assert!(durations[2] >= Duration::from_secs(3) && durations[2] <= Duration::from_secs(5));
     }
+
+    #[test]
+    fn test_command_is_taken_verbatim() {
+        let args =
+            ArgumentParser::try_parse_from(["attempt", "fixed", "-a", "5", "grep", "-v", "--wait"])
+                .unwrap();
+        match args.mode {
+            Mode::Retry(BackoffStrategy::Fixed { wait, common }) => {
+                assert_eq!(wait, 5.0);
+                assert_eq!(common.attempts, 5);
+                assert_eq!(common.verbose, 0);
+                assert_eq!(common.command, ["grep", "-v", "--wait"]);
+            }
+            _ => panic!("Expected the fixed strategy"),
+        }
+    }
+
+    #[test]
+    fn test_waits_take_durations() {
+        let args = ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "-w",
+            "2m",
+            "--wait-min=500ms",
+            "--wait-max",
+            "1.5",
+            "--",
+            "make",
+        ])
+        .unwrap();
+        match args.mode {
+            Mode::Retry(BackoffStrategy::Fixed { wait, common }) => {
+                assert_eq!(wait, 120.0);
+                assert_eq!(common.wait_params.wait_min, Some(0.5));
+                assert_eq!(common.wait_params.wait_max, Some(1.5));
+            }
+            _ => panic!("Expected the fixed strategy"),
+        }
+        assert!(
+            ArgumentParser::try_parse_from(["attempt", "fixed", "--wait-max=soon", "make"])
+                .is_err()
+        );
+        let mut help = Vec::new();
+        ArgumentParser::command().write_help(&mut help).unwrap();
+        assert!(String::from_utf8(help)
+            .unwrap()
+            .contains("--schedule <NAME>"));
+    }
+
+    #[test]
+    fn test_tag() {
+        let parse = |args: &[&str]| match ArgumentParser::try_parse_from(args).unwrap().mode {
+            Mode::Retry(backoff) => backoff.common().clone(),
+            _ => panic!("Expected a backoff strategy"),
+        };
+
+        let common = parse(&["attempt", "fixed", "/usr/bin/curl", "-s"]);
+        assert_eq!(common.tag(2), None);
+
+        let common = parse(&["attempt", "fixed", "--tag", "/usr/bin/curl", "-s"]);
+        assert_eq!(common.command, ["/usr/bin/curl", "-s"]);
+        assert_eq!(common.tag(2).unwrap(), "[curl #2]");
+
+        let common = parse(&["attempt", "fixed", "--tag=api-{attempt}", "curl"]);
+        assert_eq!(common.tag(2).unwrap(), "api-2");
+    }
+
+    #[test]
+    fn test_wait_for() {
+        let args = ["attempt", "wait-for", "tcp://db:5432", "fixed", "-a", "30"];
+        match ArgumentParser::try_parse_from(args).unwrap().mode {
+            Mode::WaitFor(args) => {
+                assert_eq!(args.target.to_string(), "tcp://db:5432");
+                assert_eq!(args.strategy.common().attempts, 30);
+                assert!(args.strategy.common().command.is_empty());
+            }
+            _ => panic!("Expected wait-for"),
+        }
+
+        assert!(
+            ArgumentParser::try_parse_from(["attempt", "wait-for", "db:5432", "fixed"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_stagger() {
+        let parse = |args: &[&str]| match ArgumentParser::try_parse_from(args).unwrap().mode {
+            Mode::Retry(backoff) => backoff.common().clone(),
+            _ => panic!("Expected a backoff strategy"),
+        };
+
+        assert_eq!(parse(&["attempt", "fixed", "true"]).stagger(), None);
+
+        let common = parse(&[
+            "attempt",
+            "fixed",
+            "--stagger-by-host",
+            "5m",
+            "--stagger-key",
+            "web-01",
+            "true",
+        ]);
+        assert_eq!(common.stagger_by_host, Some(Duration::from_secs(300)));
+        assert_eq!(
+            common.stagger(),
+            Some(stagger_offset("web-01", Duration::from_secs(300)))
+        );
+
+        let common = parse(&[
+            "attempt",
+            "fixed",
+            "--stagger-by-host",
+            "5m",
+            "--stagger-min",
+            "1m",
+            "--stagger-key",
+            "web-01",
+            "true",
+        ]);
+        assert_eq!(
+            common.stagger(),
+            Some(Duration::from_secs(60) + stagger_offset("web-01", Duration::from_secs(240)))
+        );
+
+        assert!(ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--stagger-by-host",
+            "1m",
+            "--stagger-min",
+            "5m",
+            "true"
+        ])
+        .unwrap()
+        .validate()
+        .is_err());
+        assert!(
+            ArgumentParser::try_parse_from(["attempt", "fixed", "--stagger-key", "a", "true"])
+                .is_err()
+        );
+        assert!(ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--stagger-min",
+            "1m",
+            "true"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let parse = |args: &[&str]| {
+            ArgumentParser::try_parse_from(args).map(|parser| match parser.mode {
+                Mode::Retry(backoff) => backoff.common().simulate,
+                _ => panic!("Expected a backoff strategy"),
+            })
+        };
+
+        assert_eq!(parse(&["attempt", "fixed", "true"]).unwrap(), None);
+        assert_eq!(
+            parse(&["attempt", "fixed", "--simulate", "true"]).unwrap(),
+            Some(3600.0)
+        );
+        assert_eq!(
+            parse(&["attempt", "fixed", "--simulate=60", "true"]).unwrap(),
+            Some(60.0)
+        );
+        assert!(parse(&["attempt", "fixed", "--simulate=0", "true"]).is_err());
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let parse = |args: &[&str]| match ArgumentParser::try_parse_from(args).unwrap().mode {
+            Mode::Retry(backoff) => backoff.common().timestamps,
+            _ => panic!("Expected a backoff strategy"),
+        };
+
+        assert_eq!(parse(&["attempt", "fixed", "curl"]), None);
+        assert_eq!(
+            parse(&["attempt", "fixed", "--timestamps", "curl"]),
+            Some(Timestamps::Relative)
+        );
+        assert_eq!(
+            parse(&["attempt", "fixed", "--timestamps=wall", "curl"]),
+            Some(Timestamps::Wall)
+        );
+    }
+
+    #[test]
+    fn test_misplaced_options() {
+        let command = ["curl", "--attempts", "5", "--retry", "--wait-max=3", "-v"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            misplaced_options("fixed", &command),
+            ["--attempts", "--wait-max=3"]
+        );
+        assert!(misplaced_options("fixed", &["grep".to_string(), "--help".to_string()]).is_empty());
+
+        // Options of other subcommands are only the command's.
+        let command = [
+            "echo", "--data", "x", "--jobs", "2", "--runs", "3", "--base=2",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert!(misplaced_options("fixed", &command).is_empty());
+        assert_eq!(misplaced_options("exponential", &command), ["--base=2"]);
+    }
+
+    #[test]
+    fn test_cap_becomes_fixed() {
+        let exp_args = BackoffStrategy::Exponential {
+            base: 2.0,
+            multiplier: 1.0,
+            cap_becomes_fixed: true,
+            common: CommonArguments::new(
+                3,
+                WaitParameters::new(None, None, Some(4.0)),
+                Vec::default(),
+            ),
+        };
+        assert_eq!(exp_args.attempt_limit(), None);
+        let durations = exp_args.into_iter().take(6).collect::<Vec<_>>();
+        assert_eq!(
+            durations,
+            [1, 2, 4, 4, 4, 4].map(Duration::from_secs).to_vec()
+        );
+
+        let parse = |args: &[&str]| ArgumentParser::try_parse_from(args).map(|_| ());
+        assert!(parse(&["attempt", "exponential", "--cap-becomes-fixed", "true"]).is_err());
+        assert!(parse(&[
+            "attempt",
+            "exponential",
+            "--cap-becomes-fixed",
+            "--wait-max",
+            "60",
+            "-a",
+            "5",
+            "true"
+        ])
+        .is_err());
+        assert!(parse(&[
+            "attempt",
+            "exponential",
+            "--cap-becomes-fixed",
+            "--wait-max",
+            "60",
+            "true"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn test_describe() {
+        let fixed = BackoffStrategy::Fixed {
+            wait: 1.5,
+            common: CommonArguments::new(3, WaitParameters::default(), Vec::default()),
+        };
+        assert_eq!(fixed.describe(), "fixed wait of 1.5s, up to 3 attempts");
+
+        let exp = BackoffStrategy::Exponential {
+            base: 2.0,
+            multiplier: 1.0,
+            cap_becomes_fixed: false,
+            common: CommonArguments::new(
+                5,
+                WaitParameters::new(Some(1.0), None, Some(60.0)),
+                Vec::default(),
+            ),
+        };
+        assert_eq!(
+            exp.describe(),
+            "exponential wait of 1 * 2^n seconds, jitter of +/- 1s, waiting at most 60s, up to 5 attempts"
+        );
+    }
+
+    #[test]
+    fn test_wait_from_output() {
+        let mut common = CommonArguments::new(3, WaitParameters::default(), Vec::default());
+        assert_eq!(common.wait_from_output(b"retry after 120 seconds"), None);
+
+        common.wait_from_output = Some(Regex::new(r"retry after (\S+ \w+)").unwrap());
+        assert!(common.needs_output());
+        assert_eq!(
+            common.wait_from_output(b"error: retry after 120 seconds\n"),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            common.wait_from_output(b"error: retry after 2 minutes\n"),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(common.wait_from_output(b"error: retry after a while"), None);
+        assert_eq!(common.wait_from_output(b"error: try again"), None);
+
+        common.wait_params.wait_max = Some(60.0);
+        assert_eq!(
+            common.wait_from_output(b"error: retry after 2 minutes\n"),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_tempfail() {
+        let mut common = CommonArguments::new(3, WaitParameters::default(), Vec::default());
+        assert!(common.should_retry(Some(1), b"", Written::default()).retry);
+        assert!(common.should_retry(None, b"", Written::default()).retry);
+
+        common.tempfail = true;
+        assert!(
+            common
+                .should_retry(Some(EX_TEMPFAIL), b"", Written::default())
+                .retry
+        );
+        assert!(!common.should_retry(Some(1), b"", Written::default()).retry);
+        assert!(!common.should_retry(None, b"", Written::default()).retry);
+    }
+
+    #[test]
+    fn test_retry_if_output_matches() {
+        let mut common = CommonArguments::new(3, WaitParameters::default(), Vec::default());
+        common.retry_if_output_matches = vec![
+            Regex::new(r"connecting\nerror: timed out").unwrap(),
+            Regex::new("try again").unwrap(),
+        ];
+        assert!(common.needs_output());
+        assert!(
+            common
+                .should_retry(
+                    Some(1),
+                    b"connecting\nerror: timed out\n",
+                    Written::default()
+                )
+                .retry
+        );
+        assert!(
+            common
+                .should_retry(Some(1), b"please try again later", Written::default())
+                .retry
+        );
+        assert!(
+            !common
+                .should_retry(Some(1), b"error: not found", Written::default())
+                .retry
+        );
+
+        // Retry conditions are combined with --tempfail.
+        common.tempfail = true;
+        assert!(
+            common
+                .should_retry(Some(EX_TEMPFAIL), b"error: not found", Written::default())
+                .retry
+        );
+        assert!(
+            common
+                .should_retry(Some(1), b"please try again later", Written::default())
+                .retry
+        );
+        assert!(
+            !common
+                .should_retry(Some(1), b"error: not found", Written::default())
+                .retry
+        );
+    }
+
+    #[test]
+    fn test_retry_reasons() {
+        let mut common = CommonArguments::new(3, WaitParameters::default(), Vec::default());
+        assert!(common
+            .should_retry(Some(1), b"", Written::default())
+            .reason
+            .starts_with("no retry conditions"));
+
+        common.tempfail = true;
+        common.retry_if_output_matches = vec![Regex::new("try (again|later)").unwrap()];
+        assert_eq!(
+            common.should_retry(Some(EX_TEMPFAIL), b"", Written::default()),
+            RetryDecision::new(true, String::from("--tempfail matched exit code 75"))
+        );
+        assert_eq!(
+            common.should_retry(Some(1), b"please try again", Written::default()),
+            RetryDecision {
+                retry: true,
+                reason: String::from(
+                    "--retry-if-output-matches 'try (again|later)' matched \"try again\" at bytes 7..16"
+                ),
+                groups: vec![String::from("again")],
+            }
+        );
+        assert_eq!(
+            common.should_retry(Some(1), b"not found", Written::default()),
+            RetryDecision::new(
+                false,
+                String::from(
+                    "no retry condition matched (--tempfail, --retry-if-output-matches 'try (again|later)')"
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut common = CommonArguments::new(3, WaitParameters::default(), Vec::default());
+        common.retry_if_bytes = vec![vec![0xff, 0x01]];
+        common.stop_if_bytes = vec![vec![0xff, 0x02]];
+        assert!(common.needs_output());
+        assert_eq!(
+            common.should_retry(Some(1), b"\x00\xff\x01", Written::default()),
+            RetryDecision::new(
+                true,
+                String::from("--retry-if-bytes ff01 matched at bytes 1..3")
+            )
+        );
+        assert_eq!(
+            common.should_retry(Some(1), b"\xff\x01\xff\x02", Written::default()),
+            RetryDecision::new(
+                false,
+                String::from("--stop-if-bytes ff02 matched at bytes 2..4")
+            )
+        );
+        assert!(
+            !common
+                .should_retry(Some(1), b"\xff", Written::default())
+                .retry
+        );
+
+        // A stop condition applies even without any retry conditions.
+        common.retry_if_bytes.clear();
+        assert!(
+            common
+                .should_retry(Some(1), b"\xff", Written::default())
+                .retry
+        );
+        assert!(
+            !common
+                .should_retry(Some(1), b"\xff\x02", Written::default())
+                .retry
+        );
+    }
+
+    #[test]
+    fn test_output_empty() {
+        let nothing = Written::default();
+        let stderr_only = Written {
+            stdout: false,
+            stderr: true,
+            stdout_lines: 0,
+        };
+        let arguments = ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--retry-if-output-empty=stdout",
+            "--stop-if-output-empty",
+            "true",
+        ])
+        .unwrap();
+        let Mode::Retry(strategy) = arguments.mode else {
+            panic!("expected a retry");
+        };
+        let common = strategy.common();
+        assert!(common.needs_output());
+        assert_eq!(
+            common.output_too_short(stderr_only).as_deref(),
+            Some("--retry-if-output-empty=stdout")
+        );
+        assert_eq!(
+            common.should_retry(Some(1), b"error\n", stderr_only),
+            RetryDecision::new(true, String::from("--retry-if-output-empty=stdout held"))
+        );
+        assert_eq!(
+            common.should_retry(Some(1), b"", nothing),
+            RetryDecision::new(false, String::from("--stop-if-output-empty=both held"))
+        );
+        let both = Written {
+            stdout: true,
+            stderr: true,
+            stdout_lines: 1,
+        };
+        assert_eq!(common.output_too_short(both), None);
+        assert!(!common.should_retry(Some(1), b"out\nerr\n", both).retry);
+    }
+
+    #[test]
+    fn test_line_counts() {
+        let arguments = ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--retry-if-lines-lt",
+            "1000",
+            "--stop-if-lines-gt",
+            "5000",
+            "true",
+        ])
+        .unwrap();
+        let Mode::Retry(strategy) = arguments.mode else {
+            panic!("expected a retry");
+        };
+        let common = strategy.common();
+        let lines = |stdout_lines| Written {
+            stdout: stdout_lines > 0,
+            stderr: false,
+            stdout_lines,
+        };
+        assert!(common.needs_output());
+        assert_eq!(
+            common.output_too_short(lines(3)).as_deref(),
+            Some("--retry-if-lines-lt 1000 (stdout lines: 3)")
+        );
+        assert_eq!(common.output_too_short(lines(1000)), None);
+        assert!(common.should_retry(Some(1), b"", lines(999)).retry);
+        assert_eq!(
+            common.should_retry(Some(1), b"", lines(2000)),
+            RetryDecision::new(
+                false,
+                String::from("no retry condition matched (--retry-if-lines-lt 1000)")
+            )
+        );
+        assert_eq!(
+            common.should_retry(Some(1), b"", lines(5001)),
+            RetryDecision::new(
+                false,
+                String::from("--stop-if-lines-gt 5000 held (stdout lines: 5001)")
+            )
+        );
+    }
+
+    #[test]
+    fn test_fatal_condition() {
+        let arguments = ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--abort-if-matches",
+            "(?i)permission denied",
+            "--abort-if-status",
+            "126..=127",
+            "true",
+        ])
+        .unwrap();
+        let Mode::Retry(strategy) = arguments.mode else {
+            panic!("expected a retry");
+        };
+        let common = strategy.common();
+        assert!(common.needs_output());
+        assert_eq!(common.fatal_condition(Some(1), b"timed out\n"), None);
+        assert_eq!(
+            common.fatal_condition(Some(127), b""),
+            Some(String::from(
+                "--abort-if-status 126..=127 matched exit code 127"
+            ))
+        );
+        assert_eq!(
+            common.fatal_condition(None, b"ls: Permission denied\n"),
+            Some(String::from(
+                "--abort-if-matches '(?i)permission denied' matched \"Permission denied\" at bytes 4..21"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_bench_requires_command() {
+        assert!(ArgumentParser::try_parse_from(["attempt", "bench"]).is_err());
+        assert!(ArgumentParser::try_parse_from(["attempt", "bench", "--runs", "3"]).is_err());
+        assert!(ArgumentParser::try_parse_from(["attempt", "bench", "--", "true"]).is_ok());
+    }
+
+    #[test]
+    fn test_retry_requires_command() {
+        let validate = |args: &[&str]| ArgumentParser::try_parse_from(args).unwrap().validate();
+        for strategy in ["fixed", "exponential"] {
+            assert_eq!(
+                validate(&["attempt", strategy]).unwrap_err().kind(),
+                ErrorKind::MissingRequiredArgument
+            );
+            assert!(validate(&["attempt", strategy, "-a", "2", "--"]).is_err());
+            assert!(validate(&["attempt", strategy, "true"]).is_ok());
+        }
+        // Modes which supply the command themselves don't take one.
+        assert!(validate(&["attempt", "wait-for", "tcp://localhost:1", "fixed"]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_skip_signal_value() {
+        assert_eq!(skip_signal_value("USR2"), Ok(libc::SIGUSR2));
+        assert_eq!(skip_signal_value("12"), Ok(12));
+        let forbidden = [
+            libc::SIGKILL,
+            libc::SIGSTOP,
+            libc::SIGSEGV,
+            libc::SIGTSTP,
+            0,
+        ];
+        for signal in forbidden.map(|signal| signal.to_string()) {
+            assert!(skip_signal_value(&signal).is_err(), "{}", signal);
+        }
+        for signal in ["INT", "TERM", "HUP", "USR1"] {
+            assert!(skip_signal_value(signal).is_err(), "{}", signal);
+        }
+        assert!(ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--skip-signal",
+            "9",
+            "false"
+        ])
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_signal_value() {
+        assert_eq!(signal_value("USR2"), Ok(libc::SIGUSR2));
+        assert_eq!(signal_value("sighup"), Ok(libc::SIGHUP));
+        assert_eq!(signal_value("10"), Ok(10));
+        assert!(signal_value("RELOAD").is_err());
+    }
+
+    #[test]
+    fn test_http_arguments() {
+        let arguments = ArgumentParser::try_parse_from([
+            "attempt",
+            "http",
+            "post",
+            "http://localhost:8080/jobs",
+            "-H",
+            "Content-Type:application/json",
+            "--data",
+            "{}",
+            "fixed",
+        ])
+        .unwrap();
+        let Mode::Http(args) = arguments.mode else {
+            panic!("expected http mode");
+        };
+        assert_eq!(args.method, "POST");
+        assert_eq!(args.header, ["Content-Type: application/json"]);
+        assert!(args.expect.matches(204));
+
+        assert!(header_value("no colon").is_err());
+        assert!(header_value("Bad Name: value").is_err());
+        assert!(method_value("GET /").is_err());
+    }
+
+    #[test]
+    fn test_delay_for_status() {
+        let arguments = ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--delay-for-status",
+            "75..=78=15m",
+            "--delay-for-status",
+            "1,75=5s",
+            "true",
+        ])
+        .unwrap();
+        let Mode::Retry(strategy) = arguments.mode else {
+            panic!("expected a retry");
+        };
+        let common = strategy.common();
+        let delay = |code| common.delay_for_status(code).map(|(_, delay)| delay);
+        assert_eq!(delay(Some(75)), Some(Duration::from_secs(900)));
+        assert_eq!(delay(Some(1)), Some(Duration::from_secs(5)));
+        assert_eq!(delay(Some(2)), None);
+        assert_eq!(delay(None), None);
+
+        assert!(ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--delay-for-status",
+            "75",
+            "true"
+        ])
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_success_codes() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let parse = |args: &[&str]| {
+            let arguments = ArgumentParser::try_parse_from(args).unwrap();
+            let Mode::Retry(strategy) = arguments.mode else {
+                panic!("expected a retry");
+            };
+            strategy.common().clone()
+        };
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        let killed = ExitStatus::from_raw(libc::SIGTERM);
+
+        let common = parse(&["attempt", "fixed", "--success-codes", "0,2", "diff"]);
+        assert!(common.succeeded(exited(0)));
+        assert!(common.succeeded(exited(2)));
+        assert!(!common.succeeded(exited(1)));
+        assert!(!common.succeeded(killed));
+
+        let common = parse(&["attempt", "fixed", "diff"]);
+        assert!(common.succeeded(exited(0)));
+        assert!(!common.succeeded(exited(2)));
+    }
+
+    #[test]
+    fn test_count() {
+        let arguments =
+            ArgumentParser::try_parse_from(["attempt", "fixed", "--count", "20", "true"]).unwrap();
+        let Mode::Retry(strategy) = arguments.mode else {
+            panic!("expected a retry");
+        };
+        assert_eq!(strategy.attempt_limit(), Some(20));
+        assert_eq!(strategy.clone().into_iter().count(), 20);
+        assert_eq!(strategy.describe(), "fixed wait of 5s, 20 runs");
+
+        assert!(ArgumentParser::try_parse_from([
+            "attempt",
+            "fixed",
+            "--count",
+            "20",
+            "--until-failure",
+            "true"
+        ])
+        .is_err());
+    }
 }
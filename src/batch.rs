@@ -0,0 +1,251 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::SystemTime,
+};
+
+use clap::{CommandFactory, ErrorKind};
+use log::{info, warn};
+
+use crate::{
+    arguments::{ArgumentParser, BatchArguments},
+    history, logger,
+    report::{Outcome, Report},
+    signals::handle_signals,
+    status::Status,
+    util::shell_command,
+};
+
+/// Attempt each command in the list with the same schedule, running up to `jobs` of them at once,
+/// then print how each one went. Exits successfully only if every command succeeded.
+pub(crate) fn batch(args: BatchArguments) -> Result<(), io::Error> {
+    let common = args.strategy.common().clone();
+    if !common.command.is_empty() {
+        ArgumentParser::command()
+            .error(
+                ErrorKind::UnknownArgument,
+                format!(
+                    "batch reads its commands from --commands, found '{}'",
+                    common.command.join(" ")
+                ),
+            )
+            .exit();
+    }
+    logger::init(
+        common.verbose,
+        common.quiet,
+        common.color,
+        &common.log_prefix,
+        common.log_fd,
+    )?;
+    if common.pid_file.is_some()
+        || common.reason_file.is_some()
+        || common.report_junit.is_some()
+        || common.report_csv.is_some()
+        || common.exec_last
+        || common.cronic
+        || common.control_socket.is_some()
+    {
+        warn!(
+            "--pid-file, --reason-file, --report-junit, --report-csv, --exec-last, --cronic and \
+             --control-socket are ignored in batch mode"
+        );
+    }
+
+    let commands = parse_commands(&read_commands(&args.commands)?);
+    let interrupted = Arc::new(AtomicBool::new(false));
+    // The status reported on SIGUSR1, and the command killed by a second SIGINT, belong to
+    // whichever command started most recently.
+    let status = Arc::new(Mutex::new(Status::new(
+        args.strategy.attempt_limit(),
+        args.strategy.describe(),
+    )));
+    handle_signals(
+        Arc::clone(&status),
+        Arc::clone(&interrupted),
+        !common.no_kill_on_exit,
+        None,
+        None,
+    )?;
+
+    let queue = Mutex::new(commands.iter().enumerate());
+    let reports: Mutex<Vec<Option<Report>>> = Mutex::new(commands.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..args.jobs.max(1) {
+            scope.spawn(|| loop {
+                let Some((i, command)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                if interrupted.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let mut backoff = args.strategy.clone();
+                backoff.common_mut().command = shell_command(command);
+                backoff.common_mut().exec_last = false;
+                backoff.common_mut().report_junit = None;
+                backoff.common_mut().cronic = false;
+                backoff.common_mut().control_socket = None;
+                let common = backoff.common().clone();
+                *status.lock().unwrap() = Status::new(backoff.attempt_limit(), backoff.describe());
+                // Until the first attempt starts, this thread isn't part of any attempt.
+                logger::set_attempt(0, None);
+                info!("Attempting `{}`", command);
+                let started = SystemTime::now();
+                let report = crate::attempt(backoff, &common, &status, &interrupted, None)
+                    .unwrap_or_else(|e| Report::new(Outcome::Failed, e.to_string(), 0, None));
+                if let Some(path) = &common.history_db {
+                    let path = path.clone().unwrap_or_else(history::default_path);
+                    if let Err(e) = history::record(&path, command, started, &report) {
+                        warn!("Failed to record the run in {}: {}", path.display(), e);
+                    }
+                }
+                reports.lock().unwrap()[i] = Some(report);
+            });
+        }
+    });
+
+    let summary = Summary {
+        commands: &commands,
+        reports: reports.into_inner().unwrap(),
+    };
+    println!("{}", summary);
+    std::process::exit(summary.exit_code());
+}
+
+fn read_commands(path: &std::path::Path) -> Result<String, io::Error> {
+    if path.as_os_str() == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+/// The commands in a list, one per line, skipping blank lines and comments.
+fn parse_commands(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// How each command in a batch went. Commands which never started, because `attempt` was
+/// interrupted first, have no report.
+struct Summary<'a> {
+    commands: &'a [String],
+    reports: Vec<Option<Report>>,
+}
+
+impl Summary<'_> {
+    fn exit_code(&self) -> i32 {
+        let outcomes = self
+            .reports
+            .iter()
+            .map(|report| report.as_ref().map(|r| r.outcome));
+        if outcomes
+            .clone()
+            .all(|outcome| outcome == Some(Outcome::Succeeded))
+        {
+            0
+        } else if outcomes
+            .into_iter()
+            .any(|outcome| outcome.is_none_or(|outcome| outcome == Outcome::Interrupted))
+        {
+            Report::new(Outcome::Interrupted, "", 0, None).exit_code()
+        } else {
+            1
+        }
+    }
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (command, report) in self.commands.iter().zip(&self.reports) {
+            match report {
+                Some(report) if report.outcome == Outcome::Succeeded => writeln!(
+                    f,
+                    "succeeded after {} attempt{}: {}",
+                    report.attempts,
+                    if report.attempts == 1 { "" } else { "s" },
+                    command
+                )?,
+                Some(report) => writeln!(
+                    f,
+                    "{} after {} attempt{}: {} ({})",
+                    report.outcome.name(),
+                    report.attempts,
+                    if report.attempts == 1 { "" } else { "s" },
+                    command,
+                    report.reason
+                )?,
+                None => writeln!(f, "not started: {}", command)?,
+            }
+        }
+
+        let succeeded = self
+            .reports
+            .iter()
+            .flatten()
+            .filter(|report| report.outcome == Outcome::Succeeded)
+            .count();
+        write!(
+            f,
+            "{} of {} commands succeeded",
+            succeeded,
+            self.commands.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse_commands("make build\n\n  # the flaky one\n./deploy.sh --env prod  \n"),
+            ["make build", "./deploy.sh --env prod"]
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let commands = [
+            String::from("make build"),
+            String::from("./deploy.sh"),
+            String::from("./notify.sh"),
+        ];
+        let summary = Summary {
+            commands: &commands,
+            reports: vec![
+                Some(Report::new(Outcome::Succeeded, "", 1, None)),
+                Some(Report::new(Outcome::Failed, "no attempts left", 3, None)),
+                Some(Report::new(Outcome::Succeeded, "", 2, None)),
+            ],
+        };
+        assert_eq!(
+            summary.to_string(),
+            "succeeded after 1 attempt: make build\n\
+             failed after 3 attempts: ./deploy.sh (no attempts left)\n\
+             succeeded after 2 attempts: ./notify.sh\n\
+             2 of 3 commands succeeded"
+        );
+        assert_eq!(summary.exit_code(), 1);
+
+        let summary = Summary {
+            commands: &commands[..2],
+            reports: vec![Some(Report::new(Outcome::Succeeded, "", 1, None)), None],
+        };
+        assert!(summary.to_string().contains("not started: ./deploy.sh\n"));
+        assert_eq!(summary.exit_code(), 130);
+    }
+}
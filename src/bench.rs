@@ -0,0 +1,126 @@
+use std::{collections::BTreeMap, fmt, io, time::Duration, time::Instant};
+
+use crate::{arguments::BenchArguments, util::build_command};
+
+/// Run the command the requested number of times, then print a summary of the results.
+pub(crate) fn bench(args: &BenchArguments) -> Result<(), io::Error> {
+    let mut command = build_command(&args.command);
+    let mut summary = Summary::default();
+    for _ in 0..args.runs {
+        let start = Instant::now();
+        let status = command.status()?;
+        summary.record(start.elapsed(), status.success(), status.to_string());
+    }
+
+    println!("{}", summary);
+    Ok(())
+}
+
+/// How a number of runs of a command went.
+#[derive(Debug, Default)]
+pub(crate) struct Summary {
+    durations: Vec<Duration>,
+    successes: usize,
+    outcomes: BTreeMap<String, usize>,
+}
+
+impl Summary {
+    pub(crate) fn record(&mut self, duration: Duration, success: bool, outcome: String) {
+        self.durations.push(duration);
+        if success {
+            self.successes += 1;
+        }
+        *self.outcomes.entry(outcome).or_default() += 1;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let runs = self.durations.len();
+        writeln!(f, "runs: {}", runs)?;
+        if runs == 0 {
+            return Ok(());
+        }
+
+        writeln!(
+            f,
+            "succeeded: {} ({:.1}%)",
+            self.successes,
+            100.0 * self.successes as f64 / runs as f64
+        )?;
+
+        let mut durations = self.durations.clone();
+        durations.sort();
+        writeln!(
+            f,
+            "duration: min {:.3}s, p50 {:.3}s, p90 {:.3}s, p99 {:.3}s, max {:.3}s",
+            durations[0].as_secs_f64(),
+            percentile(&durations, 50.0).as_secs_f64(),
+            percentile(&durations, 90.0).as_secs_f64(),
+            percentile(&durations, 99.0).as_secs_f64(),
+            durations[runs - 1].as_secs_f64(),
+        )?;
+
+        write!(f, "outcomes:")?;
+        for (outcome, count) in &self.outcomes {
+            write!(f, "\n  {}: {}", outcome, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The nearest-rank percentile of a sorted, non-empty list of durations.
+fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let durations = (1..=10).map(Duration::from_secs).collect::<Vec<_>>();
+        assert_eq!(percentile(&durations, 0.0), Duration::from_secs(1));
+        assert_eq!(percentile(&durations, 50.0), Duration::from_secs(5));
+        assert_eq!(percentile(&durations, 90.0), Duration::from_secs(9));
+        assert_eq!(percentile(&durations, 99.0), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = Summary::default();
+        summary.record(
+            Duration::from_millis(100),
+            true,
+            String::from("exit status: 0"),
+        );
+        summary.record(
+            Duration::from_millis(300),
+            false,
+            String::from("exit status: 1"),
+        );
+        summary.record(
+            Duration::from_millis(200),
+            true,
+            String::from("exit status: 0"),
+        );
+        summary.record(
+            Duration::from_millis(400),
+            true,
+            String::from("exit status: 0"),
+        );
+
+        assert_eq!(
+            summary.to_string(),
+            "runs: 4\n\
+             succeeded: 3 (75.0%)\n\
+             duration: min 0.100s, p50 0.200s, p90 0.400s, p99 0.400s, max 0.400s\n\
+             outcomes:\n  \
+             exit status: 0: 3\n  \
+             exit status: 1: 1"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::util::parse_duration;
+
+/// How many attempts a budget allows over how long, given as "N per DURATION" or "N/DURATION".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rate {
+    pub attempts: u32,
+    pub per: Duration,
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (attempts, per) = text
+            .split_once(" per ")
+            .or_else(|| text.split_once('/'))
+            .ok_or_else(|| format!("{} is not of the form 'N per DURATION'", text))?;
+        let attempts = match attempts.trim().parse() {
+            Ok(attempts) if attempts > 0 => attempts,
+            _ => return Err(format!("{} is not a positive number of attempts", attempts)),
+        };
+        let per = match parse_duration(per.trim()) {
+            Some(per) if !per.is_zero() => per,
+            _ => return Err(format!("{} is not a positive duration", per)),
+        };
+
+        Ok(Self { attempts, per })
+    }
+}
+
+/// A budget of attempts shared by every `attempt` process using the same file, so that many jobs
+/// retrying against one dependency don't overwhelm it between them. It's a token bucket which
+/// holds up to `rate.attempts` attempts and refills at `rate`, kept in the file as the number of
+/// attempts available and when that was last updated. The file is locked while it's used, which
+/// also works over NFS.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    path: PathBuf,
+    rate: Rate,
+}
+
+impl Budget {
+    pub(crate) fn new(path: PathBuf, rate: Rate) -> Self {
+        Self { path, rate }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Take an attempt from the budget if one is available, and otherwise return how long until
+    /// one will be.
+    pub(crate) fn take(&self) -> Result<Option<Duration>, io::Error> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        file.lock()?;
+
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let available = available(parse_state(&text), now, self.rate);
+        if available < 1.0 {
+            let per_attempt = self.rate.per.as_secs_f64() / f64::from(self.rate.attempts);
+            return Ok(Some(Duration::from_secs_f64(
+                (1.0 - available) * per_attempt,
+            )));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{} {}", available - 1.0, now)?;
+        Ok(None)
+    }
+}
+
+/// The attempts available and when that was recorded, in seconds since the Unix epoch, or `None`
+/// if the file is new or can't be understood, in which case the budget starts out full.
+fn parse_state(text: &str) -> Option<(f64, f64)> {
+    let (available, updated) = text.trim().split_once(' ')?;
+    Some((available.parse().ok()?, updated.parse().ok()?))
+}
+
+/// The attempts available at `now`, having refilled at `rate` since the state was recorded.
+fn available(state: Option<(f64, f64)>, now: f64, rate: Rate) -> f64 {
+    let capacity = f64::from(rate.attempts);
+    match state {
+        Some((available, updated)) if available.is_finite() && updated.is_finite() => {
+            let refilled = (now - updated).max(0.0) * capacity / rate.per.as_secs_f64();
+            (available + refilled).min(capacity)
+        }
+        _ => capacity,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs};
+
+    fn rate(attempts: u32, secs: u64) -> Rate {
+        Rate {
+            attempts,
+            per: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!("100 per 1m".parse(), Ok(rate(100, 60)));
+        assert_eq!("5/10s".parse(), Ok(rate(5, 10)));
+        assert!("0 per 1m".parse::<Rate>().is_err());
+        assert!("5 per 0s".parse::<Rate>().is_err());
+        assert!("5 every 1m".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn test_available() {
+        let rate = rate(10, 60);
+        assert_eq!(available(None, 1000.0, rate), 10.0);
+        assert_eq!(available(parse_state("2.5 1000"), 1000.0, rate), 2.5);
+        assert_eq!(available(parse_state("2.5 1000"), 1030.0, rate), 7.5);
+        assert_eq!(available(parse_state("2.5 1000"), 2000.0, rate), 10.0);
+        assert_eq!(available(parse_state("garbage"), 1000.0, rate), 10.0);
+    }
+
+    #[test]
+    fn test_take() {
+        let path = env::temp_dir().join(format!("attempt-budget-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let budget = Budget::new(path.clone(), rate(2, 3600));
+
+        assert_eq!(budget.take().unwrap(), None);
+        assert_eq!(budget.take().unwrap(), None);
+        let wait = budget.take().unwrap().unwrap();
+        assert!(wait > Duration::from_secs(1790) && wait <= Duration::from_secs(1800));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use std::{fs::File, io, path::PathBuf, str::FromStr};
+
+use log::warn;
+use sha2::{Digest, Sha256};
+
+/// A file the command is expected to produce, along with its SHA-256 checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileChecksum {
+    path: PathBuf,
+    sha256: String,
+}
+
+impl FromStr for FileChecksum {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (path, sha256) = text
+            .rsplit_once('=')
+            .ok_or_else(|| String::from("expected PATH=SHA256"))?;
+        if path.is_empty() {
+            return Err(String::from("expected PATH=SHA256"));
+        }
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("{} is not a SHA-256 checksum", sha256));
+        }
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            sha256: sha256.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl FileChecksum {
+    /// The file's actual checksum, as lowercase hex.
+    fn actual(&self) -> Result<String, io::Error> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(&self.path)?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Check that every file exists and has the expected checksum, logging any that don't.
+pub(crate) fn verify_files(checksums: &[FileChecksum]) -> bool {
+    let mut verified = true;
+    for checksum in checksums {
+        match checksum.actual() {
+            Ok(actual) if actual == checksum.sha256 => (),
+            Ok(actual) => {
+                warn!(
+                    "{} has checksum {}, expected {}",
+                    checksum.path.display(),
+                    actual,
+                    checksum.sha256
+                );
+                verified = false;
+            }
+            Err(e) => {
+                warn!("Failed to read {}: {}", checksum.path.display(), e);
+                verified = false;
+            }
+        }
+    }
+
+    verified
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    /// The SHA-256 checksum of "hello\n".
+    const HELLO: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    #[test]
+    fn test_parse() {
+        let checksum: FileChecksum = format!("out/a=b.tar={}", HELLO.to_ascii_uppercase())
+            .parse()
+            .unwrap();
+        assert_eq!(checksum.path, PathBuf::from("out/a=b.tar"));
+        assert_eq!(checksum.sha256, HELLO);
+
+        assert!("out/app.tar".parse::<FileChecksum>().is_err());
+        assert!("out/app.tar=abc123".parse::<FileChecksum>().is_err());
+        assert!(format!("={}", HELLO).parse::<FileChecksum>().is_err());
+    }
+
+    #[test]
+    fn test_verify_files() {
+        let path = std::env::temp_dir().join(format!("attempt-test-{}.sha", std::process::id()));
+        let checksum = format!("{}={}", path.display(), HELLO).parse().unwrap();
+        let checksums = [checksum];
+        assert!(!verify_files(&checksums));
+
+        fs::write(&path, "hello\n").unwrap();
+        assert!(verify_files(&checksums));
+
+        fs::write(&path, "corrupt\n").unwrap();
+        assert!(!verify_files(&checksums));
+        fs::remove_file(&path).unwrap();
+    }
+}
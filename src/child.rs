@@ -0,0 +1,633 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    arguments::{OutputStream, Timestamps},
+    spill::{SpillBuffer, SpilledBytes},
+    util::{format_size, format_time_of_day},
+};
+
+/// Everything the command wrote to stdout and stderr, in the order it arrived.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CapturedOutput(Arc<Mutex<SpillBuffer>>);
+
+impl CapturedOutput {
+    fn new(spill_threshold: Option<u64>) -> Self {
+        Self(Arc::new(Mutex::new(SpillBuffer::new(spill_threshold))))
+    }
+
+    fn append(&self, bytes: &[u8]) {
+        self.0.lock().unwrap().append(bytes);
+    }
+
+    fn take(&self) -> Result<SpilledBytes, io::Error> {
+        self.0.lock().unwrap().take()
+    }
+
+    fn lost(&self) -> Option<String> {
+        self.0.lock().unwrap().lost().map(String::from)
+    }
+}
+
+/// Which of stdout and stderr the command wrote anything to, and how many lines it wrote to
+/// stdout. Only known when the output passes through `attempt`; with a pseudo-terminal,
+/// everything counts as stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Written {
+    pub stdout: bool,
+    pub stderr: bool,
+    /// The number of lines written to stdout, counting a final line without a newline.
+    pub stdout_lines: usize,
+}
+
+impl Written {
+    /// Whether nothing was written to the given stream.
+    pub(crate) fn is_empty(self, stream: OutputStream) -> bool {
+        match stream {
+            OutputStream::Both => !self.stdout && !self.stderr,
+            OutputStream::Stdout => !self.stdout,
+            OutputStream::Stderr => !self.stderr,
+        }
+    }
+}
+
+/// An attempt of the command which is still running, along with any threads forwarding its
+/// output.
+pub(crate) struct RunningCommand {
+    child: Child,
+    /// The threads forwarding stdout and stderr, in that order.
+    forwarders: Vec<JoinHandle<Forwarded>>,
+    output: CapturedOutput,
+    /// What the command wrote to stdout alone, if that's being captured as well.
+    stdout: Option<CapturedOutput>,
+}
+
+/// How an attempt of the command ended, and what it wrote.
+pub(crate) struct Exited {
+    pub status: ExitStatus,
+    /// The output captured from both streams, if any.
+    pub output: SpilledBytes,
+    /// The output captured from stdout alone, if any.
+    pub stdout: SpilledBytes,
+    pub written: Written,
+    /// What the command used, if the platform reports it.
+    pub usage: Option<ResourceUsage>,
+    /// Why some of the captured output was lost, if it was.
+    pub lost_output: Option<String>,
+}
+
+/// The resources an attempt of the command used, including any processes of its own it waited
+/// for, as reported by the kernel once it exited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ResourceUsage {
+    /// The largest the command's resident set grew, in bytes.
+    pub max_rss: u64,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// Page faults which needed I/O.
+    pub major_faults: u64,
+    /// Page faults which didn't.
+    pub minor_faults: u64,
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.3}s user and {:.3}s system CPU time, {} max RSS, {} major and {} minor page faults",
+            self.user_time.as_secs_f64(),
+            self.system_time.as_secs_f64(),
+            format_size(self.max_rss),
+            self.major_faults,
+            self.minor_faults
+        )
+    }
+}
+
+impl RunningCommand {
+    pub(crate) fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Write `input` to the command's stdin, which must be piped, and then close it. This happens
+    /// in the background, so a command which never reads its stdin can't hold us up.
+    pub(crate) fn write_stdin(&mut self, input: Vec<u8>) {
+        if let Some(mut stdin) = self.child.stdin.take() {
+            thread::spawn(move || {
+                // The command may exit without reading all of its input, which is up to it.
+                let _ = stdin.write_all(&input);
+            });
+        }
+    }
+
+    /// Wait for the command to exit and for its output to be forwarded.
+    pub(crate) fn wait(mut self) -> Result<Exited, io::Error> {
+        let (status, usage) = wait_with_usage(&mut self.child)?;
+        let mut forwarded = self
+            .forwarders
+            .into_iter()
+            .map(|forwarder| forwarder.join().unwrap_or_default());
+        let stdout = forwarded.next().unwrap_or_default();
+        let stderr = forwarded.next().unwrap_or_default();
+        let written = Written {
+            stdout: stdout.bytes > 0,
+            stderr: stderr.bytes > 0,
+            stdout_lines: stdout.lines,
+        };
+
+        let lost_output = self
+            .output
+            .lost()
+            .or_else(|| self.stdout.as_ref().and_then(CapturedOutput::lost));
+        Ok(Exited {
+            status,
+            lost_output,
+            output: self.output.take()?,
+            stdout: match self.stdout {
+                Some(stdout) => stdout.take()?,
+                None => SpilledBytes::Memory(Vec::new()),
+            },
+            written,
+            usage,
+        })
+    }
+}
+
+/// Wait for the child to exit, collecting the resources it used on the way.
+#[cfg(unix)]
+fn wait_with_usage(child: &mut Child) -> Result<(ExitStatus, Option<ResourceUsage>), io::Error> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        if unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) } != -1 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    // Linux reports the resident set in kilobytes, and macOS in bytes.
+    let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    let time = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+
+    Ok((
+        ExitStatus::from_raw(status),
+        Some(ResourceUsage {
+            max_rss: usage.ru_maxrss as u64 * rss_unit,
+            user_time: time(usage.ru_utime),
+            system_time: time(usage.ru_stime),
+            major_faults: usage.ru_majflt as u64,
+            minor_faults: usage.ru_minflt as u64,
+        }),
+    ))
+}
+
+#[cfg(not(unix))]
+fn wait_with_usage(child: &mut Child) -> Result<(ExitStatus, Option<ResourceUsage>), io::Error> {
+    Ok((child.wait()?, None))
+}
+
+/// How the command's output should be handled.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OutputOptions {
+    /// Attach the command's stdout and stderr to a pseudo-terminal.
+    pub pty: bool,
+    /// Keep a copy of the output, so it can be examined once the command exits.
+    pub capture: bool,
+    /// Keep a separate copy of what's written to stdout alone.
+    pub capture_stdout: bool,
+    /// Move the captured output to a temporary file once it's larger than this many bytes.
+    pub spill_threshold: Option<u64>,
+    /// Prefix each line of output with this label.
+    pub tag: Option<String>,
+    /// Prefix each line of output with the time it arrived.
+    pub timestamps: Option<Timestamps>,
+    /// Prefix each line of output with the stream it was written to. Has no effect with a
+    /// pseudo-terminal, which merges the two.
+    pub label_streams: bool,
+    /// Capture the output without showing it.
+    pub quiet: bool,
+}
+
+impl OutputOptions {
+    /// Whether the output needs to pass through `attempt`, rather than going straight to our
+    /// stdout and stderr.
+    fn pass_through(&self) -> bool {
+        self.capture
+            || self.capture_stdout
+            || self.quiet
+            || self.tag.is_some()
+            || self.timestamps.is_some()
+            || self.label_streams
+    }
+
+    /// Where to write output bound for `stream`.
+    fn destination(&self, stream: impl Write + Send + 'static) -> Box<dyn Write + Send> {
+        if self.quiet {
+            Box::new(io::sink())
+        } else {
+            Box::new(stream)
+        }
+    }
+
+    fn line_prefix(&self, stream: &'static str) -> LinePrefix {
+        LinePrefix {
+            tag: self.tag.clone(),
+            timestamps: self.timestamps,
+            stream: self.label_streams.then_some(stream),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Spawn the command, handling its output as requested. Unless it needs to be passed through
+/// `attempt`, the command writes straight to our stdout and stderr.
+pub(crate) fn spawn(
+    command: &mut Command,
+    options: &OutputOptions,
+) -> Result<RunningCommand, io::Error> {
+    let output = CapturedOutput::new(options.spill_threshold);
+    let sink = options.capture.then(|| output.clone());
+    let stdout_output = options
+        .capture_stdout
+        .then(|| CapturedOutput::new(options.spill_threshold));
+    let stdout_sinks = [sink.clone(), stdout_output.clone()];
+
+    if options.pty {
+        let (child, terminal) = spawn_with_pty(command)?;
+        let stdout = PrefixedWriter::new(
+            options.destination(io::stdout()),
+            options.line_prefix(STDOUT_LABEL),
+        );
+        let forwarders = vec![thread::spawn(move || {
+            forward(terminal, stdout, stdout_sinks)
+        })];
+        Ok(RunningCommand {
+            child,
+            forwarders,
+            output,
+            stdout: stdout_output,
+        })
+    } else if options.pass_through() {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let child = command.spawn();
+        command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let mut child = child?;
+
+        let (stdout, stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+        let stdout_sink = PrefixedWriter::new(
+            options.destination(io::stdout()),
+            options.line_prefix(STDOUT_LABEL),
+        );
+        let stderr_sink = PrefixedWriter::new(
+            options.destination(io::stderr()),
+            options.line_prefix(STDERR_LABEL),
+        );
+        let forwarders = vec![
+            thread::spawn(move || forward(stdout, stdout_sink, stdout_sinks)),
+            thread::spawn(move || forward(stderr, stderr_sink, [sink, None])),
+        ];
+        Ok(RunningCommand {
+            child,
+            forwarders,
+            output,
+            stdout: stdout_output,
+        })
+    } else {
+        Ok(RunningCommand {
+            child: command.spawn()?,
+            forwarders: Vec::new(),
+            output,
+            stdout: stdout_output,
+        })
+    }
+}
+
+const STDOUT_LABEL: &str = "[stdout]";
+const STDERR_LABEL: &str = "[stderr]";
+
+/// What to put at the start of each line of the command's output.
+#[derive(Debug, Clone)]
+struct LinePrefix {
+    tag: Option<String>,
+    timestamps: Option<Timestamps>,
+    /// The label of the stream the line was written to.
+    stream: Option<&'static str>,
+    /// When the attempt started, for relative timestamps.
+    started: Instant,
+}
+
+impl LinePrefix {
+    fn is_empty(&self) -> bool {
+        self.tag.is_none() && self.timestamps.is_none() && self.stream.is_none()
+    }
+
+    /// The prefix for a line starting now, including the space separating it from the line.
+    fn render(&self) -> String {
+        let mut prefix = String::new();
+        match self.timestamps {
+            Some(Timestamps::Relative) => {
+                prefix += &format!("+{:.3}s ", self.started.elapsed().as_secs_f64())
+            }
+            Some(Timestamps::Wall) => {
+                prefix += &format_time_of_day(SystemTime::now());
+                prefix.push(' ');
+            }
+            None => (),
+        }
+        for label in [self.tag.as_deref(), self.stream].into_iter().flatten() {
+            prefix += label;
+            prefix.push(' ');
+        }
+
+        prefix
+    }
+}
+
+/// Writes the command's output, prefixing each line as requested. Output is written as soon as it
+/// arrives rather than a line at a time, so partial lines such as progress bars and prompts still
+/// show up promptly.
+struct PrefixedWriter<W> {
+    inner: W,
+    prefix: LinePrefix,
+    at_line_start: bool,
+}
+
+impl<W: Write> PrefixedWriter<W> {
+    fn new(inner: W, prefix: LinePrefix) -> Self {
+        Self {
+            inner,
+            prefix,
+            at_line_start: true,
+        }
+    }
+
+    fn write_chunk(&mut self, mut chunk: &[u8]) -> Result<(), io::Error> {
+        if self.prefix.is_empty() {
+            return self.inner.write_all(chunk).and_then(|_| self.inner.flush());
+        }
+
+        while !chunk.is_empty() {
+            if self.at_line_start {
+                self.inner.write_all(self.prefix.render().as_bytes())?;
+            }
+            let line_end = chunk
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(chunk.len(), |i| i + 1);
+            self.inner.write_all(&chunk[..line_end])?;
+            self.at_line_start = chunk[line_end - 1] == b'\n';
+            chunk = &chunk[line_end..];
+        }
+
+        self.inner.flush()
+    }
+}
+
+#[cfg(unix)]
+fn spawn_with_pty(command: &mut Command) -> Result<(Child, std::fs::File), io::Error> {
+    crate::pty::spawn_with_pty(command)
+}
+
+#[cfg(not(unix))]
+fn spawn_with_pty(_command: &mut Command) -> Result<(Child, std::fs::File), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--pty is only supported on Unix",
+    ))
+}
+
+/// How much output a forwarding thread copied.
+#[derive(Debug, Clone, Copy, Default)]
+struct Forwarded {
+    bytes: u64,
+    lines: usize,
+}
+
+/// Copy everything read from `source` to `sink`, appending it to each of `outputs` given as well,
+/// and return how much there was.
+fn forward(
+    mut source: impl Read,
+    mut sink: PrefixedWriter<impl Write>,
+    outputs: [Option<CapturedOutput>; 2],
+) -> Forwarded {
+    let mut forwarded = Forwarded::default();
+    let mut at_line_start = true;
+    let mut buffer = [0u8; 4096];
+    loop {
+        // Reading from a pseudo-terminal fails with EIO rather than returning 0 once it has been
+        // closed, so any error other than an interruption ends the output.
+        let n = match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        forwarded.bytes += n as u64;
+        forwarded.lines += buffer[..n].iter().filter(|&&b| b == b'\n').count();
+        at_line_start = buffer[n - 1] == b'\n';
+
+        for output in outputs.iter().flatten() {
+            output.append(&buffer[..n]);
+        }
+        // Keep draining the command's output even if we can no longer write it anywhere, so that
+        // it doesn't block.
+        let _ = sink.write_chunk(&buffer[..n]);
+    }
+    if !at_line_start {
+        forwarded.lines += 1;
+    }
+
+    forwarded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capture_combines_streams_in_order() {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "echo out; sleep 0.1; echo err >&2; sleep 0.1; echo out",
+        ]);
+        let options = OutputOptions {
+            capture: true,
+            ..Default::default()
+        };
+        let exited = spawn(&mut command, &options).unwrap().wait().unwrap();
+        assert!(exited.status.success());
+        assert_eq!(&*exited.output, b"out\nerr\nout\n");
+        assert!(exited.stdout.is_empty());
+        assert_eq!(
+            exited.written,
+            Written {
+                stdout: true,
+                stderr: true,
+                stdout_lines: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_no_capture() {
+        let mut command = Command::new("true");
+        let options = OutputOptions::default();
+        let exited = spawn(&mut command, &options).unwrap().wait().unwrap();
+        assert!(exited.status.success());
+        assert!(exited.output.is_empty());
+    }
+
+    #[test]
+    fn test_quiet_still_captures() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2"]);
+        let options = OutputOptions {
+            capture: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let output = spawn(&mut command, &options)
+            .unwrap()
+            .wait()
+            .unwrap()
+            .output;
+        assert_eq!(sorted_lines(&output), [&b"err\n"[..], b"out\n"]);
+    }
+
+    #[test]
+    fn test_capture_stdout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2"]);
+        let options = OutputOptions {
+            capture: true,
+            capture_stdout: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let exited = spawn(&mut command, &options).unwrap().wait().unwrap();
+        assert_eq!(sorted_lines(&exited.output), [&b"err\n"[..], b"out\n"]);
+        assert_eq!(&*exited.stdout, b"out\n");
+    }
+
+    /// The lines of the captured output in sorted order, as stdout and stderr are forwarded by
+    /// separate threads and lines written close together can arrive in either order.
+    fn sorted_lines(output: &[u8]) -> Vec<&[u8]> {
+        let mut lines: Vec<&[u8]> = output.split_inclusive(|&byte| byte == b'\n').collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_write_stdin() {
+        let mut command = Command::new("cat");
+        command.stdin(Stdio::piped());
+        let options = OutputOptions {
+            capture: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut child = spawn(&mut command, &options).unwrap();
+        child.write_stdin(b"attempt 2\n".to_vec());
+        assert_eq!(&*child.wait().unwrap().output, b"attempt 2\n");
+    }
+
+    #[test]
+    fn test_written() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo err >&2"]);
+        let options = OutputOptions {
+            capture: true,
+            ..Default::default()
+        };
+        let written = spawn(&mut command, &options)
+            .unwrap()
+            .wait()
+            .unwrap()
+            .written;
+        assert!(written.is_empty(OutputStream::Stdout));
+        assert!(!written.is_empty(OutputStream::Stderr));
+        assert!(!written.is_empty(OutputStream::Both));
+        assert_eq!(written.stdout_lines, 0);
+
+        let mut command = Command::new("printf");
+        command.arg("one\n\ntwo");
+        let written = spawn(&mut command, &options)
+            .unwrap()
+            .wait()
+            .unwrap()
+            .written;
+        assert_eq!(written.stdout_lines, 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resource_usage() {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done; exit 3",
+        ]);
+        let exited = spawn(&mut command, &OutputOptions::default())
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(exited.status.code(), Some(3));
+        let usage = exited.usage.unwrap();
+        assert!(usage.max_rss > 0);
+        assert!(usage.minor_faults > 0);
+        assert!(usage.user_time + usage.system_time > Duration::ZERO);
+    }
+
+    fn prefix(tag: Option<&str>, timestamps: Option<Timestamps>) -> LinePrefix {
+        LinePrefix {
+            tag: tag.map(String::from),
+            timestamps,
+            stream: None,
+            started: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_prefixed_writer() {
+        let mut writer = PrefixedWriter::new(Vec::new(), prefix(Some("[curl #2]"), None));
+        writer.write_chunk(b"one\ntw").unwrap();
+        writer.write_chunk(b"o\n").unwrap();
+        writer.write_chunk(b"three\nfour").unwrap();
+        assert_eq!(
+            writer.inner,
+            b"[curl #2] one\n[curl #2] two\n[curl #2] three\n[curl #2] four"
+        );
+
+        let mut writer = PrefixedWriter::new(Vec::new(), prefix(None, None));
+        writer.write_chunk(b"one\ntwo\n").unwrap();
+        assert_eq!(writer.inner, b"one\ntwo\n");
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let relative = prefix(Some("[curl #2]"), Some(Timestamps::Relative)).render();
+        assert!(relative.starts_with("+0.0"), "{}", relative);
+        assert!(relative.ends_with("s [curl #2] "), "{}", relative);
+
+        let wall = prefix(None, Some(Timestamps::Wall)).render();
+        assert_eq!(wall.len(), "12:34:56.789 ".len(), "{}", wall);
+    }
+
+    #[test]
+    fn test_stream_labels() {
+        let mut prefix = prefix(Some("[curl #2]"), None);
+        prefix.stream = Some(STDERR_LABEL);
+        assert_eq!(prefix.render(), "[curl #2] [stderr] ");
+    }
+}
@@ -0,0 +1,170 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+/// A set of exit codes, written as a comma-separated list of codes and ranges, e.g. "1,3..5" or
+/// "75..=78". As in Rust, `A..B` excludes B while `A..=B` includes it. `*` matches any code, and
+/// a leading `!` matches every code the rest of the pattern doesn't, e.g. "!0..1".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CodePattern {
+    text: String,
+    negated: bool,
+    ranges: Vec<RangeInclusive<i32>>,
+}
+
+impl CodePattern {
+    pub(crate) fn matches(&self, code: i32) -> bool {
+        self.ranges.iter().any(|range| range.contains(&code)) != self.negated
+    }
+}
+
+impl FromStr for CodePattern {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { text, position: 0 };
+        let negated = parser.eat("!");
+        let mut ranges = vec![parser.range()?];
+        while parser.eat(",") {
+            ranges.push(parser.range()?);
+        }
+        if parser.position < text.len() {
+            return Err(parser.error("expected ',' or the end of the pattern"));
+        }
+
+        Ok(Self {
+            text: text.to_string(),
+            negated,
+            ranges,
+        })
+    }
+}
+
+impl fmt::Display for CodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// The byte offset of the next character to parse.
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.position..]
+    }
+
+    /// Consume `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.position += token.len();
+        }
+        found
+    }
+
+    fn error(&self, expected: &str) -> String {
+        format!(
+            "{} at position {} of '{}'",
+            expected,
+            self.position + 1,
+            self.text
+        )
+    }
+
+    fn range(&mut self) -> Result<RangeInclusive<i32>, String> {
+        if self.eat("*") {
+            return Ok(i32::MIN..=i32::MAX);
+        }
+        let start = self.code()?;
+        if self.eat("..=") {
+            Ok(start..=self.code()?)
+        } else if self.eat("..") {
+            let end = self.code()?;
+            Ok(start..=end.saturating_sub(1))
+        } else {
+            Ok(start..=start)
+        }
+    }
+
+    fn code(&mut self) -> Result<i32, String> {
+        let rest = self.rest();
+        let digits = rest.strip_prefix('-').unwrap_or(rest);
+        let len = rest.len() - digits.len()
+            + digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len());
+        match rest[..len].parse() {
+            Ok(code) => {
+                self.position += len;
+                Ok(code)
+            }
+            Err(_) => Err(self.error("expected an exit code")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let pattern: CodePattern = "1,3..5,75..=78".parse().unwrap();
+        for code in [1, 3, 4, 75, 78] {
+            assert!(pattern.matches(code), "{}", code);
+        }
+        for code in [0, 2, 5, 74, 79] {
+            assert!(!pattern.matches(code), "{}", code);
+        }
+        assert_eq!(pattern.to_string(), "1,3..5,75..=78");
+        assert!("-1".parse::<CodePattern>().unwrap().matches(-1));
+    }
+
+    #[test]
+    fn test_negation_and_wildcard() {
+        let pattern: CodePattern = "!0..1".parse().unwrap();
+        assert!(!pattern.matches(0));
+        assert!(pattern.matches(1));
+        assert!(pattern.matches(-1));
+
+        let pattern: CodePattern = "*".parse().unwrap();
+        assert!(pattern.matches(0));
+        assert!(pattern.matches(i32::MAX));
+        assert!(!"!*".parse::<CodePattern>().unwrap().matches(75));
+        assert!(!"!1,75".parse::<CodePattern>().unwrap().matches(75));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "".parse::<CodePattern>(),
+            Err(String::from("expected an exit code at position 1 of ''"))
+        );
+        assert_eq!(
+            "1,x".parse::<CodePattern>(),
+            Err(String::from("expected an exit code at position 3 of '1,x'"))
+        );
+        assert_eq!(
+            "3..".parse::<CodePattern>(),
+            Err(String::from("expected an exit code at position 4 of '3..'"))
+        );
+        assert_eq!(
+            "!!1".parse::<CodePattern>(),
+            Err(String::from("expected an exit code at position 2 of '!!1'"))
+        );
+        assert_eq!(
+            "1,!2".parse::<CodePattern>(),
+            Err(String::from(
+                "expected an exit code at position 3 of '1,!2'"
+            ))
+        );
+        assert_eq!(
+            "1 2".parse::<CodePattern>(),
+            Err(String::from(
+                "expected ',' or the end of the pattern at position 2 of '1 2'"
+            ))
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use log::{info, warn};
+
+use crate::status::SharedStatus;
+
+/// A Unix socket through which a running `attempt` can be queried and steered. Each line sent to
+/// it is a command, answered with a line of JSON:
+///
+/// - `status` reports where `attempt` is in its schedule.
+/// - `skip` cuts the current wait short, retrying immediately.
+/// - `cancel` stops any further attempts, letting the running one finish, like a first SIGINT.
+/// - `extend N` allows N more attempts than the schedule did.
+///
+/// Successful commands are answered with `{"ok":true,...}`, and others with
+/// `{"ok":false,"error":"..."}`. The socket is removed once `attempt` is done with it.
+pub(crate) struct ControlSocket {
+    path: PathBuf,
+}
+
+/// What the commands sent over the socket act on.
+#[derive(Clone)]
+pub(crate) struct Controls {
+    pub status: SharedStatus,
+    pub interrupted: Arc<AtomicBool>,
+    pub skip_requested: Arc<AtomicBool>,
+}
+
+impl ControlSocket {
+    /// Listen on the given path, answering commands on a background thread. A socket left behind
+    /// by an earlier `attempt` is replaced, but one which is still in use is an error, as is
+    /// anything at the path which isn't a socket.
+    pub(crate) fn bind(path: &Path, controls: Controls) -> Result<Self, io::Error> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} already exists and isn't a socket, so it can't be the control socket",
+                        path.display()
+                    ),
+                ));
+            }
+            Ok(_) if UnixStream::connect(path).is_ok() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("The control socket {} is already in use", path.display()),
+                ));
+            }
+            Ok(_) => fs::remove_file(path)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to create the control socket {}: {}",
+                    path.display(),
+                    e
+                ),
+            )
+        })?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let controls = controls.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, &controls) {
+                        warn!("Control socket connection failed: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve(stream: UnixStream, controls: &Controls) -> Result<(), io::Error> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match run(&line, controls) {
+            Ok(fields) => format!("{{\"ok\":true{}}}", fields),
+            Err(error) => format!(
+                "{{\"ok\":false,\"error\":{}}}",
+                crate::report::json_string(&error)
+            ),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+
+    Ok(())
+}
+
+/// Carry out a command, returning the fields to add to the reply, each preceded by a comma.
+fn run(command: &str, controls: &Controls) -> Result<String, String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words[..] {
+        ["status"] => Ok(format!(",{}", controls.status.lock().unwrap().to_json())),
+        ["skip"] => {
+            if !controls.status.lock().unwrap().is_waiting() {
+                return Err(String::from("attempt isn't waiting"));
+            }
+            info!("Skipping the wait, as asked over the control socket");
+            controls.skip_requested.store(true, Ordering::SeqCst);
+            Ok(String::new())
+        }
+        ["cancel"] => {
+            if !controls.interrupted.swap(true, Ordering::SeqCst) {
+                warn!("Cancelled over the control socket, no further attempts will be made.");
+            }
+            Ok(String::new())
+        }
+        ["extend", attempts] => {
+            let attempts = match attempts.parse() {
+                Ok(attempts) if attempts > 0 => attempts,
+                _ => return Err(format!("{} is not a positive number of attempts", attempts)),
+            };
+            match controls.status.lock().unwrap().extend(attempts) {
+                Some(limit) => {
+                    info!(
+                        "Allowing up to {} attempts, as asked over the control socket",
+                        limit
+                    );
+                    Ok(format!(",\"attempts\":{}", limit))
+                }
+                None => Err(String::from("there is no limit on attempts to extend")),
+            }
+        }
+        _ => Err(format!(
+            "{} is not one of status, skip, cancel or extend N",
+            command.trim()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::Status;
+    use std::{sync::Mutex, time::Duration};
+
+    fn controls() -> Controls {
+        Controls {
+            status: Arc::new(Mutex::new(Status::new(Some(3), String::from("fixed")))),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            skip_requested: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let controls = controls();
+        assert!(run("skip", &controls).is_err());
+        controls
+            .status
+            .lock()
+            .unwrap()
+            .waiting(Duration::from_secs(60));
+        assert_eq!(run("skip", &controls), Ok(String::new()));
+        assert!(controls.skip_requested.load(Ordering::SeqCst));
+
+        assert_eq!(
+            run("extend 2", &controls),
+            Ok(String::from(",\"attempts\":5"))
+        );
+        assert!(run("extend 0", &controls).is_err());
+        assert!(run("status", &controls).unwrap().contains("\"attempts\":5"));
+
+        assert_eq!(run("cancel", &controls), Ok(String::new()));
+        assert!(controls.interrupted.load(Ordering::SeqCst));
+        assert!(run("restart", &controls).is_err());
+    }
+
+    #[test]
+    fn test_socket() {
+        let path = std::env::temp_dir().join(format!("attempt-test-{}.sock", std::process::id()));
+        let socket = ControlSocket::bind(&path, controls()).unwrap();
+        assert!(ControlSocket::bind(&path, controls()).is_err());
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        writeln!(stream, "extend 1\nbogus").unwrap();
+        let mut replies = BufReader::new(stream).lines();
+        assert_eq!(
+            replies.next().unwrap().unwrap(),
+            "{\"ok\":true,\"attempts\":4}"
+        );
+        assert!(replies
+            .next()
+            .unwrap()
+            .unwrap()
+            .starts_with("{\"ok\":false,\"error\":"));
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_keeps_other_files() {
+        let path = std::env::temp_dir().join(format!("attempt-test-{}.file", std::process::id()));
+        fs::write(&path, "not a socket").unwrap();
+        assert_eq!(
+            ControlSocket::bind(&path, controls()).err().unwrap().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replaces_stale_socket() {
+        let path = std::env::temp_dir().join(format!("attempt-test-{}.stale", std::process::id()));
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let socket = ControlSocket::bind(&path, controls()).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(socket);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    time::{Duration, SystemTime},
+};
+
+use regex::Regex;
+
+/// What to do about the command dumping core when it crashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CoreDumps {
+    /// Keep the command from dumping core at all.
+    Suppress,
+    /// Let the command dump core as far as the hard limit allows, so that its core dumps can be
+    /// collected.
+    Collect,
+}
+
+impl CoreDumps {
+    /// Arrange for the command's core size limit to be set when it's spawned.
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, command: &mut Command) -> Result<(), io::Error> {
+        use std::os::unix::process::CommandExt;
+
+        let suppress = *self == Self::Suppress;
+        let set_limit = move || {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if !suppress {
+                if unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                limit.rlim_cur = limit.rlim_max;
+            }
+            if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        };
+        unsafe { command.pre_exec(set_limit) };
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(&self, _command: &mut Command) -> Result<(), io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--collect-cores and --no-cores are only supported on Unix",
+        ))
+    }
+}
+
+/// Move the core dumped by the attempt with the given number and process ID, which started at
+/// `started`, into `dir`, returning where it was moved to.
+pub(crate) fn collect_core(
+    dir: &Path,
+    attempt: usize,
+    pid: u32,
+    started: SystemTime,
+) -> Result<PathBuf, String> {
+    let pattern = core_pattern();
+    if let Some(program) = pattern.strip_prefix('|') {
+        return Err(format!(
+            "the system hands core dumps to {}, so they can't be collected",
+            program.split_whitespace().next().unwrap_or(program)
+        ));
+    }
+    // File times can lag slightly behind the clock.
+    let since = started - Duration::from_secs(1);
+    let core = find_core(&pattern, pid, since).ok_or_else(|| {
+        format!(
+            "no core dump matching the pattern {} was found; is the hard limit on the size of \
+             core dumps zero?",
+            pattern
+        )
+    })?;
+
+    let saved = dir.join(format!("core.attempt-{}.{}", attempt, pid));
+    move_file(&core, &saved).map_err(|e| {
+        format!(
+            "failed to move {} to {}: {}",
+            core.display(),
+            saved.display(),
+            e
+        )
+    })?;
+    Ok(saved)
+}
+
+/// Whether the command dumped core when it exited.
+#[cfg(unix)]
+pub(crate) fn dumped_core(status: ExitStatus) -> bool {
+    std::os::unix::process::ExitStatusExt::core_dumped(&status)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn dumped_core(_status: ExitStatus) -> bool {
+    false
+}
+
+/// Where the system writes core dumps, as a pattern like Linux's `core_pattern`.
+#[cfg(target_os = "linux")]
+fn core_pattern() -> String {
+    let pattern = fs::read_to_string("/proc/sys/kernel/core_pattern").map_or_else(
+        |_| String::from("core"),
+        |pattern| pattern.trim().to_owned(),
+    );
+    let uses_pid = fs::read_to_string("/proc/sys/kernel/core_uses_pid")
+        .is_ok_and(|uses_pid| uses_pid.trim() != "0");
+    if uses_pid && !pattern.starts_with('|') && !pattern.contains("%p") {
+        pattern + ".%p"
+    } else {
+        pattern
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn core_pattern() -> String {
+    String::from("/cores/core.%p")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn core_pattern() -> String {
+    String::from("%e.core")
+}
+
+/// Find the newest core dump written since `since` whose path matches `pattern` for the process
+/// `pid`. Specifiers other than the process ID could match anything.
+fn find_core(pattern: &str, pid: u32, since: SystemTime) -> Option<PathBuf> {
+    let (dir, name) = match pattern.rsplit_once('/') {
+        Some(("", name)) => (Path::new("/"), name),
+        Some((dir, name)) => (Path::new(dir), name),
+        None => (Path::new("."), pattern),
+    };
+    let name = name_regex(name, pid);
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_str().is_some_and(|n| name.is_match(n)))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .filter(|(modified, _)| *modified >= since)
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// A regex matching the file names the pattern expands to for the process `pid`.
+fn name_regex(pattern: &str, pid: u32) -> Regex {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+            continue;
+        }
+        match chars.next() {
+            Some('%') => regex.push('%'),
+            Some('p' | 'P') => regex.push_str(&pid.to_string()),
+            _ => regex.push_str(".*"),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).expect("escaped pattern is a valid regex")
+}
+
+/// Move a file, copying it if it's on another filesystem.
+fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_name_regex() {
+        let regex = name_regex("core.%e.%p.%t", 1234);
+        assert!(regex.is_match("core.sh.1234.1662316200"));
+        assert!(!regex.is_match("core.sh.12345.1662316200"));
+        assert!(name_regex("core", 1234).is_match("core"));
+        assert!(name_regex("100%%.%P", 7).is_match("100%.7"));
+        assert!(!name_regex("core", 1234).is_match("score"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_core_dumps() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "ulimit -c"]);
+        CoreDumps::Suppress.apply(&mut command).unwrap();
+        assert_eq!(command.output().unwrap().stdout, b"0\n");
+
+        let dir = env::temp_dir().join(format!("attempt-cores-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut command = Command::new("sh");
+        command.args(["-c", "kill -SEGV $$"]).current_dir(&dir);
+        CoreDumps::Collect.apply(&mut command).unwrap();
+        let started = SystemTime::now() - Duration::from_secs(1);
+        let child = command.spawn().unwrap();
+        let pid = child.id();
+        let status = child.wait_with_output().unwrap().status;
+        // Nothing is dumped if the hard limit is zero, and the pattern may put cores elsewhere.
+        let pattern = core_pattern();
+        if dumped_core(status) && !pattern.starts_with('|') && !pattern.contains('/') {
+            let pattern = format!("{}/{}", dir.display(), pattern);
+            let core = find_core(&pattern, pid, started).unwrap();
+            assert_eq!(core.parent(), Some(dir.as_path()));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
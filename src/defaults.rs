@@ -0,0 +1,559 @@
+use std::{env, ffi::OsString, fs, io, path::PathBuf};
+
+use clap::{Arg, Command, CommandFactory};
+use toml::{value::Table, Value};
+
+use crate::{alias, arguments::ArgumentParser};
+
+/// The subcommands which take the retry options that defaults apply to.
+const STRATEGIES: [&str; 2] = ["fixed", "exponential"];
+
+/// Where the defaults file is kept: `$XDG_CONFIG_HOME/attempt/defaults.toml`, falling back to
+/// `~/.config`.
+pub(crate) fn default_path() -> PathBuf {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".config"));
+    config_home.join("attempt").join("defaults.toml")
+}
+
+/// The table in the defaults file which named schedules are kept in, as `[schedule.NAME]` tables.
+const SCHEDULES: &str = "schedule";
+
+/// The command line arguments with any named schedule and defaults from the defaults file filled
+/// in, or the arguments as they are if there's no defaults file.
+pub(crate) fn with_defaults(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let path = default_path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let in_file = |e| format!("{}: {}", path.display(), e);
+    let args = use_schedule(args, &schedules(&text).map_err(in_file)?).map_err(in_file)?;
+    insert_defaults(args, &parse(&text).map_err(in_file)?).map_err(in_file)
+}
+
+/// Parse a defaults file, where each key is the long name of an option. Flags take a boolean,
+/// options which may be repeated take a value or an array of values, and other options take a
+/// single value. Named schedules and commands saved with `attempt save` are kept in the same
+/// file, and are skipped.
+fn parse(text: &str) -> Result<Vec<(String, Value)>, String> {
+    match text.parse::<Value>().map_err(|e| e.to_string())? {
+        Value::Table(table) => Ok(table
+            .into_iter()
+            .filter(|(name, _)| name != alias::SECTION && name != SCHEDULES)
+            .collect()),
+        _ => Err(String::from("expected a table of options")),
+    }
+}
+
+/// The named schedules in a defaults file, each a table with a strategy and the options to use
+/// with it.
+fn schedules(text: &str) -> Result<Table, String> {
+    match text.parse::<Value>().map_err(|e| e.to_string())? {
+        Value::Table(mut table) => match table.remove(SCHEDULES) {
+            Some(Value::Table(schedules)) => Ok(schedules),
+            Some(_) => Err(format!("{} must be a table", SCHEDULES)),
+            None => Ok(Table::new()),
+        },
+        _ => Err(String::from("expected a table of options")),
+    }
+}
+
+/// Replace `--schedule NAME`, given in place of a strategy subcommand, with the schedule's
+/// strategy followed by its options. Options given on the command line take precedence over the
+/// schedule's, which take precedence over the defaults.
+fn use_schedule(mut args: Vec<OsString>, schedules: &Table) -> Result<Vec<OsString>, String> {
+    let given = given(&args);
+    let Some(at) = find_strategy(&given) else {
+        return Ok(args);
+    };
+    let (name, length) = match given[at].strip_prefix("--schedule=") {
+        Some(name) => (name, 1),
+        None if given[at] == "--schedule" => (
+            given
+                .get(at + 1)
+                .filter(|name| !name.starts_with('-'))
+                .ok_or("--schedule needs the name of a schedule")?
+                .as_str(),
+            2,
+        ),
+        None => return Ok(args),
+    };
+
+    let schedule = schedules
+        .get(name)
+        .and_then(Value::as_table)
+        .ok_or_else(|| format!("there is no schedule named {}", name))?;
+    let strategy = schedule
+        .get("strategy")
+        .and_then(Value::as_str)
+        .filter(|strategy| STRATEGIES.contains(strategy))
+        .ok_or_else(|| {
+            format!(
+                "the schedule {} needs a strategy of fixed or exponential",
+                name
+            )
+        })?;
+    let options: Vec<(String, Value)> = schedule
+        .iter()
+        .filter(|(option, _)| *option != "strategy")
+        .map(|(option, value)| (option.clone(), value.clone()))
+        .collect();
+
+    // The first argument is the program name, which `given` skipped.
+    args.splice(at + 1..at + 1 + length, [OsString::from(strategy)]);
+    insert_defaults(args, &options)
+}
+
+/// Insert an argument for each default right after the strategy subcommand, unless the option
+/// was given on the command line, which always takes precedence.
+fn insert_defaults(
+    mut args: Vec<OsString>,
+    defaults: &[(String, Value)],
+) -> Result<Vec<OsString>, String> {
+    let given = given(&args);
+    let Some(strategy) = find_strategy(&given).filter(|&at| STRATEGIES.contains(&&*given[at]))
+    else {
+        return Ok(args);
+    };
+    let parser = ArgumentParser::command();
+    let subcommand = parser.find_subcommand(&given[strategy]).unwrap();
+    let (options, _) = strategy_options(subcommand, &given, strategy);
+    let options: Vec<&str> = options.into_iter().map(Arg::get_id).collect();
+
+    let mut inserted = Vec::new();
+    for (name, value) in defaults {
+        let argument = subcommand
+            .get_arguments()
+            .find(|argument| argument.get_long() == Some(name))
+            .ok_or_else(|| format!("--{} is not an option", name))?;
+        if options.contains(&argument.get_id()) {
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Boolean(true) => inserted.push(format!("--{}", name)),
+                Value::Boolean(false) => (),
+                Value::String(text) => inserted.push(format!("--{}={}", name, text)),
+                Value::Integer(number) => inserted.push(format!("--{}={}", name, number)),
+                Value::Float(number) => inserted.push(format!("--{}={}", name, number)),
+                _ => return Err(format!("the value of {} is not supported", name)),
+            }
+        }
+    }
+
+    // The first argument is the program name, which `given` skipped.
+    let at = strategy + 2;
+    args.splice(at..at, inserted.into_iter().map(OsString::from));
+    Ok(args)
+}
+
+/// Whether the command given to the strategy subcommand follows `--`, rather than starting right
+/// after the strategy's options.
+pub(crate) fn command_follows_separator(args: &[OsString]) -> bool {
+    let given = given(args);
+    let Some(strategy) = find_strategy(&given) else {
+        return false;
+    };
+    let parser = ArgumentParser::command();
+    let Some(subcommand) = parser.find_subcommand(&given[strategy]) else {
+        return false;
+    };
+    let (_, end) = strategy_options(subcommand, &given, strategy);
+    given.get(end).is_some_and(|arg| arg == "--")
+}
+
+/// The options given to the strategy subcommand at `given[strategy]`, and where the arguments
+/// after them start.
+fn strategy_options<'a, 'help>(
+    subcommand: &'a Command<'help>,
+    given: &[String],
+    strategy: usize,
+) -> (Vec<&'a Arg<'help>>, usize) {
+    let mut options = Vec::new();
+    let mut i = strategy + 1;
+    while let Some((arguments, next)) = options_at(subcommand, given, i) {
+        options.extend(arguments);
+        i = next;
+    }
+    (options, i)
+}
+
+/// The arguments after the program name.
+fn given(args: &[OsString]) -> Vec<String> {
+    args.iter()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Where the arguments which stand on their own are, rather than being the value of an option,
+/// among `attempt`'s own arguments. The arguments are followed the way the parser would, up to
+/// `--` or the start of the command, whose first argument is included as it can't be told from a
+/// positional argument until it's known not to be one.
+pub(crate) fn standalone_arguments(args: &[OsString]) -> Vec<usize> {
+    let given = given(args);
+    let parser = ArgumentParser::command();
+    let mut command = &parser;
+    let mut standalone = Vec::new();
+    let mut i = 0;
+    while i < given.len() && given[i] != "--" {
+        // The first argument is the program name, which `given` skipped.
+        standalone.push(i + 1);
+        if let Some(subcommand) = command
+            .has_subcommands()
+            .then(|| command.find_subcommand(&given[i]))
+            .flatten()
+        {
+            command = subcommand;
+            i += 1;
+            continue;
+        }
+        match options_at(command, &given, i) {
+            Some((_, next)) => i = next,
+            None if command.has_subcommands() => i += 1,
+            None => break,
+        }
+    }
+    standalone
+}
+
+/// Where the strategy subcommand, or `--schedule` in its place, is among the arguments. The
+/// arguments are followed the way the parser would, so that nothing from the command onwards,
+/// nor the value of an option, is taken for the strategy.
+fn find_strategy(given: &[String]) -> Option<usize> {
+    let parser = ArgumentParser::command();
+    let mut command = &parser;
+    let mut i = 0;
+    while i < given.len() && given[i] != "--" {
+        let arg = given[i].as_str();
+        if command.has_subcommands() {
+            if STRATEGIES.contains(&arg) || arg == "--schedule" || arg.starts_with("--schedule=") {
+                return Some(i);
+            }
+            if let Some(subcommand) = command.find_subcommand(arg) {
+                command = subcommand;
+                i += 1;
+                continue;
+            }
+        }
+        match options_at(command, given, i) {
+            Some((_, next)) => i = next,
+            // A positional argument comes before the strategy, but the command comes after it.
+            None if command.has_subcommands() => i += 1,
+            None => return None,
+        }
+    }
+    None
+}
+
+/// The options of `command` given by `given[i]`, and where the next argument after them and
+/// their values is, or `None` if it's the start of the command, a positional argument or `--`.
+fn options_at<'a, 'help>(
+    command: &'a Command<'help>,
+    given: &[String],
+    i: usize,
+) -> Option<(Vec<&'a Arg<'help>>, usize)> {
+    let arg = given.get(i).filter(|arg| *arg != "--")?;
+    // A value given separately follows the option, unless it must be given with `=`.
+    let separate_value =
+        |argument: &Arg| argument.is_takes_value_set() && !argument.is_require_equals_set();
+    if let Some(long) = arg.strip_prefix("--") {
+        let (name, value) = match long.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (long, false),
+        };
+        let argument = command
+            .get_arguments()
+            .find(|argument| argument.get_long() == Some(name));
+        let next = match argument {
+            Some(argument) if !value && separate_value(argument) => i + 2,
+            _ => i + 1,
+        };
+        return Some((argument.into_iter().collect(), next));
+    }
+
+    let shorts = arg.strip_prefix('-').filter(|shorts| !shorts.is_empty())?;
+    let mut arguments = Vec::new();
+    for (at, short) in shorts.char_indices() {
+        let Some(argument) = command
+            .get_arguments()
+            .find(|argument| argument.get_short() == Some(short))
+        else {
+            break;
+        };
+        arguments.push(argument);
+        if argument.is_takes_value_set() {
+            // The rest of the argument is the option's value, if there is any.
+            let rest = &shorts[at + short.len_utf8()..];
+            let next = if rest.is_empty() && separate_value(argument) {
+                i + 2
+            } else {
+                i + 1
+            };
+            return Some((arguments, next));
+        }
+    }
+    Some((arguments, i + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arguments::Mode;
+    use clap::Parser;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_insert_defaults() {
+        let defaults = parse(
+            r#"
+            jitter = 0.5
+            attempts = 5
+            explain = true
+            no-progress = false
+            retry-if-output-matches = ["timed out", "try again"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            insert_defaults(args(&["attempt", "fixed", "-w", "2", "make"]), &defaults).unwrap(),
+            args(&[
+                "attempt",
+                "fixed",
+                "--attempts=5",
+                "--explain",
+                "--jitter=0.5",
+                "--retry-if-output-matches=timed out",
+                "--retry-if-output-matches=try again",
+                "-w",
+                "2",
+                "make"
+            ])
+        );
+
+        // Options given on the command line take precedence, in either form.
+        assert_eq!(
+            insert_defaults(
+                args(&[
+                    "attempt",
+                    "fixed",
+                    "-a3",
+                    "--jitter=1",
+                    "--retry-if-output-matches",
+                    "x",
+                    "--explain",
+                    "make"
+                ]),
+                &defaults
+            )
+            .unwrap(),
+            args(&[
+                "attempt",
+                "fixed",
+                "-a3",
+                "--jitter=1",
+                "--retry-if-output-matches",
+                "x",
+                "--explain",
+                "make"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_defaults_follow_the_strategy() {
+        let defaults = parse("attempts = 5").unwrap();
+        assert_eq!(
+            insert_defaults(
+                args(&["attempt", "wait-for", "tcp://db:5432", "exponential"]),
+                &defaults
+            )
+            .unwrap(),
+            args(&[
+                "attempt",
+                "wait-for",
+                "tcp://db:5432",
+                "exponential",
+                "--attempts=5"
+            ])
+        );
+        assert_eq!(
+            insert_defaults(args(&["attempt", "bench", "make"]), &defaults).unwrap(),
+            args(&["attempt", "bench", "make"])
+        );
+    }
+
+    #[test]
+    fn test_options_of_the_command() {
+        let defaults = parse("attempts = 5\njitter = 0.5").unwrap();
+        // Without --, the command's own options aren't taken for attempt's.
+        assert_eq!(
+            insert_defaults(args(&["attempt", "fixed", "curl", "-a", "-j"]), &defaults).unwrap(),
+            args(&[
+                "attempt",
+                "fixed",
+                "--attempts=5",
+                "--jitter=0.5",
+                "curl",
+                "-a",
+                "-j"
+            ])
+        );
+        // Nor are the values of attempt's options.
+        assert_eq!(
+            insert_defaults(
+                args(&["attempt", "fixed", "-w", "-1", "-a2", "make", "--jitter=1"]),
+                &defaults
+            )
+            .unwrap(),
+            args(&[
+                "attempt",
+                "fixed",
+                "--jitter=0.5",
+                "-w",
+                "-1",
+                "-a2",
+                "make",
+                "--jitter=1"
+            ])
+        );
+        assert_eq!(
+            insert_defaults(args(&["attempt", "--", "fixed", "make"]), &defaults).unwrap(),
+            args(&["attempt", "--", "fixed", "make"])
+        );
+    }
+
+    #[test]
+    fn test_command_follows_separator() {
+        assert!(command_follows_separator(&args(&[
+            "attempt", "fixed", "-a", "1", "--", "echo", "--wait"
+        ])));
+        assert!(command_follows_separator(&args(&[
+            "attempt",
+            "wait-for",
+            "tcp://db:5432",
+            "fixed",
+            "--"
+        ])));
+        assert!(!command_follows_separator(&args(&[
+            "attempt", "fixed", "-a", "1", "echo", "--", "--wait"
+        ])));
+        assert!(!command_follows_separator(&args(&[
+            "attempt", "bench", "--", "true"
+        ])));
+    }
+
+    #[test]
+    fn test_saved_commands() {
+        let defaults =
+            parse("attempts = 5\n\n[alias.build]\nargs = [\"fixed\", \"make\"]\n").unwrap();
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(
+            insert_defaults(
+                args(&["attempt", "save", "build", "fixed", "make"]),
+                &defaults
+            )
+            .unwrap(),
+            args(&["attempt", "save", "build", "fixed", "make"])
+        );
+    }
+
+    #[test]
+    fn test_schedules() {
+        let text = r#"
+            attempts = 5
+            jitter = 0.5
+
+            [schedule.slow-api]
+            strategy = "exponential"
+            base = 3
+            wait-max = "10m"
+            attempts = 10
+            "#;
+        let schedules = schedules(text).unwrap();
+        let defaults = parse(text).unwrap();
+        let expand = |arguments: &[&str]| {
+            insert_defaults(use_schedule(args(arguments), &schedules)?, &defaults)
+        };
+        assert_eq!(
+            expand(&[
+                "attempt",
+                "--schedule",
+                "slow-api",
+                "--base=2",
+                "--",
+                "curl"
+            ])
+            .unwrap(),
+            args(&[
+                "attempt",
+                "exponential",
+                "--jitter=0.5",
+                "--attempts=10",
+                "--wait-max=10m",
+                "--base=2",
+                "--",
+                "curl"
+            ])
+        );
+        assert_eq!(
+            expand(&[
+                "attempt",
+                "pipeline",
+                "a | b",
+                "--schedule=slow-api",
+                "-a",
+                "3"
+            ])
+            .unwrap(),
+            args(&[
+                "attempt",
+                "pipeline",
+                "a | b",
+                "exponential",
+                "--jitter=0.5",
+                "--base=3",
+                "--wait-max=10m",
+                "-a",
+                "3"
+            ])
+        );
+        let parsed = ArgumentParser::try_parse_from(
+            expand(&["attempt", "--schedule", "slow-api", "--", "curl"]).unwrap(),
+        )
+        .unwrap();
+        let Mode::Retry(strategy) = parsed.mode else {
+            panic!("expected a retry");
+        };
+        assert_eq!(strategy.common().wait_params.wait_max, Some(600.0));
+        assert!(expand(&["attempt", "--schedule", "fast", "curl"]).is_err());
+        assert!(expand(&["attempt", "--schedule"]).is_err());
+        assert!(use_schedule(
+            args(&["attempt", "--schedule=a", "curl"]),
+            &super::schedules("[schedule.a]\nbase = 3\n").unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let defaults = parse("no-such-option = true").unwrap();
+        assert_eq!(
+            insert_defaults(args(&["attempt", "fixed", "make"]), &defaults),
+            Err(String::from("--no-such-option is not an option"))
+        );
+        assert!(parse("attempts = ").is_err());
+    }
+}
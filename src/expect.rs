@@ -0,0 +1,94 @@
+use std::{fs, path::Path};
+
+/// Compare what the command wrote to stdout with the contents of the reference file at `path`,
+/// returning where they first differ, if they do. Line by line, differences in line endings and
+/// trailing whitespace are ignored.
+pub(crate) fn compare_output(path: &Path, stdout: &[u8], linewise: bool) -> Option<String> {
+    let expected = match fs::read(path) {
+        Ok(expected) => expected,
+        Err(e) => return Some(format!("failed to read {}: {}", path.display(), e)),
+    };
+
+    if linewise {
+        let mut expected = lines(&expected);
+        let mut actual = lines(stdout);
+        let mut number = 1;
+        loop {
+            match (expected.next(), actual.next()) {
+                (None, None) => return None,
+                (expected, actual) if expected == actual => number += 1,
+                _ => {
+                    return Some(format!(
+                        "stdout differs from {} at line {}",
+                        path.display(),
+                        number
+                    ))
+                }
+            }
+        }
+    } else if expected != stdout {
+        let offset = expected
+            .iter()
+            .zip(stdout)
+            .take_while(|(expected, actual)| expected == actual)
+            .count();
+        Some(format!(
+            "stdout differs from {} at byte {}",
+            path.display(),
+            offset
+        ))
+    } else {
+        None
+    }
+}
+
+/// The lines of `bytes`, without their line endings or trailing whitespace.
+fn lines(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    bytes
+        .split(|&b| b == b'\n')
+        .filter(move |_| !bytes.is_empty())
+        .map(<[u8]>::trim_ascii_end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(
+            lines(b"one\r\ntwo  \n\nthree").collect::<Vec<_>>(),
+            [&b"one"[..], b"two", b"", b"three"]
+        );
+        assert_eq!(lines(b"one\n").count(), 1);
+        assert_eq!(lines(b"").count(), 0);
+    }
+
+    #[test]
+    fn test_compare_output() {
+        let path = env::temp_dir().join(format!("attempt-expect-{}", std::process::id()));
+        fs::write(&path, "id,name\n1,a\n2,b\n").unwrap();
+
+        assert_eq!(compare_output(&path, b"id,name\n1,a\n2,b\n", false), None);
+        assert_eq!(
+            compare_output(&path, b"id,name\n1,a\n", false),
+            Some(format!("stdout differs from {} at byte 12", path.display()))
+        );
+        assert_eq!(compare_output(&path, b"id,name\r\n1,a \r\n2,b", true), None);
+        assert_eq!(
+            compare_output(&path, b"id,name\n1,a\n2,c\n", true),
+            Some(format!("stdout differs from {} at line 3", path.display()))
+        );
+        assert_eq!(
+            compare_output(&path, b"id,name\n1,a\n2,b\n3,c\n", true),
+            Some(format!("stdout differs from {} at line 4", path.display()))
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(compare_output(&path, b"", false)
+            .unwrap()
+            .starts_with("failed to read"));
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::info;
+
+use crate::{
+    util::{build_command, format_duration, format_size, parse_size, shell_command},
+    window::TimeWindow,
+};
+
+/// How often unmet requirements are checked again.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How often a deferred attempt checks whether `attempt` has been interrupted.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Something the host must provide before an attempt is worth making. While a requirement is
+/// unmet attempts are deferred, rather than spent on failures the command can do nothing about.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Requirement {
+    /// At least this many bytes free on the filesystem holding the path.
+    FreeDisk { bytes: u64, path: PathBuf },
+    /// At least this many bytes of memory available.
+    FreeMemory { bytes: u64 },
+    /// A shell command which exits successfully.
+    Precheck { command: String },
+    /// A default route, so that there's some way to reach the network.
+    Network,
+    /// A 1-minute load average no higher than this.
+    MaxLoad { load: f64 },
+    /// A time of day within this window.
+    Window(TimeWindow),
+}
+
+impl Requirement {
+    /// Why the requirement isn't met, or `None` if it is. Requirements which can't be checked on
+    /// this platform are taken to be met.
+    fn unmet(&self) -> Option<String> {
+        match self {
+            Self::FreeDisk { bytes, path } => {
+                let free = free_disk(path)?;
+                (free < *bytes).then(|| {
+                    format!(
+                        "{} free on {}, need {}",
+                        format_size(free),
+                        path.display(),
+                        format_size(*bytes)
+                    )
+                })
+            }
+            Self::FreeMemory { bytes } => {
+                let free = free_memory()?;
+                (free < *bytes).then(|| {
+                    format!(
+                        "{} of memory available, need {}",
+                        format_size(free),
+                        format_size(*bytes)
+                    )
+                })
+            }
+            Self::Precheck { command } => match build_command(&shell_command(command))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+            {
+                Ok(status) if status.success() => None,
+                Ok(status) => Some(format!("precheck `{}` failed ({})", command, status)),
+                Err(e) => Some(format!("precheck `{}` failed to run: {}", command, e)),
+            },
+            Self::Network => {
+                (!network_is_up()?).then(|| String::from("there's no default network route"))
+            }
+            Self::MaxLoad { load } => {
+                let current = load_average()?;
+                (current > *load).then(|| format!("load average is {:.2}, above {}", current, load))
+            }
+            Self::Window(window) => window.opens_in(SystemTime::now()).map(|minutes| {
+                format!(
+                    "outside of the window {}, which opens in {}",
+                    window,
+                    format_duration(Duration::from_secs(u64::from(minutes) * 60))
+                )
+            }),
+        }
+    }
+}
+
+/// Parse a free disk requirement given as SIZE[:PATH], where PATH defaults to the current
+/// directory.
+pub(crate) fn parse_free_disk(text: &str) -> Result<Requirement, String> {
+    let (size, path) = text.split_once(':').unwrap_or((text, "."));
+    Ok(Requirement::FreeDisk {
+        bytes: parse_size(size).ok_or_else(|| format!("{} is not a size", size))?,
+        path: PathBuf::from(path),
+    })
+}
+
+/// Parse a free memory requirement given as SIZE.
+pub(crate) fn parse_free_memory(text: &str) -> Result<Requirement, String> {
+    Ok(Requirement::FreeMemory {
+        bytes: parse_size(text).ok_or_else(|| format!("{} is not a size", text))?,
+    })
+}
+
+/// Block until every requirement is met, checking again periodically. Returns false if
+/// `attempt` was interrupted while waiting.
+pub(crate) fn wait_until_met(requirements: &[Requirement], interrupted: &AtomicBool) -> bool {
+    let mut deferred_since: Option<Instant> = None;
+    loop {
+        let unmet: Vec<String> = requirements.iter().filter_map(Requirement::unmet).collect();
+        if unmet.is_empty() {
+            if let Some(since) = deferred_since {
+                info!(
+                    "Requirements met after deferring for {}, continuing",
+                    format_duration(since.elapsed())
+                );
+            }
+            return true;
+        }
+        if deferred_since.is_none() {
+            info!("Deferring the attempt: {}", unmet.join("; "));
+            deferred_since = Some(Instant::now());
+        }
+
+        for _ in 0..(RECHECK_INTERVAL.as_millis() / INTERRUPT_POLL_INTERVAL.as_millis()) {
+            if interrupted.load(Ordering::SeqCst) {
+                return false;
+            }
+            thread::sleep(INTERRUPT_POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn free_disk(path: &std::path::Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_disk(_path: &std::path::Path) -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn free_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kilobytes: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn free_memory() -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn load_average() -> Option<f64> {
+    let mut load = [0.0];
+    (unsafe { libc::getloadavg(load.as_mut_ptr(), 1) } == 1).then_some(load[0])
+}
+
+#[cfg(not(unix))]
+fn load_average() -> Option<f64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn network_is_up() -> Option<bool> {
+    let ipv4 = std::fs::read_to_string("/proc/net/route").ok()?;
+    let ipv6 = std::fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+    Some(has_default_route(&ipv4, &ipv6))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn network_is_up() -> Option<bool> {
+    None
+}
+
+/// Whether either routing table, in the format of /proc/net/route and /proc/net/ipv6_route, has
+/// a usable default route.
+#[cfg_attr(not(target_os = "linux"), allow(unused))]
+fn has_default_route(ipv4: &str, ipv6: &str) -> bool {
+    const RTF_UP: u32 = 0x1;
+    const RTF_REJECT: u32 = 0x200;
+    let usable = |flags: &str| {
+        u32::from_str_radix(flags, 16)
+            .is_ok_and(|flags| flags & RTF_UP != 0 && flags & RTF_REJECT == 0)
+    };
+
+    let ipv4_default = ipv4.lines().skip(1).any(|route| {
+        let fields: Vec<&str> = route.split_whitespace().collect();
+        fields.len() > 7 && fields[1] == "00000000" && fields[7] == "00000000" && usable(fields[3])
+    });
+    let ipv6_default = ipv6.lines().any(|route| {
+        let fields: Vec<&str> = route.split_whitespace().collect();
+        fields.len() > 9
+            && fields[0].bytes().all(|b| b == b'0')
+            && fields[1] == "00"
+            && fields[9] != "lo"
+            && usable(fields[8])
+    });
+
+    ipv4_default || ipv6_default
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_free_disk() {
+        assert_eq!(
+            parse_free_disk("10G:/var/lib"),
+            Ok(Requirement::FreeDisk {
+                bytes: 10 * 1024 * 1024 * 1024,
+                path: PathBuf::from("/var/lib")
+            })
+        );
+        assert_eq!(
+            parse_free_disk("512M"),
+            Ok(Requirement::FreeDisk {
+                bytes: 512 * 1024 * 1024,
+                path: PathBuf::from(".")
+            })
+        );
+        assert!(parse_free_disk("plenty:/var/lib").is_err());
+        assert_eq!(
+            parse_free_memory("1G"),
+            Ok(Requirement::FreeMemory {
+                bytes: 1024 * 1024 * 1024
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_free_disk() {
+        let met = Requirement::FreeDisk {
+            bytes: 0,
+            path: PathBuf::from("/"),
+        };
+        assert_eq!(met.unmet(), None);
+
+        let unmet = Requirement::FreeDisk {
+            bytes: u64::MAX,
+            path: PathBuf::from("/"),
+        };
+        assert!(unmet.unmet().unwrap().contains("free on /"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_precheck() {
+        let passing = Requirement::Precheck {
+            command: String::from("test -d /"),
+        };
+        assert_eq!(passing.unmet(), None);
+
+        let failing = Requirement::Precheck {
+            command: String::from("exit 3"),
+        };
+        assert!(failing
+            .unmet()
+            .unwrap()
+            .starts_with("precheck `exit 3` failed"));
+    }
+
+    #[test]
+    fn test_has_default_route() {
+        const IPV4_HEADER: &str =
+            "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\tMTU\tWindow\tIRTT\n";
+        let ipv4_default = "eth0\t00000000\t010200C0\t0003\t0\t0\t0\t00000000\t0\t0\t0\n";
+        let ipv4_local = "eth0\t000200C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n";
+        let ipv6_default = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+                            fd000000000000000000000000000001 00000400 00000001 00000000 00000003 eth0\n";
+        let ipv6_unreachable = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+                                00000000000000000000000000000000 ffffffff 00000001 00000000 00200200 lo\n";
+
+        assert!(has_default_route(
+            &format!("{}{}{}", IPV4_HEADER, ipv4_default, ipv4_local),
+            ""
+        ));
+        assert!(!has_default_route(
+            &format!("{}{}", IPV4_HEADER, ipv4_local),
+            ipv6_unreachable
+        ));
+        assert!(has_default_route(IPV4_HEADER, ipv6_default));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_max_load() {
+        assert_eq!(Requirement::MaxLoad { load: f64::MAX }.unmet(), None);
+        assert!(Requirement::MaxLoad { load: -1.0 }
+            .unmet()
+            .unwrap()
+            .starts_with("load average is "));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_interrupt_ends_deferral() {
+        let unmet = Requirement::FreeDisk {
+            bytes: u64::MAX,
+            path: PathBuf::from("/"),
+        };
+        assert!(!wait_until_met(&[unmet], &AtomicBool::new(true)));
+    }
+}
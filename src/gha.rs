@@ -0,0 +1,36 @@
+/// Start a collapsible section of a GitHub Actions log, which lasts until `end_group`. Like the
+/// other workflow commands, this is written to stdout, where Actions picks it out of the step's
+/// output.
+pub(crate) fn group(title: &str) {
+    println!("::group::{}", escape(title));
+}
+
+pub(crate) fn end_group() {
+    println!("::endgroup::");
+}
+
+pub(crate) fn warning(message: &str) {
+    println!("::warning::{}", escape(message));
+}
+
+pub(crate) fn error(message: &str) {
+    println!("::error::{}", escape(message));
+}
+
+/// Escape a message so that it stays on one line and can't be mistaken for the end of a command.
+fn escape(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("100% done\r\nnext"), "100%25 done%0D%0Anext");
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    process::Stdio,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::warn;
+
+use crate::util::{build_command, shell_command};
+
+/// How long an attempt has to exit after being asked to, once its health check has failed,
+/// before it's killed outright.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+/// How often the check is run unless --health-interval says otherwise.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A shell command run periodically while an attempt of the command is running, which kills the
+/// attempt if it fails, so that a command which is stuck but still alive gets retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HealthCheck {
+    command: String,
+    interval: Duration,
+}
+
+impl HealthCheck {
+    pub(crate) fn new(command: String, interval: Option<Duration>) -> Self {
+        Self {
+            command,
+            interval: interval.unwrap_or(DEFAULT_INTERVAL),
+        }
+    }
+
+    /// Start checking on the attempt with the given process ID, until it's finished.
+    pub(crate) fn monitor(&self, pid: u32) -> HealthMonitor {
+        let finished = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let check = self.clone();
+            let finished = Arc::clone(&finished);
+            thread::spawn(move || check.watch(pid, &finished))
+        };
+
+        HealthMonitor { finished, thread }
+    }
+
+    /// Run the check every interval until the attempt is finished, returning why it was killed
+    /// if the check failed.
+    fn watch(&self, pid: u32, finished: &(Mutex<bool>, Condvar)) -> Option<String> {
+        let (lock, condvar) = finished;
+        loop {
+            let (done, _) = condvar
+                .wait_timeout_while(lock.lock().unwrap(), self.interval, |done| !*done)
+                .unwrap();
+            if *done {
+                return None;
+            }
+            drop(done);
+
+            let failure = match build_command(&shell_command(&self.command))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+            {
+                Ok(status) if status.success() => continue,
+                Ok(status) => format!("health check `{}` failed ({})", self.command, status),
+                Err(e) => format!("health check `{}` failed to run: {}", self.command, e),
+            };
+            warn!("Stopping the command, as its {}", failure);
+
+            let done = lock.lock().unwrap();
+            if *done {
+                return None;
+            }
+            terminate(pid, false);
+            let (done, _) = condvar
+                .wait_timeout_while(done, KILL_GRACE, |done| !*done)
+                .unwrap();
+            if !*done {
+                terminate(pid, true);
+            }
+            return Some(failure);
+        }
+    }
+}
+
+/// The health checks of a running attempt.
+pub(crate) struct HealthMonitor {
+    finished: Arc<(Mutex<bool>, Condvar)>,
+    thread: JoinHandle<Option<String>>,
+}
+
+impl HealthMonitor {
+    /// Stop checking, once the attempt has exited, returning why it was killed if a check failed.
+    pub(crate) fn finish(self) -> Option<String> {
+        let (lock, condvar) = &*self.finished;
+        *lock.lock().unwrap() = true;
+        condvar.notify_all();
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32, kill: bool) {
+    let signal = if kill { libc::SIGKILL } else { libc::SIGTERM };
+    unsafe { libc::kill(pid as libc::pid_t, signal) };
+}
+
+/// Only Unix can stop a running attempt, so failed checks are only logged elsewhere.
+#[cfg(not(unix))]
+fn terminate(_pid: u32, _kill: bool) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{process::Command, time::Instant};
+
+    fn check(command: &str) -> HealthCheck {
+        HealthCheck::new(String::from(command), Some(Duration::from_millis(100)))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_check_kills_attempt() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let start = Instant::now();
+        let monitor = check("exit 1").monitor(child.id());
+        let status = child.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!status.success());
+        assert_eq!(
+            monitor.finish(),
+            Some(String::from(
+                "health check `exit 1` failed (exit status: 1)"
+            ))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_passing_check() {
+        let mut child = Command::new("sleep").arg("0.5").spawn().unwrap();
+        let monitor = check("true").monitor(child.id());
+        assert!(child.wait().unwrap().success());
+        assert_eq!(monitor.finish(), None);
+    }
+}
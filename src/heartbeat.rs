@@ -0,0 +1,93 @@
+use std::{
+    cell::Cell,
+    fs::File,
+    io,
+    path::PathBuf,
+    time::{Instant, SystemTime},
+};
+
+use log::warn;
+
+/// A file whose modification time shows that `attempt` is still alive, so that external watchdogs
+/// can tell a long backoff from a hang. It's touched as each attempt starts and while waiting.
+#[derive(Debug)]
+pub(crate) struct HeartbeatFile {
+    path: PathBuf,
+    /// When the file was last touched, so that waits don't touch it more often than needed.
+    touched: Cell<Option<Instant>>,
+    /// Whether a failure to touch the file has been reported, so it's only reported once.
+    warned: Cell<bool>,
+}
+
+impl HeartbeatFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            touched: Cell::new(None),
+            warned: Cell::new(false),
+        }
+    }
+
+    /// Set the file's modification time to now, creating it if it doesn't exist.
+    pub(crate) fn touch(&self) {
+        let result = File::options()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        self.touched.set(Some(Instant::now()));
+        if let Err(e) = result {
+            self.warn(e);
+        }
+    }
+
+    /// Touch the file unless it was touched within the last second.
+    pub(crate) fn touch_if_stale(&self) {
+        if self
+            .touched
+            .get()
+            .is_none_or(|touched| touched.elapsed().as_secs() >= 1)
+        {
+            self.touch();
+        }
+    }
+
+    fn warn(&self, e: io::Error) {
+        if !self.warned.replace(true) {
+            warn!(
+                "Failed to touch the heartbeat file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn test_touch() {
+        let path = std::env::temp_dir().join(format!("attempt-heartbeat-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let heartbeat = HeartbeatFile::new(path.clone());
+
+        heartbeat.touch();
+        let first = fs::metadata(&path).unwrap().modified().unwrap();
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(first - Duration::from_secs(60))
+            .unwrap();
+        // Touched too recently to be touched again.
+        heartbeat.touch_if_stale();
+        assert!(fs::metadata(&path).unwrap().modified().unwrap() < first);
+        heartbeat.touch();
+        assert!(fs::metadata(&path).unwrap().modified().unwrap() >= first);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use rusqlite::{params, Connection, TransactionBehavior};
+
+use crate::{
+    arguments::HistoryArguments,
+    report::{signal, Report},
+    util::{format_duration, format_timestamp},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        command TEXT NOT NULL,
+        started TEXT NOT NULL,
+        duration_secs REAL NOT NULL,
+        outcome TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        attempts INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS attempts (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        number INTEGER NOT NULL,
+        started TEXT NOT NULL,
+        duration_secs REAL NOT NULL,
+        exit_code INTEGER,
+        signal INTEGER,
+        decision TEXT NOT NULL,
+        max_rss_bytes INTEGER,
+        user_cpu_secs REAL,
+        system_cpu_secs REAL,
+        major_page_faults INTEGER,
+        minor_page_faults INTEGER,
+        reason TEXT
+    );
+";
+
+/// Columns added to the attempts table since it was first created, which databases created by
+/// older versions lack.
+const ADDED_ATTEMPT_COLUMNS: [(&str, &str); 6] = [
+    ("max_rss_bytes", "INTEGER"),
+    ("user_cpu_secs", "REAL"),
+    ("system_cpu_secs", "REAL"),
+    ("major_page_faults", "INTEGER"),
+    ("minor_page_faults", "INTEGER"),
+    ("reason", "TEXT"),
+];
+
+/// Where the history database is kept unless --history-db says otherwise:
+/// `$XDG_DATA_HOME/attempt/history.db`, falling back to `~/.local/share`.
+pub(crate) fn default_path() -> PathBuf {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/share")
+        });
+    data_home.join("attempt").join("history.db")
+}
+
+fn open(path: &Path) -> Result<Connection, io::Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut connection = Connection::open(path).map_err(io::Error::other)?;
+    // Other runs, or other commands in a batch, may be recording at the same time.
+    connection
+        .busy_timeout(Duration::from_secs(5))
+        .map_err(io::Error::other)?;
+    connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+    add_missing_columns(&mut connection).map_err(io::Error::other)?;
+    Ok(connection)
+}
+
+fn add_missing_columns(connection: &mut Connection) -> Result<(), rusqlite::Error> {
+    // Take the write lock first, so that runs opening an old database at once don't both add them.
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let existing = transaction
+        .prepare("SELECT name FROM pragma_table_info('attempts')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (name, kind) in ADDED_ATTEMPT_COLUMNS {
+        if !existing.iter().any(|column| column == name) {
+            transaction.execute_batch(&format!(
+                "ALTER TABLE attempts ADD COLUMN {} {}",
+                name, kind
+            ))?;
+        }
+    }
+    transaction.commit()
+}
+
+/// Record a run of `command` which started at `started`, along with each of its attempts.
+pub(crate) fn record(
+    path: &Path,
+    command: &str,
+    started: SystemTime,
+    report: &Report,
+) -> Result<(), io::Error> {
+    let mut connection = open(path)?;
+    let transaction = connection.transaction().map_err(io::Error::other)?;
+    transaction
+        .execute(
+            "INSERT INTO runs (command, started, duration_secs, outcome, reason, attempts)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                command,
+                format_timestamp(started),
+                started.elapsed().unwrap_or_default().as_secs_f64(),
+                report.outcome.name(),
+                report.reason,
+                report.attempts as i64,
+            ],
+        )
+        .map_err(io::Error::other)?;
+    let run_id = transaction.last_insert_rowid();
+    for record in &report.history {
+        let usage = record.usage;
+        transaction
+            .execute(
+                "INSERT INTO attempts
+                     (run_id, number, started, duration_secs, exit_code, signal, decision,
+                      max_rss_bytes, user_cpu_secs, system_cpu_secs, major_page_faults,
+                      minor_page_faults, reason)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    run_id,
+                    record.number as i64,
+                    format_timestamp(record.started),
+                    record.duration.as_secs_f64(),
+                    record.exit_status.code(),
+                    signal(record.exit_status),
+                    record.decision,
+                    usage.map(|usage| usage.max_rss as i64),
+                    usage.map(|usage| usage.user_time.as_secs_f64()),
+                    usage.map(|usage| usage.system_time.as_secs_f64()),
+                    usage.map(|usage| usage.major_faults as i64),
+                    usage.map(|usage| usage.minor_faults as i64),
+                    record.reason,
+                ],
+            )
+            .map_err(io::Error::other)?;
+    }
+
+    transaction.commit().map_err(io::Error::other)
+}
+
+/// How the runs of one command went.
+#[derive(Debug, PartialEq)]
+struct Summary {
+    command: String,
+    runs: u64,
+    succeeded: u64,
+    mean_attempts: f64,
+    mean_duration: Duration,
+}
+
+fn summarize(
+    connection: &Connection,
+    command_like: Option<&str>,
+    since: Option<SystemTime>,
+) -> Result<Vec<Summary>, rusqlite::Error> {
+    let mut statement = connection.prepare(
+        "SELECT command, COUNT(*), SUM(outcome = 'succeeded'), AVG(attempts), AVG(duration_secs)
+         FROM runs
+         WHERE (?1 IS NULL OR command LIKE ?1) AND (?2 IS NULL OR started >= ?2)
+         GROUP BY command
+         ORDER BY command",
+    )?;
+    let rows = statement.query_map(params![command_like, since.map(format_timestamp)], |row| {
+        Ok(Summary {
+            command: row.get(0)?,
+            runs: row.get(1)?,
+            succeeded: row.get(2)?,
+            mean_attempts: row.get(3)?,
+            mean_duration: Duration::from_secs_f64(row.get::<_, f64>(4)?.max(0.0)),
+        })
+    })?;
+    rows.collect()
+}
+
+/// An attempt which failed, and why it was retried or ended the run.
+#[derive(Debug, PartialEq)]
+struct Failure {
+    command: String,
+    attempt: u64,
+    started: String,
+    duration: Duration,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    decision: String,
+    reason: Option<String>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {} (attempt {}) {} after running for {}",
+            self.started,
+            self.command,
+            self.attempt,
+            self.decision,
+            format_duration(self.duration)
+        )?;
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => write!(f, ", exit code {}", code)?,
+            (None, Some(signal)) => write!(f, ", killed by signal {}", signal)?,
+            (None, None) => (),
+        }
+        match &self.reason {
+            Some(reason) => write!(f, ": {}", reason),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The most recent failed attempts, newest first.
+fn recent_failures(
+    connection: &Connection,
+    command_like: Option<&str>,
+    since: Option<SystemTime>,
+    limit: usize,
+) -> Result<Vec<Failure>, rusqlite::Error> {
+    let mut statement = connection.prepare(
+        "SELECT runs.command, attempts.number, attempts.started, attempts.duration_secs,
+                attempts.exit_code, attempts.signal, attempts.decision, attempts.reason
+         FROM attempts JOIN runs ON runs.id = attempts.run_id
+         WHERE attempts.decision NOT IN ('succeeded', 'failed')
+             AND (?1 IS NULL OR runs.command LIKE ?1) AND (?2 IS NULL OR attempts.started >= ?2)
+         ORDER BY attempts.started DESC, attempts.run_id DESC, attempts.number DESC
+         LIMIT ?3",
+    )?;
+    let rows = statement.query_map(
+        params![command_like, since.map(format_timestamp), limit as i64],
+        |row| {
+            Ok(Failure {
+                command: row.get(0)?,
+                attempt: row.get(1)?,
+                started: row.get(2)?,
+                duration: Duration::from_secs_f64(row.get::<_, f64>(3)?.max(0.0)),
+                exit_code: row.get(4)?,
+                signal: row.get(5)?,
+                decision: row.get(6)?,
+                reason: row.get(7)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+/// Print how often each recorded command succeeded, and how long it took, or with --why, the
+/// most recent failed attempts and why they were retried.
+pub(crate) fn history(args: &HistoryArguments) -> Result<(), io::Error> {
+    let path = args.history_db.clone().unwrap_or_else(default_path);
+    let connection = open(&path)?;
+    let since = args
+        .since
+        .and_then(|since| SystemTime::now().checked_sub(since));
+    if let Some(limit) = args.why {
+        let failures = recent_failures(&connection, args.command_like.as_deref(), since, limit)
+            .map_err(io::Error::other)?;
+        if failures.is_empty() {
+            println!("No failed attempts recorded in {}", path.display());
+        }
+        // Oldest first, so that the most recent failure is nearest the prompt.
+        for failure in failures.iter().rev() {
+            println!("{}", failure);
+        }
+        return Ok(());
+    }
+    let summaries =
+        summarize(&connection, args.command_like.as_deref(), since).map_err(io::Error::other)?;
+    if summaries.is_empty() {
+        println!("No runs recorded in {}", path.display());
+        return Ok(());
+    }
+
+    println!(
+        "{:>6} {:>10} {:>13} {:>13}  COMMAND",
+        "RUNS", "SUCCEEDED", "MEAN ATTEMPTS", "MEAN DURATION"
+    );
+    for summary in summaries {
+        println!(
+            "{:>6} {:>9.0}% {:>13.1} {:>13}  {}",
+            summary.runs,
+            100.0 * summary.succeeded as f64 / summary.runs as f64,
+            summary.mean_attempts,
+            format_duration(summary.mean_duration),
+            summary.command
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::Outcome;
+
+    #[test]
+    fn test_record_and_summarize() {
+        let path = env::temp_dir().join(format!("attempt-history-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let started = SystemTime::now() - Duration::from_secs(10);
+        let succeeded = Report::new(Outcome::Succeeded, "the command succeeded", 2, None);
+        let failed = Report::new(Outcome::Failed, "no attempts left", 3, None);
+        record(&path, "cargo test", started, &succeeded).unwrap();
+        record(&path, "cargo test", started, &failed).unwrap();
+        record(&path, "make", started, &succeeded).unwrap();
+
+        let connection = open(&path).unwrap();
+        let summaries = summarize(&connection, Some("cargo%"), None).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].command, "cargo test");
+        assert_eq!(summaries[0].runs, 2);
+        assert_eq!(summaries[0].succeeded, 1);
+        assert_eq!(summaries[0].mean_attempts, 2.5);
+        assert!(summaries[0].mean_duration >= Duration::from_secs(10));
+
+        assert_eq!(summarize(&connection, None, None).unwrap().len(), 2);
+        assert!(summarize(&connection, None, Some(SystemTime::now()))
+            .unwrap()
+            .is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recent_failures() {
+        use crate::report::AttemptRecord;
+        use std::{process::Command, time::UNIX_EPOCH};
+
+        let path = env::temp_dir().join(format!("attempt-history-why-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let status = |script| Command::new("sh").args(["-c", script]).status().unwrap();
+        let attempt = |number, exit_status, decision, reason: Option<&str>| AttemptRecord {
+            number,
+            started: UNIX_EPOCH + Duration::from_secs(1_662_316_200 + number as u64 * 60),
+            duration: Duration::from_secs(42),
+            exit_status,
+            decision,
+            output: None,
+            usage: None,
+            reason: reason.map(String::from),
+        };
+        let report = Report::new(Outcome::Succeeded, "", 3, None).with_history(vec![
+            attempt(
+                1,
+                status("exit 75"),
+                "retried",
+                Some("75 matched --retry-if-status"),
+            ),
+            attempt(2, status("kill -9 $$"), "retried", None),
+            attempt(3, status("exit 0"), "succeeded", None),
+        ]);
+        record(&path, "./serve", SystemTime::now(), &report).unwrap();
+
+        let connection = open(&path).unwrap();
+        let failures = recent_failures(&connection, None, None, 20).unwrap();
+        assert_eq!(
+            failures.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "2022-09-04T18:32:00.000Z  ./serve (attempt 2) retried after running for 42s, \
+                 killed by signal 9",
+                "2022-09-04T18:31:00.000Z  ./serve (attempt 1) retried after running for 42s, \
+                 exit code 75: 75 matched --retry-if-status",
+            ]
+        );
+        assert_eq!(
+            recent_failures(&connection, None, None, 1).unwrap().len(),
+            1
+        );
+        assert!(recent_failures(&connection, Some("make"), None, 20)
+            .unwrap()
+            .is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resource_usage_in_older_database() {
+        use crate::{child::ResourceUsage, report::AttemptRecord};
+        use std::process::Command;
+
+        let path = env::temp_dir().join(format!("attempt-history-old-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE attempts (
+                     run_id INTEGER NOT NULL, number INTEGER NOT NULL, started TEXT NOT NULL,
+                     duration_secs REAL NOT NULL, exit_code INTEGER, signal INTEGER,
+                     decision TEXT NOT NULL
+                 );",
+            )
+            .unwrap();
+
+        let report =
+            Report::new(Outcome::Succeeded, "", 1, None).with_history(vec![AttemptRecord {
+                number: 1,
+                started: SystemTime::now(),
+                duration: Duration::from_secs(1),
+                exit_status: Command::new("true").status().unwrap(),
+                decision: "succeeded",
+                output: None,
+                usage: Some(ResourceUsage {
+                    max_rss: 4096,
+                    user_time: Duration::from_millis(500),
+                    ..Default::default()
+                }),
+                reason: None,
+            }]);
+        record(&path, "make", SystemTime::now(), &report).unwrap();
+
+        let (max_rss, user_cpu): (i64, f64) = open(&path)
+            .unwrap()
+            .query_row(
+                "SELECT max_rss_bytes, user_cpu_secs FROM attempts",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((max_rss, user_cpu), (4096, 0.5));
+        fs::remove_file(&path).unwrap();
+    }
+}
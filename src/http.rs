@@ -0,0 +1,328 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{probe::connect, util::parse_timestamp};
+
+/// How long a request waits to connect, or for more of the response, before failing.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A URL requested by `attempt http`. Only plain HTTP is supported, as there's no TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HttpUrl {
+    authority: String,
+    path: String,
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => {
+                return Err(String::from(
+                    "https:// isn't supported, as attempt can't make TLS connections",
+                ))
+            }
+            _ => return Err(format!("{} is not an http:// URL", url)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("{} has no host", url));
+        }
+
+        Ok(Self {
+            authority: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.path)
+    }
+}
+
+/// Which response statuses count as success, given as a comma separated list of codes where x
+/// matches any digit, such as "2xx" or "200,304".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StatusPattern(Vec<String>);
+
+impl StatusPattern {
+    pub(crate) fn matches(&self, status: u16) -> bool {
+        let status = status.to_string();
+        self.0.iter().any(|pattern| {
+            pattern.len() == status.len()
+                && pattern
+                    .chars()
+                    .zip(status.chars())
+                    .all(|(pattern, digit)| pattern == 'x' || pattern == digit)
+        })
+    }
+}
+
+impl FromStr for StatusPattern {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.split(',')
+            .map(|pattern| {
+                let pattern = pattern.trim().to_ascii_lowercase();
+                let valid = pattern.len() == 3
+                    && pattern.starts_with(['1', '2', '3', '4', '5'])
+                    && pattern.chars().all(|c| c == 'x' || c.is_ascii_digit());
+                if valid {
+                    Ok(pattern)
+                } else {
+                    Err(format!("{} is not a status such as 200 or 2xx", pattern))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for StatusPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(","))
+    }
+}
+
+/// A request to make, over and over if need be.
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub method: String,
+    pub url: HttpUrl,
+    /// Extra headers, each given as "Name: value".
+    pub headers: Vec<String>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl Request {
+    /// Make the request, returning the response. Requests are made with HTTP/1.0, so that the
+    /// response is never chunked and ends when the connection is closed.
+    pub(crate) fn send(&self) -> Result<Response, io::Error> {
+        let address = if self.url.authority.contains(':') {
+            self.url.authority.clone()
+        } else {
+            format!("{}:80", self.url.authority)
+        };
+        let mut stream = connect(&address, REQUEST_TIMEOUT)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\nUser-Agent: attempt/{}\r\n",
+            self.method,
+            self.url.path,
+            self.url.authority,
+            env!("CARGO_PKG_VERSION")
+        );
+        for header in &self.headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        if let Some(body) = &self.body {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        if let Some(body) = &self.body {
+            stream.write_all(body)?;
+        }
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        parse_response(&response, SystemTime::now())
+    }
+}
+
+/// A response to a request.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub status: u16,
+    /// The status line, without the protocol, such as "503 Service Unavailable".
+    pub status_line: String,
+    /// How long the server asked to wait before trying again, if it did.
+    pub retry_after: Option<Duration>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Whether trying again might get a different response: after server errors, and when the
+    /// server asks for fewer requests.
+    pub(crate) fn is_retryable(&self) -> bool {
+        self.status >= 500 || self.status == 429
+    }
+}
+
+fn parse_response(response: &[u8], now: SystemTime) -> Result<Response, io::Error> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("the response ended before its headers did"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let mut lines = head.split("\r\n");
+    let status_line = lines
+        .next()
+        .and_then(|line| line.split_once(' '))
+        .map(|(_, status)| status.trim().to_string())
+        .ok_or_else(|| invalid("the response has no status line"))?;
+    let status = status_line
+        .split(' ')
+        .next()
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("the response has no status code"))?;
+    let retry_after = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("retry-after"))
+        .and_then(|(_, value)| parse_retry_after(value.trim(), now));
+
+    Ok(Response {
+        status,
+        status_line,
+        retry_after,
+        body: response[end + 4..].to_vec(),
+    })
+}
+
+/// Parse a Retry-After header, given either in seconds or as an HTTP date such as
+/// "Sun, 06 Nov 1994 08:49:37 GMT".
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = fields[..] else {
+        return None;
+    };
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|name| *name == month)?
+        + 1;
+    let date = parse_timestamp(&format!("{}-{:02}-{}T{}Z", year, month, day, time))?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread,
+        time::UNIX_EPOCH,
+    };
+
+    #[test]
+    fn test_parse_url() {
+        let url: HttpUrl = "http://localhost:8080/api/jobs?id=1".parse().unwrap();
+        assert_eq!(url.authority, "localhost:8080");
+        assert_eq!(url.path, "/api/jobs?id=1");
+        assert_eq!(
+            "http://example.com".parse::<HttpUrl>().unwrap().to_string(),
+            "http://example.com/"
+        );
+        assert!("https://example.com"
+            .parse::<HttpUrl>()
+            .unwrap_err()
+            .contains("TLS"));
+        assert!("example.com".parse::<HttpUrl>().is_err());
+    }
+
+    #[test]
+    fn test_status_pattern() {
+        let pattern: StatusPattern = "2xx, 304".parse().unwrap();
+        assert!(pattern.matches(204));
+        assert!(pattern.matches(304));
+        assert!(!pattern.matches(301));
+        assert!(!pattern.matches(500));
+        assert_eq!(pattern.to_string(), "2xx,304");
+        assert!("2x".parse::<StatusPattern>().is_err());
+        assert!("abc".parse::<StatusPattern>().is_err());
+        assert!("9xx".parse::<StatusPattern>().is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_717);
+        let response = parse_response(
+            b"HTTP/1.1 503 Service Unavailable\r\nretry-after: 120\r\n\r\ntry later",
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            Response {
+                status: 503,
+                status_line: String::from("503 Service Unavailable"),
+                retry_after: Some(Duration::from_secs(120)),
+                body: b"try later".to_vec(),
+            }
+        );
+        assert!(response.is_retryable());
+
+        let response = parse_response(
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n",
+            now,
+        )
+        .unwrap();
+        assert_eq!(response.retry_after, Some(Duration::from_secs(60)));
+        assert!(response.is_retryable());
+
+        assert!(!parse_response(b"HTTP/1.0 404 Not Found\r\n\r\n", now)
+            .unwrap()
+            .is_retryable());
+        assert!(parse_response(b"HTTP/1.0 200 OK\r\n", now).is_err());
+    }
+
+    #[test]
+    fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let request = Request {
+            method: String::from("POST"),
+            url: format!("http://{}/jobs", listener.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+            headers: vec![String::from("Content-Type: application/json")],
+            body: Some(b"{}".to_vec()),
+        };
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push(line);
+            }
+            let mut body = [0; 2];
+            reader.read_exact(&mut body).unwrap();
+            assert_eq!(head[0], "POST /jobs HTTP/1.0\r\n");
+            assert!(head.contains(&String::from("Content-Type: application/json\r\n")));
+            assert!(head.contains(&String::from("Content-Length: 2\r\n")));
+            assert_eq!(&body, b"{}");
+            write!(stream, "HTTP/1.0 201 Created\r\n\r\n{{\"id\":1}}").unwrap();
+        });
+
+        let response = request.send().unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, b"{\"id\":1}");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    cell::Cell,
+    env,
+    fs::File,
+    io::{self, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::arguments::ColorChoice;
+
+/// Writes `attempt`'s own messages, prefixed with the current attempt and their level.
+struct Logger {
+    color: AtomicBool,
+    prefix: OnceLock<String>,
+    attempt: AtomicUsize,
+    /// The attempt limit, or 0 if there isn't one.
+    attempts: AtomicUsize,
+    /// Messages held back rather than written, if they're being held.
+    held: Mutex<Option<Vec<String>>>,
+}
+
+thread_local! {
+    /// The attempt under way on this thread, when several commands are attempted at once.
+    static THREAD_ATTEMPT: Cell<Option<(usize, Option<usize>)>> = const { Cell::new(None) };
+}
+
+/// The file descriptor given by --log-fd, if any, which `attempt`'s own messages are written to
+/// in place of stderr. It's never closed.
+static LOG_FILE: OnceLock<File> = OnceLock::new();
+
+static LOGGER: Logger = Logger {
+    color: AtomicBool::new(false),
+    prefix: OnceLock::new(),
+    attempt: AtomicUsize::new(0),
+    attempts: AtomicUsize::new(0),
+    held: Mutex::new(None),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let (attempt, attempts) = THREAD_ATTEMPT.get().unwrap_or_else(|| {
+                (
+                    self.attempt.load(Ordering::Relaxed),
+                    Some(self.attempts.load(Ordering::Relaxed)).filter(|&attempts| attempts > 0),
+                )
+            });
+            let prefix = render_prefix(
+                self.prefix.get().map_or("", String::as_str),
+                attempt,
+                attempts,
+            );
+            let level = level_name(record.level());
+            let message = if self.color.load(Ordering::Relaxed) {
+                format!(
+                    "{}{}{}\x1b[0m: {}",
+                    prefix,
+                    level_color(record.level()),
+                    level,
+                    record.args()
+                )
+            } else {
+                format!("{}{}: {}", prefix, level, record.args())
+            };
+            write_message(message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// Fill in the `{attempt}` and `{attempts}` placeholders of the log prefix, where an unlimited
+/// number of attempts is shown as ∞. Nothing is added before the first attempt has started, or
+/// if the template is empty.
+pub(crate) fn render_prefix(template: &str, attempt: usize, attempts: Option<usize>) -> String {
+    if template.is_empty() || attempt == 0 {
+        return String::new();
+    }
+
+    let attempts = attempts.map_or(String::from("∞"), |attempts| attempts.to_string());
+    let prefix = template
+        .replace("{attempts}", &attempts)
+        .replace("{attempt}", &attempt.to_string());
+    format!("{} ", prefix)
+}
+
+/// Record the attempt now under way, so that it is included in log messages. Messages logged
+/// from the same thread use this attempt even if another thread has since started its own, and
+/// messages from other threads use the most recent attempt.
+pub(crate) fn set_attempt(attempt: usize, attempts: Option<usize>) {
+    THREAD_ATTEMPT.set(Some((attempt, attempts)));
+    LOGGER.attempt.store(attempt, Ordering::Relaxed);
+    LOGGER
+        .attempts
+        .store(attempts.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Hold back log messages from now on, rather than writing them, until they're released or
+/// discarded.
+pub(crate) fn hold() {
+    LOGGER.held.lock().unwrap().get_or_insert_with(Vec::new);
+}
+
+/// Write the messages held back so far, and write messages as they're logged from now on.
+pub(crate) fn release() {
+    if let Some(held) = LOGGER.held.lock().unwrap().take() {
+        for message in held {
+            write_messages(&format!("{}\n", message));
+        }
+    }
+}
+
+/// Drop the messages held back so far, and write messages as they're logged from now on.
+pub(crate) fn discard() {
+    LOGGER.held.lock().unwrap().take();
+}
+
+/// Whether log messages are being held back.
+pub(crate) fn holding() -> bool {
+    LOGGER.held.lock().unwrap().is_some()
+}
+
+/// Write a message on a line of its own, such as a log message or a summary, or hold it back
+/// with the log messages if they're being held.
+pub(crate) fn write_message(message: String) {
+    match LOGGER.held.lock().unwrap().as_mut() {
+        Some(held) => held.push(message),
+        None => write_messages(&format!("{}\n", message)),
+    }
+}
+
+/// Write `attempt`'s own output, such as log messages and the countdown, to stderr or the file
+/// descriptor given by --log-fd. The text is written all at once, so that it isn't interleaved
+/// with messages from other threads.
+pub(crate) fn write_messages(text: &str) {
+    let _ = match LOG_FILE.get() {
+        Some(mut file) => file.write_all(text.as_bytes()),
+        None => io::stderr().lock().write_all(text.as_bytes()),
+    };
+}
+
+/// Whether `attempt`'s own messages are written to a terminal.
+pub(crate) fn messages_are_terminal() -> bool {
+    match LOG_FILE.get() {
+        Some(file) => file.is_terminal(),
+        None => io::stderr().is_terminal(),
+    }
+}
+
+/// The already open file descriptor given by --log-fd.
+#[cfg(unix)]
+fn open_log_fd(fd: i32) -> Result<File, io::Error> {
+    use std::os::fd::FromRawFd;
+
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--log-fd {} is not an open file descriptor", fd),
+        ));
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn open_log_fd(_fd: i32) -> Result<File, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--log-fd is only supported on Unix",
+    ))
+}
+
+/// The ANSI escape sequence used to highlight each level.
+fn level_color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "\x1b[1;31m",
+        Level::Warn => "\x1b[1;33m",
+        Level::Info => "\x1b[1;32m",
+        Level::Debug | Level::Trace => "\x1b[2m",
+    }
+}
+
+/// Whether to color log messages. When left to decide automatically, color is used if messages
+/// are written to a terminal and the `NO_COLOR` environment variable is unset or empty.
+pub(crate) fn use_color(choice: ColorChoice, is_tty: bool, no_color: Option<&str>) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_tty && no_color.is_none_or(str::is_empty),
+    }
+}
+
+/// The level to log at, starting from `Info` and adjusted by the number of times `--verbose` and
+/// `--quiet` were passed.
+pub(crate) fn level_filter(verbose: u8, quiet: u8) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    let index = (3 + verbose as isize - quiet as isize).clamp(0, LEVELS.len() as isize - 1);
+
+    LEVELS[index as usize]
+}
+
+pub(crate) fn init(
+    verbose: u8,
+    quiet: u8,
+    color: ColorChoice,
+    prefix: &str,
+    log_fd: Option<i32>,
+) -> Result<(), io::Error> {
+    if let Some(fd) = log_fd {
+        let _ = LOG_FILE.set(open_log_fd(fd)?);
+    }
+    LOGGER
+        .prefix
+        .set(prefix.to_string())
+        .expect("The logger was initialized twice");
+    let no_color = env::var("NO_COLOR").ok();
+    LOGGER.color.store(
+        use_color(color, messages_are_terminal(), no_color.as_deref()),
+        Ordering::Relaxed,
+    );
+    log::set_logger(&LOGGER).expect("Failed to install the logger");
+    log::set_max_level(level_filter(verbose, quiet));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level_filter() {
+        assert_eq!(level_filter(0, 0), LevelFilter::Info);
+        assert_eq!(level_filter(1, 0), LevelFilter::Debug);
+        assert_eq!(level_filter(0, 1), LevelFilter::Warn);
+        assert_eq!(level_filter(1, 1), LevelFilter::Info);
+        assert_eq!(level_filter(10, 0), LevelFilter::Trace);
+        assert_eq!(level_filter(0, 10), LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_render_prefix() {
+        let template = "[attempt {attempt}/{attempts}]";
+        assert_eq!(render_prefix(template, 3, Some(5)), "[attempt 3/5] ");
+        assert_eq!(render_prefix(template, 3, None), "[attempt 3/∞] ");
+        assert_eq!(render_prefix(template, 0, Some(5)), "");
+        assert_eq!(render_prefix("", 3, Some(5)), "");
+        assert_eq!(render_prefix("#{attempt}", 3, Some(5)), "#3 ");
+    }
+
+    #[test]
+    fn test_use_color() {
+        assert!(use_color(ColorChoice::Auto, true, None));
+        assert!(use_color(ColorChoice::Auto, true, Some("")));
+        assert!(!use_color(ColorChoice::Auto, true, Some("1")));
+        assert!(!use_color(ColorChoice::Auto, false, None));
+
+        assert!(use_color(ColorChoice::Always, false, Some("1")));
+        assert!(!use_color(ColorChoice::Never, true, None));
+    }
+}
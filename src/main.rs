@@ -1,21 +1,1207 @@
+mod alias;
+mod argfile;
 mod arguments;
+mod batch;
+mod bench;
+mod budget;
+mod checksum;
+mod child;
+mod code_pattern;
+#[cfg(unix)]
+mod control;
+mod cores;
+mod defaults;
+mod expect;
+mod gate;
+mod gha;
+mod health;
+mod heartbeat;
+mod history;
+mod http;
+mod logger;
+mod pid_file;
+mod pipeline;
+mod probe;
+#[cfg(unix)]
+mod pty;
+mod report;
+mod sandbox;
+mod scratch;
+mod signals;
+mod spill;
+mod start_limit;
+mod status;
 mod util;
+mod wait;
+mod watch;
+mod window;
 
-use std::{io, thread};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use arguments::ArgumentParser;
-use clap::Parser;
+use arguments::{
+    misplaced_options, ArgumentParser, BackoffStrategy, CommonArguments, HttpArguments, Mode,
+    RetryDecision, WaitForArguments,
+};
+use budget::Budget;
+use child::{Exited, OutputOptions, Written};
+use clap::{CommandFactory, ErrorKind, Parser};
+use cores::{collect_core, dumped_core, CoreDumps};
+use gate::Requirement;
+use health::HealthMonitor;
+use log::{debug, info, log, warn, Level};
+use pid_file::PidFile;
+use probe::Probe;
+use report::{AttemptRecord, Outcome, Report};
+use sandbox::Sandbox;
+use scratch::ScratchDir;
+use signals::handle_signals;
+use spill::SpilledBytes;
+use status::{SharedStatus, Status};
+use util::{
+    build_command, duration_from_f64, fill_placeholders, format_clock_time, format_duration,
+    unix_time,
+};
+use wait::Waiter;
 
 fn main() -> Result<(), io::Error> {
-    let args = ArgumentParser::parse();
-    let mut command = args.backoff.command();
-    for duration in args.backoff {
-        if command.status()?.success() {
-            std::process::exit(0);
+    run(parse(env::args_os().collect()))
+}
+
+/// Parse and check the command line, after expanding argument files and filling in defaults.
+fn parse(args: Vec<OsString>) -> Mode {
+    let args = argfile::expand(args)
+        .and_then(defaults::with_defaults)
+        .unwrap_or_else(|e| {
+            ArgumentParser::command()
+                .error(ErrorKind::InvalidValue, e)
+                .exit()
+        });
+    let separated = defaults::command_follows_separator(&args);
+    let mut parsed = ArgumentParser::parse_from(args);
+    parsed.validate().unwrap_or_else(|e| e.exit());
+    if let Mode::Retry(backoff) = &mut parsed.mode {
+        backoff.common_mut().separated = separated;
+    }
+    parsed.mode
+}
+
+fn run(mode: Mode) -> Result<(), io::Error> {
+    match mode {
+        Mode::Retry(backoff) => retry(backoff),
+        Mode::Bench(args) => bench::bench(&args),
+        Mode::WaitFor(args) => wait_for(args),
+        Mode::Http(args) => http(args),
+        Mode::Pipeline(args) => pipeline::pipeline(args),
+        Mode::Batch(args) => batch::batch(args),
+        Mode::History(args) => history::history(&args),
+        Mode::Save(args) => alias::save(&args),
+        Mode::Run(args) => {
+            let mut arguments = vec![env::args_os().next().unwrap_or_default()];
+            arguments.extend(alias::expand(&args.name).unwrap_or_else(|e| {
+                ArgumentParser::command()
+                    .error(ErrorKind::InvalidValue, e)
+                    .exit()
+            }));
+            run(parse(arguments))
+        }
+    }
+}
+
+fn retry(backoff: BackoffStrategy) -> Result<(), io::Error> {
+    let common = backoff.common().clone();
+    logger::init(
+        common.verbose,
+        common.quiet,
+        common.color,
+        &common.log_prefix,
+        common.log_fd,
+    )?;
+    if !common.separated {
+        for option in misplaced_options(backoff.name(), &common.command) {
+            warn!(
+                "{} comes after the command, so it was passed to the command rather than to attempt. \
+                 Put -- before the command to silence this warning.",
+                option
+            );
+        }
+    }
+    if common.cronic {
+        logger::hold();
+    }
+    let pid_file = common.pid_file.clone().map(PidFile::new);
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let status = Arc::new(Mutex::new(Status::new(
+        backoff.attempt_limit(),
+        backoff.describe(),
+    )));
+    handle_signals(
+        Arc::clone(&status),
+        Arc::clone(&interrupted),
+        !common.no_kill_on_exit,
+        pid_file.clone(),
+        common.reload_signal,
+    )?;
+
+    let started = SystemTime::now();
+    let result = attempt(backoff, &common, &status, &interrupted, pid_file.as_ref());
+    if let Some(pid_file) = &pid_file {
+        pid_file.remove();
+    }
+
+    let report = result?;
+    if let Some(path) = &common.history_db {
+        let path = path.clone().unwrap_or_else(history::default_path);
+        if let Err(e) = history::record(&path, &common.command.join(" "), started, &report) {
+            warn!("Failed to record the run in {}: {}", path.display(), e);
+        }
+    }
+    finish(report, &common)
+}
+
+/// Write the reason file and reports if requested, then exit with the report's status code.
+fn finish(report: Report, common: &CommonArguments) -> ! {
+    if let Some(path) = &common.reason_file {
+        if let Err(e) = report.write(path) {
+            warn!("Failed to write the reason file: {}", e);
+        }
+    }
+    if let Some(path) = &common.report_csv {
+        if let Err(e) = report.write_csv(path) {
+            warn!("Failed to write the CSV report: {}", e);
+        }
+    }
+    if let Some(path) = &common.report_junit {
+        if let Err(e) = report.write_junit(path, &common.command.join(" ")) {
+            warn!("Failed to write the JUnit report: {}", e);
+        }
+    }
+    if common.cronic {
+        if report.outcome == Outcome::Succeeded {
+            logger::discard();
         } else {
-            thread::sleep(duration);
+            logger::release();
+            let _ = io::stdout().write_all(&report.output);
         }
     }
 
-    std::process::exit(1);
+    std::process::exit(report.exit_code());
+}
+
+/// Run the command until it succeeds or the schedule is exhausted, reporting how it ended and
+/// how each attempt went.
+fn attempt(
+    backoff: BackoffStrategy,
+    common: &CommonArguments,
+    status: &SharedStatus,
+    interrupted: &Arc<AtomicBool>,
+    pid_file: Option<&PidFile>,
+) -> Result<Report, io::Error> {
+    let mut history = Vec::new();
+    let mut output = SpilledBytes::default();
+    let report = run_attempts(
+        backoff,
+        common,
+        status,
+        interrupted,
+        pid_file,
+        &mut history,
+        &mut output,
+    )?;
+    Ok(report.with_history(history).with_output(output))
+}
+
+fn run_attempts(
+    backoff: BackoffStrategy,
+    common: &CommonArguments,
+    status: &SharedStatus,
+    interrupted: &Arc<AtomicBool>,
+    pid_file: Option<&PidFile>,
+    history: &mut Vec<AttemptRecord>,
+    last_output: &mut SpilledBytes,
+) -> Result<Report, io::Error> {
+    let sandbox = common.sandbox()?;
+    let core_dumps = common.core_dumps();
+    let health_check = common.health_check();
+    let mut command = backoff.command();
+    confine(&mut command, &sandbox, core_dumps.as_ref())?;
+    if let Some(dir) = &common.collect_cores {
+        fs::create_dir_all(dir).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to create the core dump directory {}: {}",
+                    dir.display(),
+                    e
+                ),
+            )
+        })?;
+    }
+    if cfg!(not(unix)) && common.exec_last {
+        warn!("--exec-last is only supported on Unix, so the last attempt will be run as usual");
+    }
+    let stdin_template = match &common.stdin_template {
+        Some(path) => Some(fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to read the stdin template {}: {}",
+                    path.display(),
+                    e
+                ),
+            )
+        })?),
+        None => None,
+    };
+    let explain = explain_level(common);
+
+    let first_started = Instant::now();
+    run_schedule(backoff, common, status, interrupted, |turn, waiter| {
+        let n = turn.number - 1;
+        let elapsed = waiter
+            .simulated_elapsed()
+            .unwrap_or_else(|| first_started.elapsed());
+        let values = placeholder_values(turn, elapsed);
+        if common.template {
+            command = templated_command(common, &values, &sandbox, core_dumps.as_ref())?;
+        }
+        #[cfg(unix)]
+        if common.exec_last && turn.is_last() {
+            return Err(exec_last(&mut command, pid_file));
+        }
+        let scratch = scratch_dir(common, &mut command, turn.number)?;
+        waiter.forget_changes();
+        waiter.heartbeat();
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        if common.gha {
+            gha::group(&match turn.attempts {
+                Some(attempts) => format!("Attempt {} of {}", turn.number, attempts),
+                None => format!("Attempt {}", turn.number),
+            });
+        }
+        let output_options = OutputOptions {
+            pty: common.pty,
+            capture: common.needs_output() || common.report_junit.is_some() || common.cronic,
+            capture_stdout: common.expect_output_file.is_some(),
+            spill_threshold: Some(common.spill_output_over),
+            tag: common.tag(turn.number),
+            timestamps: common.timestamps,
+            label_streams: common.label_streams,
+            quiet: common.cronic,
+        };
+        let input = stdin_template.as_deref().map(|template| {
+            command.stdin(Stdio::piped());
+            fill_placeholders(template, &values)
+        });
+        let mut child = child::spawn(&mut command, &output_options)?;
+        if let Some(input) = input {
+            child.write_stdin(input.into_bytes());
+        }
+        let pid = child.id();
+        status.lock().unwrap().child_started(pid);
+        let health = health_check.as_ref().map(|check| check.monitor(pid));
+        if let Some(pid_file) = pid_file {
+            if let Err(e) = pid_file.write(pid) {
+                warn!("Failed to write the pid file: {}", e);
+            }
+        }
+        let Exited {
+            status: exit_status,
+            output,
+            stdout,
+            written,
+            usage,
+            lost_output,
+        } = child.wait()?;
+        let unhealthy = health.and_then(HealthMonitor::finish);
+        if common.gha {
+            gha::end_group();
+        }
+        if let Some(scratch) = scratch {
+            if common.keep_failed_temp_dir && !common.succeeded(exit_status) {
+                info!(
+                    "Kept the temporary directory of the failed attempt at {}",
+                    scratch.keep().display()
+                );
+            }
+        }
+        let ran_for = started.elapsed();
+        if let Some(usage) = usage {
+            debug!("Attempt {} used {}", turn.number, usage);
+        }
+        if dumped_core(exit_status) {
+            collect_core_dump(common, turn.number, pid, started_at);
+        }
+        status.lock().unwrap().child_exited();
+        let report = |outcome, reason| Report::new(outcome, reason, turn.number, Some(exit_status));
+        history.push(AttemptRecord {
+            number: turn.number,
+            started: started_at,
+            duration: ran_for,
+            exit_status,
+            decision: "retried",
+            output: common
+                .report_junit
+                .is_some()
+                .then(|| AttemptRecord::output_tail(&output)),
+            usage,
+            reason: None,
+        });
+        // Kept for --cronic by moving it rather than copying it, as it may be large.
+        let output: &SpilledBytes = if common.cronic {
+            *last_output = output;
+            last_output
+        } else {
+            &output
+        };
+
+        // An unrecoverable error ends the run whatever the other conditions say.
+        if let Some(reason) = fatal_reason(common, exit_status, output, lost_output) {
+            warn!("Aborting ({}): {}", exit_status, reason);
+            history[n].decision = "aborted";
+            history[n].reason = Some(reason.clone());
+            if common.gha {
+                gha::error(&format!("Aborting ({}): {}", exit_status, reason));
+            }
+            return Ok(Step::Done(report(Outcome::Fatal, reason)));
+        }
+
+        // With --count, every run is made whatever the outcome.
+        if common.count.is_some() {
+            return Ok(count_run(
+                turn,
+                exit_status,
+                common,
+                history,
+                interrupted,
+                explain,
+            ));
+        }
+
+        let succeeded = common.succeeded(exit_status);
+        let unmet = unmet_condition(common, succeeded, unhealthy, &stdout, written);
+        if common.until_failure && !succeeded {
+            log!(
+                explain,
+                "Failed ({}), which is what --until-failure was waiting for",
+                exit_status
+            );
+            history[n].decision = "succeeded";
+            return Ok(Step::Done(report(
+                Outcome::Succeeded,
+                format!("the command failed ({})", exit_status),
+            )));
+        } else if !common.until_failure && succeeded && unmet.is_none() {
+            log!(explain, "Succeeded ({})", exit_status);
+            history[n].decision = "succeeded";
+            return Ok(Step::Done(report(
+                Outcome::Succeeded,
+                String::from("the command succeeded"),
+            )));
+        } else if interrupted.load(Ordering::SeqCst) {
+            warn!("Command failed after interrupt ({})", exit_status);
+            history[n].decision = "interrupted";
+            return Ok(Step::Done(report(
+                Outcome::Interrupted,
+                String::from("interrupted"),
+            )));
+        }
+
+        let RetryDecision {
+            retry,
+            reason,
+            groups,
+        } = if common.until_failure {
+            RetryDecision::new(
+                true,
+                String::from("the command succeeded, and --until-failure runs it until it fails"),
+            )
+        } else if let Some(reason) = unmet {
+            RetryDecision::new(true, reason)
+        } else {
+            common.should_retry(exit_status.code(), output, written)
+        };
+        if !retry {
+            log!(explain, "Not retrying ({}): {}", exit_status, reason);
+            history[n].decision = "stopped";
+            history[n].reason = Some(reason.clone());
+            if common.gha {
+                gha::error(&format!("Not retrying ({}): {}", exit_status, reason));
+            }
+            Ok(Step::Done(
+                report(Outcome::Failed, reason).with_groups(groups),
+            ))
+        } else if turn.is_last() {
+            // There's no point waiting if there won't be another attempt.
+            log!(
+                explain,
+                "Would retry ({}), but no attempts are left: {}",
+                exit_status,
+                reason
+            );
+            history[n].decision = "gave up";
+            history[n].reason = Some(reason.clone());
+            if common.gha {
+                gha::error(&format!(
+                    "Failed after {} attempts ({}): {}",
+                    turn.number, exit_status, reason
+                ));
+            }
+            Ok(Step::Done(
+                report(Outcome::Failed, format!("no attempts left, {}", reason))
+                    .with_groups(groups),
+            ))
+        } else if let Some(reason) = start_limit_hit(common, history) {
+            warn!("Giving up ({}): {}", exit_status, reason);
+            history[n].decision = "gave up";
+            history[n].reason = Some(reason.clone());
+            if common.gha {
+                gha::error(&format!("Giving up ({}): {}", exit_status, reason));
+            }
+            Ok(Step::Done(report(Outcome::StartLimitHit, reason)))
+        } else {
+            log!(explain, "Retrying ({}): {}", exit_status, reason);
+            history[n].reason = Some(reason.clone());
+            if common.gha && !common.until_failure {
+                gha::warning(&format!(
+                    "Attempt {} failed ({}), retrying: {}",
+                    turn.number, exit_status, reason
+                ));
+            }
+            Ok(Step::Retry {
+                wait: retry_wait(common, turn.wait, exit_status, output, ran_for),
+                exit_status: Some(exit_status),
+            })
+        }
+    })
+}
+
+/// Apply the sandbox and core dump settings to the command.
+fn confine(
+    command: &mut Command,
+    sandbox: &Sandbox,
+    core_dumps: Option<&CoreDumps>,
+) -> Result<(), io::Error> {
+    sandbox.apply(command)?;
+    if let Some(core_dumps) = core_dumps {
+        core_dumps.apply(command)?;
+    }
+    Ok(())
+}
+
+/// The values of the placeholders filled in by --template and --stdin-template.
+fn placeholder_values(turn: &Turn, elapsed: Duration) -> [(&'static str, String); 4] {
+    [
+        ("attempt", turn.number.to_string()),
+        ("elapsed", elapsed.as_secs().to_string()),
+        (
+            "last_status",
+            turn.last_exit
+                .and_then(|status| status.code())
+                .map_or(String::new(), |code| code.to_string()),
+        ),
+        ("timestamp", unix_time().to_string()),
+    ]
+}
+
+/// The command with its placeholders filled in for this attempt, for --template.
+fn templated_command(
+    common: &CommonArguments,
+    values: &[(&str, String)],
+    sandbox: &Sandbox,
+    core_dumps: Option<&CoreDumps>,
+) -> Result<Command, io::Error> {
+    let arguments: Vec<String> = common
+        .command
+        .iter()
+        .map(|argument| fill_placeholders(argument, values))
+        .collect();
+    let mut command = build_command(&arguments);
+    confine(&mut command, sandbox, core_dumps)?;
+    Ok(command)
+}
+
+/// Run the last attempt in place of `attempt`, for --exec-last. This only returns if the command
+/// couldn't be run.
+#[cfg(unix)]
+fn exec_last(command: &mut Command, pid_file: Option<&PidFile>) -> io::Error {
+    info!("Running the last attempt in place of attempt");
+    if let Some(pid_file) = pid_file {
+        if let Err(e) = pid_file.write(std::process::id()) {
+            warn!("Failed to write the pid file: {}", e);
+        }
+    }
+    std::os::unix::process::CommandExt::exec(command)
+}
+
+/// A fresh temporary directory for the attempt, given to the command as ATTEMPT_TMPDIR, if
+/// --temp-dir was given.
+fn scratch_dir(
+    common: &CommonArguments,
+    command: &mut Command,
+    attempt: usize,
+) -> Result<Option<ScratchDir>, io::Error> {
+    if !common.temp_dir {
+        return Ok(None);
+    }
+    let scratch = ScratchDir::create(attempt)?;
+    command.env("ATTEMPT_TMPDIR", scratch.path());
+    Ok(Some(scratch))
+}
+
+/// Move the core dump of an attempt into the --collect-cores directory, if it was given.
+fn collect_core_dump(common: &CommonArguments, attempt: usize, pid: u32, started_at: SystemTime) {
+    let Some(dir) = &common.collect_cores else {
+        return;
+    };
+    match collect_core(dir, attempt, pid, started_at) {
+        Ok(path) => info!(
+            "Saved the core dump of attempt {} to {}",
+            attempt,
+            path.display()
+        ),
+        Err(e) => warn!(
+            "Failed to collect the core dump of attempt {}: {}",
+            attempt, e
+        ),
+    }
+}
+
+/// Why the run must end at once whatever the other conditions say, if it must: because output
+/// which decisions depend on was lost, or because a fatal condition held.
+fn fatal_reason(
+    common: &CommonArguments,
+    exit_status: ExitStatus,
+    output: &[u8],
+    lost_output: Option<String>,
+) -> Option<String> {
+    match lost_output {
+        Some(error) => Some(format!("output of the attempt was lost: {}", error)),
+        None => common.fatal_condition(exit_status.code(), output),
+    }
+}
+
+/// Record how a run made with --count went, and carry on unless it was the last run or the run
+/// was interrupted.
+fn count_run(
+    turn: &Turn,
+    exit_status: ExitStatus,
+    common: &CommonArguments,
+    history: &mut [AttemptRecord],
+    interrupted: &AtomicBool,
+    explain: Level,
+) -> Step {
+    let succeeded = common.succeeded(exit_status);
+    log!(
+        explain,
+        "{} ({})",
+        if succeeded { "Succeeded" } else { "Failed" },
+        exit_status
+    );
+    history[turn.number - 1].decision = if succeeded { "succeeded" } else { "failed" };
+    if interrupted.load(Ordering::SeqCst) {
+        Step::Done(Report::new(
+            Outcome::Interrupted,
+            "interrupted",
+            turn.number,
+            Some(exit_status),
+        ))
+    } else if turn.is_last() {
+        Step::Done(summarize_runs(history, common))
+    } else {
+        Step::Retry {
+            wait: turn.wait,
+            exit_status: Some(exit_status),
+        }
+    }
+}
+
+/// Why an attempt must be retried although the command's exit alone wouldn't say so, if it must.
+/// An attempt stopped by its health check is always retried, as is a command which exits
+/// successfully without producing the expected files or writing the expected output, whatever
+/// the retry conditions say about failed exits.
+fn unmet_condition(
+    common: &CommonArguments,
+    succeeded: bool,
+    unhealthy: Option<String>,
+    stdout: &[u8],
+    written: Written,
+) -> Option<String> {
+    if unhealthy.is_some() {
+        unhealthy
+    } else if common.until_failure || !succeeded {
+        None
+    } else if !checksum::verify_files(&common.success_if_file_matches) {
+        Some(String::from(
+            "--success-if-file-matches failed after a successful exit",
+        ))
+    } else if let Some(difference) = common
+        .expect_output_file
+        .as_deref()
+        .and_then(|path| expect::compare_output(path, stdout, common.expect_output_linewise))
+    {
+        Some(format!(
+            "--expect-output-file failed after a successful exit ({})",
+            difference
+        ))
+    } else {
+        common
+            .output_too_short(written)
+            .map(|condition| format!("{} held after a successful exit", condition))
+    }
+}
+
+/// Why the command has failed too often too quickly to carry on, if --start-limit was hit.
+fn start_limit_hit(common: &CommonArguments, history: &[AttemptRecord]) -> Option<String> {
+    let limit = common.start_limit.as_ref()?;
+    let ended = history
+        .iter()
+        .map(|record| record.started + record.duration);
+    limit.hit(ended, SystemTime::now()).then(|| {
+        format!(
+            "the command failed {} times within {}, hitting --start-limit",
+            limit.failures,
+            format_duration(limit.within)
+        )
+    })
+}
+
+/// How long to wait before retrying: as long as the command asked, or as --delay-for-status says
+/// for its exit code, or the scheduled wait, adapted to how long the attempt ran with --adaptive.
+fn retry_wait(
+    common: &CommonArguments,
+    scheduled: Duration,
+    exit_status: ExitStatus,
+    output: &[u8],
+    ran_for: Duration,
+) -> Duration {
+    if let Some(requested) = common.wait_from_output(output) {
+        debug!(
+            "Waiting {} as requested by the command",
+            format_duration(requested)
+        );
+        return requested;
+    }
+    match common.delay_for_status(exit_status.code()) {
+        Some((pattern, delay)) => {
+            debug!(
+                "Waiting {} as the exit code matched --delay-for-status {}",
+                format_duration(delay),
+                pattern
+            );
+            delay
+        }
+        None if common.wait_params.adaptive => {
+            let adapted = util::adapt_wait(scheduled, ran_for, common.wait_params);
+            debug!(
+                "Waiting {} rather than {}, as the command ran for {}",
+                format_duration(adapted),
+                format_duration(scheduled),
+                format_duration(ran_for)
+            );
+            adapted
+        }
+        None => scheduled,
+    }
+}
+
+/// Where an attempt falls in the schedule.
+struct Turn {
+    /// The number of the attempt, counting from 1.
+    number: usize,
+    /// How many attempts will be made, if there's a limit.
+    attempts: Option<usize>,
+    /// How long the schedule waits before the next attempt.
+    wait: Duration,
+    /// How the command exited on the previous attempt, if it ran.
+    last_exit: Option<ExitStatus>,
+}
+
+impl Turn {
+    /// Whether the schedule allows no more attempts after this one.
+    fn is_last(&self) -> bool {
+        Some(self.number) == self.attempts
+    }
+}
+
+/// What an attempt came to.
+enum Step {
+    /// No more attempts are to be made, and the run ended as reported.
+    Done(Report),
+    /// Another attempt is to be made after waiting, with the exit status of the attempt, if it
+    /// ran a command, to report if the run is interrupted before then.
+    Retry {
+        wait: Duration,
+        exit_status: Option<ExitStatus>,
+    },
+}
+
+/// Follow the schedule, making each attempt with `attempt` until it ends the run or the schedule
+/// is exhausted. Everything between the attempts is done here, whatever is being attempted: the
+/// delay before the first, the control socket, the conditions waited for before each one and the
+/// waits between them.
+fn run_schedule(
+    backoff: BackoffStrategy,
+    common: &CommonArguments,
+    status: &SharedStatus,
+    interrupted: &Arc<AtomicBool>,
+    mut attempt: impl FnMut(&Turn, &Waiter) -> Result<Step, io::Error>,
+) -> Result<Report, io::Error> {
+    let attempts = backoff.attempt_limit();
+    let waiter = Waiter::new(
+        common.skip_signal,
+        Arc::clone(interrupted),
+        attempts,
+        !common.no_progress,
+        common.simulate,
+        &common.watch,
+        common.heartbeat_file.as_deref(),
+    )?;
+    #[cfg(unix)]
+    let _control_socket = match &common.control_socket {
+        Some(path) => Some(control::ControlSocket::bind(
+            path,
+            control::Controls {
+                status: Arc::clone(status),
+                interrupted: Arc::clone(interrupted),
+                skip_requested: waiter.skip_requests(),
+            },
+        )?),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if common.control_socket.is_some() {
+        warn!("--control-socket is only supported on Unix, so it will be ignored");
+    }
+    let requirements = common.requirements();
+    let budget = common.budget();
+    let explain = explain_level(common);
+
+    // A fixed delay comes first, and any stagger is added on top of it.
+    let delays = [
+        ("Delaying", "delaying", common.initial_delay()),
+        ("Staggering", "staggering", common.stagger()),
+    ];
+    for (verb, doing, delay) in delays {
+        let Some(delay) = delay else { continue };
+        info!("{} the first attempt by {}", verb, format_duration(delay));
+        status.lock().unwrap().waiting(delay);
+        waiter.wait(delay, 1);
+        if interrupted.load(Ordering::SeqCst) {
+            return Ok(Report::new(
+                Outcome::Interrupted,
+                format!("interrupted while {}", doing),
+                0,
+                None,
+            ));
+        }
+    }
+
+    let mut last_exit: Option<ExitStatus> = None;
+    // More attempts can be allowed over the control socket, so the schedule mustn't run out.
+    let schedule = if common.control_socket.is_some() {
+        backoff.unlimited()
+    } else {
+        backoff.into_iter()
+    };
+    for (n, wait) in schedule.enumerate() {
+        let attempts = if common.control_socket.is_some() {
+            let attempts = status.lock().unwrap().attempt_limit();
+            waiter.set_attempt_limit(attempts);
+            attempts
+        } else {
+            attempts
+        };
+        logger::set_attempt(n + 1, attempts);
+        let stopped = deferred_by_gates(&requirements, interrupted)
+            .or_else(|| required_file_removed(common))
+            .or_else(|| wait_for_budget(budget.as_ref(), status, &waiter, interrupted, n + 1));
+        if let Some((outcome, reason)) = stopped {
+            return Ok(Report::new(outcome, reason, n, last_exit));
+        }
+        if let Some(elapsed) = waiter.simulated_elapsed() {
+            info!("Starting at {} of simulated time", format_duration(elapsed));
+        }
+        status.lock().unwrap().attempt_started();
+
+        let turn = Turn {
+            number: n + 1,
+            attempts,
+            wait,
+            last_exit,
+        };
+        let wait = match attempt(&turn, &waiter)? {
+            Step::Done(report) => return Ok(report),
+            Step::Retry { wait, exit_status } => {
+                last_exit = exit_status;
+                wait
+            }
+        };
+        log!(
+            explain,
+            "Next attempt in {}, at {}",
+            format_duration(wait),
+            format_clock_time(waiter.ends_at(wait))
+        );
+        status.lock().unwrap().waiting(wait);
+        waiter.wait(wait, n + 2);
+        if interrupted.load(Ordering::SeqCst) {
+            return Ok(Report::new(
+                Outcome::Interrupted,
+                "interrupted while waiting",
+                n + 1,
+                last_exit,
+            ));
+        }
+    }
+
+    Ok(Report::new(
+        Outcome::Failed,
+        "no attempts were made",
+        0,
+        None,
+    ))
+}
+
+/// Wait until the host provides what each attempt requires, such as free disk space or a network,
+/// reporting why the run must stop if it's interrupted while it waits.
+fn deferred_by_gates(
+    requirements: &[Requirement],
+    interrupted: &Arc<AtomicBool>,
+) -> Option<(Outcome, String)> {
+    (!gate::wait_until_met(requirements, interrupted)).then(|| {
+        (
+            Outcome::Interrupted,
+            String::from("interrupted while deferring"),
+        )
+    })
+}
+
+/// Why no more attempts can be made, if a file given with --require-file has been removed.
+fn required_file_removed(common: &CommonArguments) -> Option<(Outcome, String)> {
+    let path = common.missing_required_file()?;
+    warn!(
+        "{} no longer exists, so no more attempts will be made",
+        path.display()
+    );
+    Some((Outcome::Aborted, format!("{} was removed", path.display())))
+}
+
+/// Wait until the shared budget allows another attempt, reporting why the run must stop if
+/// it's interrupted while it waits. If the budget can't be used, attempts carry on without it
+/// rather than never being made again.
+fn wait_for_budget(
+    budget: Option<&Budget>,
+    status: &SharedStatus,
+    waiter: &Waiter,
+    interrupted: &Arc<AtomicBool>,
+    next_attempt: usize,
+) -> Option<(Outcome, String)> {
+    let budget = budget?;
+    let mut deferred = false;
+    loop {
+        match budget.take() {
+            Ok(None) => return None,
+            Ok(Some(wait)) => {
+                if !deferred {
+                    info!(
+                        "The shared budget in {} is used up, waiting",
+                        budget.path().display()
+                    );
+                    deferred = true;
+                }
+                status.lock().unwrap().waiting(wait);
+                waiter.wait(wait, next_attempt);
+                if interrupted.load(Ordering::SeqCst) {
+                    return Some((
+                        Outcome::Interrupted,
+                        String::from("interrupted while waiting for the shared budget"),
+                    ));
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to use the shared budget in {}: {}",
+                    budget.path().display(),
+                    e
+                );
+                return None;
+            }
+        }
+    }
+}
+
+/// How loudly to report why each attempt was or wasn't retried.
+fn explain_level(common: &CommonArguments) -> Level {
+    if common.explain {
+        Level::Info
+    } else {
+        Level::Debug
+    }
+}
+
+/// Print a summary of the runs made with --count, and report success only if all of them
+/// succeeded.
+fn summarize_runs(history: &[AttemptRecord], common: &CommonArguments) -> Report {
+    let mut summary = bench::Summary::default();
+    for record in history {
+        summary.record(
+            record.duration,
+            common.succeeded(record.exit_status),
+            record.exit_status.to_string(),
+        );
+    }
+    logger::write_message(summary.to_string());
+
+    let succeeded = history
+        .iter()
+        .filter(|record| record.decision == "succeeded")
+        .count();
+    let outcome = if succeeded == history.len() {
+        Outcome::Succeeded
+    } else {
+        Outcome::Failed
+    };
+    Report::new(
+        outcome,
+        format!("{} of {} runs succeeded", succeeded, history.len()),
+        history.len(),
+        history.last().map(|record| record.exit_status),
+    )
+}
+
+fn wait_for(args: WaitForArguments) -> Result<(), io::Error> {
+    let common = args.strategy.common().clone();
+    if !common.command.is_empty() {
+        ArgumentParser::command()
+            .error(
+                ErrorKind::UnknownArgument,
+                format!(
+                    "wait-for takes a target rather than a command, found '{}'",
+                    common.command.join(" ")
+                ),
+            )
+            .exit();
+    }
+    logger::init(
+        common.verbose,
+        common.quiet,
+        common.color,
+        &common.log_prefix,
+        common.log_fd,
+    )?;
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let status = Arc::new(Mutex::new(Status::new(
+        args.strategy.attempt_limit(),
+        args.strategy.describe(),
+    )));
+    handle_signals(
+        Arc::clone(&status),
+        Arc::clone(&interrupted),
+        true,
+        None,
+        None,
+    )?;
+
+    let result = probe(&args.target, args.strategy, &common, &status, &interrupted);
+    finish(result?, &common)
+}
+
+/// Check the target until it's ready or the schedule is exhausted, reporting how it ended.
+fn probe(
+    target: &Probe,
+    backoff: BackoffStrategy,
+    common: &CommonArguments,
+    status: &SharedStatus,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Report, io::Error> {
+    run_schedule(backoff, common, status, interrupted, |turn, waiter| {
+        waiter.heartbeat();
+        let reason = match target.check() {
+            Ok(()) => {
+                info!("{} is ready", target);
+                return Ok(Step::Done(Report::new(
+                    Outcome::Succeeded,
+                    format!("{} is ready", target),
+                    turn.number,
+                    None,
+                )));
+            }
+            Err(e) => {
+                info!("{} is not ready: {}", target, e);
+                format!("{} is not ready: {}", target, e)
+            }
+        };
+
+        if interrupted.load(Ordering::SeqCst) {
+            Ok(Step::Done(Report::new(
+                Outcome::Interrupted,
+                "interrupted",
+                turn.number,
+                None,
+            )))
+        } else if turn.is_last() {
+            warn!("{} did not become ready", target);
+            Ok(Step::Done(Report::new(
+                Outcome::Failed,
+                reason,
+                turn.number,
+                None,
+            )))
+        } else {
+            Ok(Step::Retry {
+                wait: turn.wait,
+                exit_status: None,
+            })
+        }
+    })
+}
+
+fn http(args: HttpArguments) -> Result<(), io::Error> {
+    let common = args.strategy.common().clone();
+    if !common.command.is_empty() {
+        ArgumentParser::command()
+            .error(
+                ErrorKind::UnknownArgument,
+                format!(
+                    "http takes a method and URL rather than a command, found '{}'",
+                    common.command.join(" ")
+                ),
+            )
+            .exit();
+    }
+    logger::init(
+        common.verbose,
+        common.quiet,
+        common.color,
+        &common.log_prefix,
+        common.log_fd,
+    )?;
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let status = Arc::new(Mutex::new(Status::new(
+        args.strategy.attempt_limit(),
+        args.strategy.describe(),
+    )));
+    handle_signals(
+        Arc::clone(&status),
+        Arc::clone(&interrupted),
+        true,
+        None,
+        None,
+    )?;
+
+    let request = http::Request {
+        method: args.method,
+        url: args.url,
+        headers: args.header,
+        body: args.data.map(String::into_bytes),
+    };
+    let result = request_until_expected(
+        &request,
+        &args.expect,
+        args.strategy,
+        &common,
+        &status,
+        &interrupted,
+    );
+    finish(result?, &common)
+}
+
+/// Make the request until it gets an expected response, printing its body, or until it gets a
+/// response that retrying won't change or the schedule is exhausted. Server errors and failed
+/// connections are retried, waiting as long as the server asks with Retry-After if it does.
+fn request_until_expected(
+    request: &http::Request,
+    expect: &http::StatusPattern,
+    backoff: BackoffStrategy,
+    common: &CommonArguments,
+    status: &SharedStatus,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Report, io::Error> {
+    let target = format!("{} {}", request.method, request.url);
+    run_schedule(backoff, common, status, interrupted, |turn, waiter| {
+        waiter.heartbeat();
+        let (reason, retry_after) = match request.send() {
+            Ok(response) if expect.matches(response.status) => {
+                info!("{} returned {}", target, response.status_line);
+                io::stdout().write_all(&response.body)?;
+                return Ok(Step::Done(Report::new(
+                    Outcome::Succeeded,
+                    format!("{} returned {}", target, response.status_line),
+                    turn.number,
+                    None,
+                )));
+            }
+            Ok(response) if response.is_retryable() => {
+                info!("{} returned {}", target, response.status_line);
+                (
+                    format!("{} returned {}", target, response.status_line),
+                    response.retry_after,
+                )
+            }
+            Ok(response) => {
+                warn!(
+                    "{} returned {}, which isn't retried",
+                    target, response.status_line
+                );
+                return Ok(Step::Done(Report::new(
+                    Outcome::Failed,
+                    format!("{} returned {}", target, response.status_line),
+                    turn.number,
+                    None,
+                )));
+            }
+            Err(e) => {
+                info!("{} failed: {}", target, e);
+                (format!("{} failed: {}", target, e), None)
+            }
+        };
+
+        if interrupted.load(Ordering::SeqCst) {
+            return Ok(Step::Done(Report::new(
+                Outcome::Interrupted,
+                "interrupted",
+                turn.number,
+                None,
+            )));
+        } else if turn.is_last() {
+            warn!("{} never returned {}", target, expect);
+            return Ok(Step::Done(Report::new(
+                Outcome::Failed,
+                reason,
+                turn.number,
+                None,
+            )));
+        }
+
+        let wait = match retry_after {
+            Some(retry_after) => {
+                let retry_after = match common.wait_params.wait_max.and_then(duration_from_f64) {
+                    Some(wait_max) => retry_after.min(wait_max),
+                    None => retry_after,
+                };
+                debug!(
+                    "Waiting {} as the server asked",
+                    format_duration(retry_after)
+                );
+                retry_after
+            }
+            None => turn.wait,
+        };
+        Ok(Step::Retry {
+            wait,
+            exit_status: None,
+        })
+    })
 }
@@ -0,0 +1,47 @@
+use std::{fs, io, path::PathBuf};
+
+/// A file holding the PID of the command currently being attempted, so that external tools can
+/// find it.
+#[derive(Debug, Clone)]
+pub(crate) struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Replace the contents of the file with the given PID. The file is written to a temporary
+    /// path first and renamed into place, so readers never see a partial write.
+    pub(crate) fn write(&self, pid: u32) -> Result<(), io::Error> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        fs::write(&temp_path, format!("{}\n", pid))?;
+        fs::rename(&temp_path, &self.path)
+    }
+
+    pub(crate) fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_and_remove() {
+        let path = std::env::temp_dir().join(format!("attempt-test-{}.pid", std::process::id()));
+        let pid_file = PidFile::new(path.clone());
+
+        pid_file.write(1234).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1234\n");
+        pid_file.write(5678).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "5678\n");
+
+        pid_file.remove();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    io,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    time::SystemTime,
+};
+
+use clap::{CommandFactory, ErrorKind};
+use log::{info, warn};
+
+use crate::{
+    arguments::{ArgumentParser, BackoffStrategy, PipelineArguments},
+    history, logger,
+    report::{Outcome, Report},
+    scratch::ScratchDir,
+    signals::handle_signals,
+    status::Status,
+    util::shell_command,
+};
+
+/// Run a shell pipeline one stage at a time, retrying only the stage which failed.
+///
+/// Each stage's output is written to a temporary file which the next stage reads, so that a
+/// retried stage gets the same input as the first attempt at it did. The stages therefore run one
+/// after another rather than all at once. The last stage writes to stdout as usual.
+pub(crate) fn pipeline(args: PipelineArguments) -> Result<(), io::Error> {
+    let common = args.strategy.common().clone();
+    if !common.command.is_empty() {
+        ArgumentParser::command()
+            .error(
+                ErrorKind::UnknownArgument,
+                format!(
+                    "pipeline takes its stages as a single argument, found '{}'",
+                    common.command.join(" ")
+                ),
+            )
+            .exit();
+    }
+    let stages = split_stages(&args.pipeline).unwrap_or_else(|e| {
+        ArgumentParser::command()
+            .error(ErrorKind::InvalidValue, e)
+            .exit()
+    });
+    let named = args.stage_attempts.iter().map(|(stage, _)| stage);
+    if let Some(stage) = named
+        .chain(args.stage_wait.iter().map(|(stage, _)| stage))
+        .find(|&&stage| stage > stages.len())
+    {
+        ArgumentParser::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!(
+                    "there is no stage {}, as the pipeline has {} stages numbered from 1",
+                    stage,
+                    stages.len()
+                ),
+            )
+            .exit();
+    }
+    logger::init(
+        common.verbose,
+        common.quiet,
+        common.color,
+        &common.log_prefix,
+        common.log_fd,
+    )?;
+    if common.pid_file.is_some() || common.exec_last || common.control_socket.is_some() {
+        warn!("--pid-file, --exec-last and --control-socket are ignored in pipeline mode");
+    }
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let status = Arc::new(Mutex::new(Status::new(
+        args.strategy.attempt_limit(),
+        args.strategy.describe(),
+    )));
+    handle_signals(
+        Arc::clone(&status),
+        Arc::clone(&interrupted),
+        !common.no_kill_on_exit,
+        None,
+        None,
+    )?;
+
+    let dir = ScratchDir::create_named("pipeline")?;
+    let mut result = Ok(Report::new(Outcome::Failed, "no stages were run", 0, None));
+    for (i, stage) in stages.iter().enumerate() {
+        let backoff = stage_strategy(
+            &args,
+            i + 1,
+            &stage_command(dir.path(), i, stages.len(), stage),
+        );
+        let stage_common = backoff.common().clone();
+        *status.lock().unwrap() = Status::new(backoff.attempt_limit(), backoff.describe());
+        info!("Running stage {} of {}: `{}`", i + 1, stages.len(), stage);
+        let started = SystemTime::now();
+        result = crate::attempt(backoff, &stage_common, &status, &interrupted, None);
+        logger::set_attempt(0, None);
+        let Ok(report) = &mut result else { break };
+        if let Some(path) = &common.history_db {
+            let path = path.clone().unwrap_or_else(history::default_path);
+            if let Err(e) = history::record(&path, stage, started, report) {
+                warn!("Failed to record the stage in {}: {}", path.display(), e);
+            }
+        }
+
+        if report.outcome != Outcome::Succeeded {
+            warn!(
+                "Stage {} of {} ended the pipeline ({}): `{}`",
+                i + 1,
+                stages.len(),
+                report.outcome.name(),
+                stage
+            );
+            report.reason = format!("stage {} (`{}`): {}", i + 1, stage, report.reason);
+            break;
+        } else if i + 1 == stages.len() {
+            report.reason = format!("all {} stages succeeded", stages.len());
+        }
+    }
+    // `finish` exits, so the directory wouldn't be removed when it went out of scope.
+    drop(dir);
+
+    crate::finish(result?, &common)
+}
+
+/// The schedule for a stage, following --stage-attempts and --stage-wait if they name it.
+fn stage_strategy(args: &PipelineArguments, stage: usize, command: &str) -> BackoffStrategy {
+    let mut backoff = args.strategy.clone();
+    if let Some((_, wait)) = args.stage_wait.iter().rfind(|(n, _)| *n == stage) {
+        backoff = BackoffStrategy::Fixed {
+            wait: wait.as_secs_f64(),
+            common: backoff.common().clone(),
+        };
+    }
+    let common = backoff.common_mut();
+    if let Some((_, attempts)) = args.stage_attempts.iter().rfind(|(n, _)| *n == stage) {
+        common.attempts = *attempts;
+    }
+    common.command = shell_command(command);
+    common.exec_last = false;
+    common.pid_file = None;
+    common.control_socket = None;
+    backoff
+}
+
+/// The shell command for the stage with index `i`, reading the previous stage's output and
+/// writing its own to a file, except for the first stage's input and the last stage's output.
+fn stage_command(dir: &Path, i: usize, stages: usize, stage: &str) -> String {
+    let output = |i: usize| quote(&dir.join(format!("stage-{}.out", i + 1)));
+    let mut command = format!("( {} )", stage);
+    if i > 0 {
+        command.push_str(&format!(" < {}", output(i - 1)));
+    }
+    if i + 1 < stages {
+        command.push_str(&format!(" > {}", output(i)));
+    }
+    command
+}
+
+#[cfg(unix)]
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+#[cfg(not(unix))]
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.display())
+}
+
+/// Split a pipeline into its stages at each `|` which isn't quoted, escaped, part of `||`, or
+/// inside parentheses, `$(...)` or backticks.
+fn split_stages(pipeline: &str) -> Result<Vec<String>, String> {
+    let mut stages = Vec::new();
+    let mut stage = String::new();
+    // The quotes, backticks and parentheses which are open, innermost last.
+    let mut open = Vec::new();
+    let mut chars = pipeline.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, open.last().copied()) {
+            ('\'', Some('\'')) | ('"', Some('"')) | ('`', Some('`')) | (')', Some('(')) => {
+                open.pop();
+            }
+            (_, Some('\'')) => (),
+            ('\\', _) => {
+                stage.push(c);
+                if let Some(escaped) = chars.next() {
+                    stage.push(escaped);
+                }
+                continue;
+            }
+            ('`', _) => open.push(c),
+            // Only substitutions are special within double quotes.
+            ('(', Some('"')) if stage.ends_with('$') => open.push(c),
+            (_, Some('"')) => (),
+            ('\'' | '"' | '(', _) => open.push(c),
+            ('|', None) if chars.peek() == Some(&'|') => {
+                stage.push(c);
+                chars.next();
+            }
+            ('|', None) => {
+                stages.push(std::mem::take(&mut stage));
+                continue;
+            }
+            _ => (),
+        }
+        stage.push(c);
+    }
+    match open.last() {
+        Some('(') => return Err(format!("{} has an unclosed parenthesis", pipeline)),
+        Some('`') => return Err(format!("{} has an unterminated backtick", pipeline)),
+        Some(_) => return Err(format!("{} has an unterminated quote", pipeline)),
+        None => stages.push(stage),
+    }
+
+    let stages: Vec<String> = stages
+        .into_iter()
+        .map(|stage| stage.trim().to_string())
+        .collect();
+    if stages.iter().any(String::is_empty) {
+        return Err(format!("{} has an empty stage", pipeline));
+    }
+    Ok(stages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arguments::{ArgumentParser, Mode};
+    use clap::Parser;
+
+    #[test]
+    fn test_split_stages() {
+        assert_eq!(
+            split_stages("make dist | gzip -9 |./upload.sh").unwrap(),
+            ["make dist", "gzip -9", "./upload.sh"]
+        );
+        assert_eq!(
+            split_stages(r#"grep 'a|b' | tr "|" \| | test -s x || echo empty"#).unwrap(),
+            [r#"grep 'a|b'"#, r#"tr "|" \|"#, "test -s x || echo empty"]
+        );
+        assert_eq!(
+            split_stages("echo $(a | b) | c").unwrap(),
+            ["echo $(a | b)", "c"]
+        );
+        assert_eq!(
+            split_stages("echo `a | b` | c").unwrap(),
+            ["echo `a | b`", "c"]
+        );
+        assert_eq!(
+            split_stages(r#"echo "$(tr '|' x <<< "a|b" | rev)" | (cat | wc) | c"#).unwrap(),
+            [r#"echo "$(tr '|' x <<< "a|b" | rev)""#, "(cat | wc)", "c"]
+        );
+        assert!(split_stages("a | | b").is_err());
+        assert!(split_stages("echo 'a | b").is_err());
+        assert!(split_stages("echo $(a | b").is_err());
+        assert!(split_stages("echo `a | b").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stage_command() {
+        let dir = Path::new("/tmp/attempt-pipeline-1");
+        assert_eq!(
+            stage_command(dir, 0, 3, "make dist"),
+            "( make dist ) > '/tmp/attempt-pipeline-1/stage-1.out'"
+        );
+        assert_eq!(
+            stage_command(dir, 1, 3, "gzip"),
+            "( gzip ) < '/tmp/attempt-pipeline-1/stage-1.out' > '/tmp/attempt-pipeline-1/stage-2.out'"
+        );
+        assert_eq!(
+            stage_command(dir, 2, 3, "./upload.sh"),
+            "( ./upload.sh ) < '/tmp/attempt-pipeline-1/stage-2.out'"
+        );
+    }
+
+    #[test]
+    fn test_stage_strategy() {
+        let arguments = ArgumentParser::try_parse_from([
+            "attempt",
+            "pipeline",
+            "a | b | c",
+            "--stage-attempts",
+            "3=10",
+            "--stage-wait",
+            "2=5s",
+            "exponential",
+            "-a",
+            "4",
+        ])
+        .unwrap();
+        let Mode::Pipeline(args) = arguments.mode else {
+            panic!("expected pipeline mode");
+        };
+
+        let first = stage_strategy(&args, 1, "a");
+        assert!(matches!(first, BackoffStrategy::Exponential { .. }));
+        assert_eq!(first.attempt_limit(), Some(4));
+        assert_eq!(first.common().command, shell_command("a"));
+        assert!(matches!(
+            stage_strategy(&args, 2, "b"),
+            BackoffStrategy::Fixed { wait, .. } if wait == 5.0
+        ));
+        assert_eq!(stage_strategy(&args, 3, "c").attempt_limit(), Some(10));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// How long a probe waits to connect, or for a response, before giving up.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A built-in readiness check for a service, used by `attempt wait-for` in place of a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Probe {
+    /// Ready once a TCP connection can be made to the address.
+    Tcp { address: String },
+    /// Ready once a connection can be made to the Unix socket.
+    Unix { path: PathBuf },
+    /// Ready once a GET request to the path gets a 2xx or 3xx response.
+    Http { authority: String, path: String },
+}
+
+impl FromStr for Probe {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = target
+            .split_once("://")
+            .ok_or_else(|| format!("{} is not a URL", target))?;
+        match scheme {
+            "tcp" => Ok(Self::Tcp {
+                address: rest.trim_end_matches('/').to_string(),
+            }),
+            "unix" => Ok(Self::Unix {
+                path: PathBuf::from(rest),
+            }),
+            "http" => {
+                let (authority, path) = match rest.find('/') {
+                    Some(i) => rest.split_at(i),
+                    None => (rest, "/"),
+                };
+                Ok(Self::Http {
+                    authority: authority.to_string(),
+                    path: path.to_string(),
+                })
+            }
+            _ => Err(format!(
+                "{}:// is not supported, use tcp://, unix:// or http://",
+                scheme
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { address } => write!(f, "tcp://{}", address),
+            Self::Unix { path } => write!(f, "unix://{}", path.display()),
+            Self::Http { authority, path } => write!(f, "http://{}{}", authority, path),
+        }
+    }
+}
+
+impl Probe {
+    /// Check whether the service is ready, returning why not if it isn't.
+    pub(crate) fn check(&self) -> Result<(), io::Error> {
+        match self {
+            Self::Tcp { address } => connect(address, PROBE_TIMEOUT).map(drop),
+            Self::Unix { path } => connect_unix(path),
+            Self::Http { authority, path } => {
+                let address = if authority.contains(':') {
+                    authority.clone()
+                } else {
+                    format!("{}:80", authority)
+                };
+                let mut stream = connect(&address, PROBE_TIMEOUT)?;
+                stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+                let request = format!(
+                    "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                    path, authority
+                );
+                stream.write_all(request.as_bytes())?;
+
+                let mut status_line = String::new();
+                BufReader::new(stream).read_line(&mut status_line)?;
+                match status_line.split_whitespace().nth(1).map(str::parse::<u16>) {
+                    Some(Ok(200..=399)) => Ok(()),
+                    _ => Err(io::Error::other(format!(
+                        "responded with {:?}",
+                        status_line.trim()
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+/// Connect to the first of the address's resolved addresses which accepts a connection within
+/// `timeout`.
+pub(crate) fn connect(address: &str, timeout: Duration) -> Result<TcpStream, io::Error> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+
+    Err(error)
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &std::path::Path) -> Result<(), io::Error> {
+    std::os::unix::net::UnixStream::connect(path).map(drop)
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &std::path::Path) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix:// is only supported on Unix",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "tcp://db:5432".parse(),
+            Ok(Probe::Tcp {
+                address: String::from("db:5432")
+            })
+        );
+        assert_eq!(
+            "unix:///run/foo.sock".parse(),
+            Ok(Probe::Unix {
+                path: PathBuf::from("/run/foo.sock")
+            })
+        );
+        assert_eq!(
+            "http://localhost:8080/healthz".parse(),
+            Ok(Probe::Http {
+                authority: String::from("localhost:8080"),
+                path: String::from("/healthz")
+            })
+        );
+        assert_eq!(
+            "http://localhost".parse::<Probe>().unwrap().to_string(),
+            "http://localhost/"
+        );
+        assert!("db:5432".parse::<Probe>().is_err());
+        assert!("https://example.com".parse::<Probe>().is_err());
+    }
+
+    #[test]
+    fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let probe = Probe::Tcp {
+            address: listener.local_addr().unwrap().to_string(),
+        };
+        assert!(probe.check().is_ok());
+
+        drop(listener);
+        assert!(probe.check().is_err());
+    }
+
+    #[test]
+    fn test_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let probe = Probe::Http {
+            authority: listener.local_addr().unwrap().to_string(),
+            path: String::from("/healthz"),
+        };
+        let server = thread::spawn(move || {
+            for response in [
+                "HTTP/1.0 503 Service Unavailable",
+                "HTTP/1.0 204 No Content",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                assert_eq!(request_line, "GET /healthz HTTP/1.0\r\n");
+                write!(stream, "{}\r\n\r\n", response).unwrap();
+            }
+        });
+
+        assert!(probe.check().is_err());
+        assert!(probe.check().is_ok());
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix() {
+        let path = std::env::temp_dir().join(format!("attempt-test-{}.sock", std::process::id()));
+        let probe = Probe::Unix { path: path.clone() };
+        assert!(probe.check().is_err());
+
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(probe.check().is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs::File,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    process::{Child, Command, Stdio},
+    ptr,
+};
+
+/// Spawn the command with its stdout and stderr attached to a new pseudo-terminal, so that it
+/// behaves as it would when run interactively. Everything it writes can be read from the returned
+/// master end of the terminal, until the command and any children holding the terminal open have
+/// exited.
+pub(crate) fn spawn_with_pty(command: &mut Command) -> Result<(Child, File), io::Error> {
+    let (master, slave) = open()?;
+    command.stdout(slave.try_clone()?).stderr(slave);
+    let child = command.spawn();
+    // Close our handles on the terminal, so that reading from it ends when the command exits.
+    command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+
+    Ok((child?, master))
+}
+
+/// Open a pseudo-terminal sized to match ours, returning the master and slave ends.
+fn open() -> Result<(File, OwnedFd), io::Error> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let has_size = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+
+    let (mut master, mut slave) = (0, 0);
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            if has_size { &size } else { ptr::null() },
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    // Don't translate "\n" into "\r\n", since the output may well be going to a file.
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    unsafe {
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+            termios.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+    }
+
+    Ok((master, slave))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_command_sees_a_terminal() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "test -t 1 && test -t 2 && echo ok"]);
+        let (mut child, mut terminal) = spawn_with_pty(&mut command).unwrap();
+        assert!(child.wait().unwrap().success());
+
+        let mut output = Vec::new();
+        let _ = terminal.read_to_end(&mut output);
+        assert_eq!(output, b"ok\n");
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    fmt::Write,
+    fs, io,
+    path::Path,
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
+
+use crate::{child::ResourceUsage, spill::SpilledBytes, util::format_timestamp};
+
+/// The conventional status code for a process stopped by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+/// The status code used when a file required by --require-file was removed, so that it can be
+/// told apart from the command failing.
+const EXIT_ABORTED: i32 = 3;
+/// The status code used when --abort-if-matches or --abort-if-status found an unrecoverable
+/// error, so that callers can tell it apart from running out of attempts.
+const EXIT_FATAL: i32 = 4;
+/// The status code used when the command failed too often in a short time for --start-limit.
+const EXIT_START_LIMIT: i32 = 5;
+/// How much of each attempt's output to keep for reports, from the end of the output.
+const OUTPUT_TAIL: usize = 64 * 1024;
+
+/// How a run of `attempt` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Succeeded,
+    Failed,
+    Interrupted,
+    /// Stopped because a file required by --require-file was removed.
+    Aborted,
+    /// Stopped at once because of an unrecoverable error.
+    Fatal,
+    /// Gave up because the command failed too often in a short time.
+    StartLimitHit,
+}
+
+impl Outcome {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed => "failed",
+            Outcome::Interrupted => "interrupted",
+            Outcome::Aborted => "aborted",
+            Outcome::Fatal => "fatal",
+            Outcome::StartLimitHit => "start-limit-hit",
+        }
+    }
+}
+
+/// A summary of a run of `attempt`: how it ended, why, and the last command's exit status.
+#[derive(Debug)]
+pub(crate) struct Report {
+    pub outcome: Outcome,
+    /// What ended the run, e.g. the retry condition which didn't hold.
+    pub reason: String,
+    pub attempts: usize,
+    pub exit_status: Option<ExitStatus>,
+    /// The capture groups of the --retry-if-output-matches pattern which decided the outcome.
+    pub groups: Vec<String>,
+    /// How each attempt went, in order.
+    pub history: Vec<AttemptRecord>,
+    /// The output of the last attempt, if it was kept for --cronic.
+    pub output: SpilledBytes,
+}
+
+/// How a single attempt went.
+#[derive(Debug)]
+pub(crate) struct AttemptRecord {
+    pub number: usize,
+    pub started: SystemTime,
+    pub duration: Duration,
+    pub exit_status: ExitStatus,
+    /// What was done after the attempt: "succeeded", "retried", "stopped", "gave up",
+    /// "aborted" or "interrupted". Runs with --count either "succeeded" or "failed".
+    pub decision: &'static str,
+    /// The end of the attempt's output, if it was kept for a report.
+    pub output: Option<String>,
+    /// What the attempt used, if the platform reports it.
+    pub usage: Option<ResourceUsage>,
+    /// Why the attempt was retried, or why it ended the run if it failed.
+    pub reason: Option<String>,
+}
+
+impl AttemptRecord {
+    /// The end of the output, as much as reports include.
+    pub(crate) fn output_tail(output: &[u8]) -> String {
+        String::from_utf8_lossy(&output[output.len().saturating_sub(OUTPUT_TAIL)..]).into_owned()
+    }
+}
+
+impl Report {
+    pub(crate) fn new(
+        outcome: Outcome,
+        reason: impl Into<String>,
+        attempts: usize,
+        exit_status: Option<ExitStatus>,
+    ) -> Self {
+        Self {
+            outcome,
+            reason: reason.into(),
+            attempts,
+            exit_status,
+            groups: Vec::new(),
+            history: Vec::new(),
+            output: SpilledBytes::default(),
+        }
+    }
+
+    pub(crate) fn with_history(self, history: Vec<AttemptRecord>) -> Self {
+        Self { history, ..self }
+    }
+
+    pub(crate) fn with_output(self, output: SpilledBytes) -> Self {
+        Self { output, ..self }
+    }
+
+    pub(crate) fn with_groups(self, groups: Vec<String>) -> Self {
+        Self { groups, ..self }
+    }
+
+    /// The status code `attempt` should exit with.
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self.outcome {
+            Outcome::Succeeded => 0,
+            Outcome::Failed => 1,
+            Outcome::Interrupted => EXIT_INTERRUPTED,
+            Outcome::Aborted => EXIT_ABORTED,
+            Outcome::Fatal => EXIT_FATAL,
+            Outcome::StartLimitHit => EXIT_START_LIMIT,
+        }
+    }
+
+    /// The report as a single JSON object. `timed_out` is always false, as attempts don't time
+    /// out yet, but is included so that readers can rely on it. `match_groups` is empty unless a
+    /// pattern with capture groups decided the outcome. `resource_usage` is what the last attempt
+    /// used, or null if that isn't known.
+    pub(crate) fn to_json(&self) -> String {
+        let code = self.exit_status.and_then(|status| status.code());
+        format!(
+            "{{\"outcome\":\"{}\",\"reason\":{},\"attempts\":{},\"exit_code\":{},\"signal\":{},\"timed_out\":false,\"match_groups\":[{}],\"resource_usage\":{}}}",
+            self.outcome.name(),
+            json_string(&self.reason),
+            self.attempts,
+            json_number(code),
+            json_number(self.exit_status.and_then(signal)),
+            self.groups
+                .iter()
+                .map(|group| json_string(group))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.history
+                .last()
+                .and_then(|record| record.usage)
+                .map_or(String::from("null"), |usage| json_usage(&usage)),
+        )
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<(), io::Error> {
+        fs::write(path, format!("{}\n", self.to_json()))
+    }
+
+    /// The attempts as a JUnit XML test suite, with a test case for each attempt. Attempts which
+    /// didn't succeed are reported as failures.
+    pub(crate) fn to_junit(&self, command: &str) -> String {
+        let failures = self
+            .history
+            .iter()
+            .filter(|record| record.decision != "succeeded")
+            .count();
+        let total: Duration = self.history.iter().map(|record| record.duration).sum();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuite name=\"attempt\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+            self.history.len(),
+            failures,
+            total.as_secs_f64()
+        );
+        for record in &self.history {
+            let _ = writeln!(
+                xml,
+                "  <testcase name=\"attempt {}\" classname=\"{}\" time=\"{:.3}\">",
+                record.number,
+                xml_escape(command),
+                record.duration.as_secs_f64()
+            );
+            if record.decision != "succeeded" {
+                let _ = writeln!(
+                    xml,
+                    "    <failure message=\"{}\">{}</failure>",
+                    xml_escape(&record.exit_status.to_string()),
+                    record.decision
+                );
+            }
+            if let Some(output) = &record.output {
+                let _ = writeln!(xml, "    <system-out>{}</system-out>", xml_escape(output));
+            }
+            xml.push_str("  </testcase>\n");
+        }
+        xml.push_str("</testsuite>\n");
+
+        xml
+    }
+
+    /// The attempts as CSV, with a header and a row for each attempt.
+    pub(crate) fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("attempt,timestamp,duration_secs,exit_code,signal,timed_out,decision\n");
+        for record in &self.history {
+            let _ = writeln!(
+                csv,
+                "{},{},{:.3},{},{},false,{}",
+                record.number,
+                format_timestamp(record.started),
+                record.duration.as_secs_f64(),
+                record
+                    .exit_status
+                    .code()
+                    .map_or(String::new(), |code| code.to_string()),
+                signal(record.exit_status).map_or(String::new(), |signal| signal.to_string()),
+                record.decision
+            );
+        }
+
+        csv
+    }
+
+    pub(crate) fn write_csv(&self, path: &Path) -> Result<(), io::Error> {
+        fs::write(path, self.to_csv())
+    }
+
+    pub(crate) fn write_junit(&self, path: &Path, command: &str) -> Result<(), io::Error> {
+        fs::write(path, self.to_junit(command))
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn signal(status: ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(&status)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Escape text for XML, dropping control characters which XML 1.0 can't represent.
+fn xml_escape(text: &str) -> String {
+    let mut xml = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '&' => xml.push_str("&amp;"),
+            '"' => xml.push_str("&quot;"),
+            '\n' | '\r' | '\t' => xml.push(c),
+            c if c.is_control() => (),
+            c => xml.push(c),
+        }
+    }
+    xml
+}
+
+fn json_usage(usage: &ResourceUsage) -> String {
+    format!(
+        "{{\"max_rss_bytes\":{},\"user_cpu_secs\":{:.3},\"system_cpu_secs\":{:.3},\"major_page_faults\":{},\"minor_page_faults\":{}}}",
+        usage.max_rss,
+        usage.user_time.as_secs_f64(),
+        usage.system_time.as_secs_f64(),
+        usage.major_faults,
+        usage.minor_faults
+    )
+}
+
+fn json_number(number: Option<i32>) -> String {
+    number.map_or(String::from("null"), |number| number.to_string())
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let report = Report::new(Outcome::Interrupted, "interrupted", 2, None);
+        assert_eq!(report.exit_code(), 130);
+        assert_eq!(
+            report.to_json(),
+            r#"{"outcome":"interrupted","reason":"interrupted","attempts":2,"exit_code":null,"signal":null,"timed_out":false,"match_groups":[],"resource_usage":null}"#
+        );
+    }
+
+    #[test]
+    fn test_aborted() {
+        let report = Report::new(Outcome::Aborted, "/run/deploy.token was removed", 1, None);
+        assert_eq!(report.exit_code(), 3);
+        assert!(report
+            .to_json()
+            .starts_with(r#"{"outcome":"aborted","reason":"/run/deploy.token was removed""#));
+    }
+
+    #[test]
+    fn test_fatal() {
+        let report = Report::new(Outcome::Fatal, "", 1, None);
+        assert_eq!(report.exit_code(), 4);
+        assert!(report.to_json().starts_with(r#"{"outcome":"fatal""#));
+    }
+
+    #[test]
+    fn test_start_limit_hit() {
+        let report = Report::new(Outcome::StartLimitHit, "", 5, None);
+        assert_eq!(report.exit_code(), 5);
+        assert!(report
+            .to_json()
+            .starts_with(r#"{"outcome":"start-limit-hit""#));
+    }
+
+    #[test]
+    fn test_json_with_groups() {
+        let report = Report::new(Outcome::Failed, "", 3, None)
+            .with_groups(vec![String::from("E1234"), String::new()]);
+        assert!(report.to_json().contains(r#""match_groups":["E1234",""],"#));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_json_with_exit_status() {
+        use std::process::Command;
+
+        let status = Command::new("sh").args(["-c", "exit 3"]).status().unwrap();
+        let report = Report::new(Outcome::Failed, "pattern \"x\"\n", 3, Some(status));
+        assert_eq!(report.exit_code(), 1);
+        assert_eq!(
+            report.to_json(),
+            r#"{"outcome":"failed","reason":"pattern \"x\"\n","attempts":3,"exit_code":3,"signal":null,"timed_out":false,"match_groups":[],"resource_usage":null}"#
+        );
+
+        let status = Command::new("sh")
+            .args(["-c", "kill -9 $$"])
+            .status()
+            .unwrap();
+        let report = Report::new(Outcome::Failed, "", 1, Some(status));
+        assert!(report.to_json().contains(r#""exit_code":null,"signal":9"#));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_json_with_resource_usage() {
+        use std::process::Command;
+
+        let status = Command::new("true").status().unwrap();
+        let report = Report::new(Outcome::Succeeded, "", 1, Some(status)).with_history(vec![
+            AttemptRecord {
+                number: 1,
+                started: SystemTime::now(),
+                duration: Duration::from_secs(2),
+                exit_status: status,
+                decision: "succeeded",
+                output: None,
+                usage: Some(ResourceUsage {
+                    max_rss: 12_582_912,
+                    user_time: Duration::from_millis(1250),
+                    system_time: Duration::from_millis(40),
+                    major_faults: 2,
+                    minor_faults: 3100,
+                }),
+                reason: None,
+            },
+        ]);
+        assert!(report.to_json().ends_with(
+            r#""resource_usage":{"max_rss_bytes":12582912,"user_cpu_secs":1.250,"system_cpu_secs":0.040,"major_page_faults":2,"minor_page_faults":3100}}"#
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_junit() {
+        use std::process::Command;
+
+        let record = |number, code: i32, decision| AttemptRecord {
+            number,
+            started: SystemTime::now(),
+            duration: Duration::from_millis(1500),
+            exit_status: Command::new("sh")
+                .args(["-c", &format!("exit {}", code)])
+                .status()
+                .unwrap(),
+            decision,
+            output: Some(String::from("error: <timeout>\x1b[0m\n")),
+            usage: None,
+            reason: None,
+        };
+        let report = Report::new(Outcome::Succeeded, "", 2, None)
+            .with_history(vec![record(1, 1, "retried"), record(2, 0, "succeeded")]);
+        assert_eq!(
+            report.to_junit("curl -f \"https://example.com\""),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="attempt" tests="2" failures="1" time="3.000">
+  <testcase name="attempt 1" classname="curl -f &quot;https://example.com&quot;" time="1.500">
+    <failure message="exit status: 1">retried</failure>
+    <system-out>error: &lt;timeout&gt;[0m
+</system-out>
+  </testcase>
+  <testcase name="attempt 2" classname="curl -f &quot;https://example.com&quot;" time="1.500">
+    <system-out>error: &lt;timeout&gt;[0m
+</system-out>
+  </testcase>
+</testsuite>
+"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_csv() {
+        use std::{process::Command, time::UNIX_EPOCH};
+
+        let status = |script| Command::new("sh").args(["-c", script]).status().unwrap();
+        let record = |number, exit_status, decision| AttemptRecord {
+            number,
+            started: UNIX_EPOCH + Duration::from_secs(1_662_316_200),
+            duration: Duration::from_millis(250),
+            exit_status,
+            decision,
+            output: None,
+            usage: None,
+            reason: None,
+        };
+        let report = Report::new(Outcome::Failed, "", 2, None).with_history(vec![
+            record(1, status("exit 75"), "retried"),
+            record(2, status("kill -9 $$"), "gave up"),
+        ]);
+        assert_eq!(
+            report.to_csv(),
+            "attempt,timestamp,duration_secs,exit_code,signal,timed_out,decision\n\
+             1,2022-09-04T18:30:00.000Z,0.250,75,,false,retried\n\
+             2,2022-09-04T18:30:00.000Z,0.250,,9,false,gave up\n"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::{ffi::CString, fs, io, path::Path, process::Command, sync::Arc};
+
+/// A single instruction of a classic BPF program, laid out like the kernel's `sock_filter`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Instruction {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// The size of an instruction in a compiled BPF program.
+const INSTRUCTION_SIZE: usize = 8;
+/// The most instructions the kernel accepts in a single filter.
+const MAX_INSTRUCTIONS: usize = 4096;
+
+/// Restrictions applied to the command between fork and exec, so that commands which are run
+/// over and over get some defense in depth.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sandbox {
+    /// Set `no_new_privs`, so the command can't gain privileges through setuid binaries or file
+    /// capabilities.
+    pub no_new_privileges: bool,
+    /// Remount the root filesystem read-only in a mount namespace of the command's own.
+    pub read_only_root: bool,
+    /// Mount an empty tmpfs on /tmp in a mount namespace of the command's own.
+    pub private_tmp: bool,
+    /// Run the command in a network namespace of its own, with only a loopback interface.
+    pub private_network: bool,
+    /// Change the command's root directory to this one.
+    pub chroot: Option<CString>,
+    /// The directory to start the command in, within the new root. Defaults to the new root.
+    pub chdir: Option<CString>,
+    /// A seccomp filter to install just before exec.
+    pub seccomp_filter: Option<Arc<[Instruction]>>,
+}
+
+impl Sandbox {
+    fn is_empty(&self) -> bool {
+        !self.no_new_privileges
+            && !self.read_only_root
+            && !self.private_tmp
+            && !self.private_network
+            && self.chroot.is_none()
+            && self.seccomp_filter.is_none()
+    }
+
+    /// Arrange for the command to be sandboxed when it's spawned. Must only be called once for
+    /// each command, as the restrictions would otherwise be applied twice.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn apply(&self, command: &mut Command) -> Result<(), io::Error> {
+        use std::os::unix::process::CommandExt;
+
+        if !self.is_empty() {
+            let sandbox = self.clone();
+            unsafe { command.pre_exec(move || sandbox.enter()) };
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn apply(&self, _command: &mut Command) -> Result<(), io::Error> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sandboxing options such as --read-only-root and --private-tmp are only supported \
+                 on Linux",
+            ))
+        }
+    }
+
+    /// Apply the restrictions to the current process. Runs in the child after fork, so it must
+    /// not allocate. The filesystem is set up first, while the process still has the privileges
+    /// to do so, and the seccomp filter is installed last so that it only applies to the command.
+    #[cfg(target_os = "linux")]
+    fn enter(&self) -> Result<(), io::Error> {
+        let check = |result: libc::c_int| {
+            if result == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        };
+
+        unsafe {
+            if self.private_network {
+                check(libc::unshare(libc::CLONE_NEWNET))?;
+                loopback_up()?;
+            }
+            let root = c"/".as_ptr();
+            if self.read_only_root || self.private_tmp {
+                check(libc::unshare(libc::CLONE_NEWNS))?;
+                // Keep our changes from propagating back to the mounts we were started with.
+                check(libc::mount(
+                    std::ptr::null(),
+                    root,
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ))?;
+                // Make the new root a mount of its own, so that it can be remounted read-only.
+                if let Some(new_root) = &self.chroot {
+                    check(libc::mount(
+                        new_root.as_ptr(),
+                        new_root.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ))?;
+                }
+            }
+            // Paths from here on are within the new root.
+            if let Some(new_root) = &self.chroot {
+                check(libc::chroot(new_root.as_ptr()))?;
+                check(libc::chdir(self.chdir.as_deref().unwrap_or(c"/").as_ptr()))?;
+            }
+            if self.private_tmp {
+                check(libc::mount(
+                    c"tmpfs".as_ptr(),
+                    c"/tmp".as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    c"mode=1777".as_ptr().cast(),
+                ))?;
+            }
+            // The new /tmp is a mount of its own, so it stays writable.
+            if self.read_only_root {
+                check(libc::mount(
+                    std::ptr::null(),
+                    root,
+                    std::ptr::null(),
+                    libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY,
+                    std::ptr::null(),
+                ))?;
+            }
+            // The kernel only lets unprivileged processes install a seccomp filter once
+            // no_new_privs is set.
+            if self.no_new_privileges || self.seccomp_filter.is_some() {
+                check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            }
+            if let Some(filter) = &self.seccomp_filter {
+                let program = libc::sock_fprog {
+                    len: filter.len() as libc::c_ushort,
+                    filter: filter.as_ptr() as *mut libc::sock_filter,
+                };
+                check(libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                ))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Bring up the loopback interface of a new network namespace, which starts out down, so that the
+/// command can still reach services of its own on localhost.
+#[cfg(target_os = "linux")]
+unsafe fn loopback_up() -> Result<(), io::Error> {
+    let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut request: libc::ifreq = std::mem::zeroed();
+    for (to, from) in request.ifr_name.iter_mut().zip(b"lo") {
+        *to = *from as libc::c_char;
+    }
+    let mut result = libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request);
+    if result == 0 {
+        request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        result = libc::ioctl(socket, libc::SIOCSIFFLAGS, &request);
+    }
+    let error = io::Error::last_os_error();
+    libc::close(socket);
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// A path as a C string, for use after fork when allocating isn't allowed.
+pub(crate) fn c_path(path: &Path) -> Result<CString, io::Error> {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    // Sandboxing isn't supported elsewhere, so the path is never used.
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+
+    CString::new(bytes).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} contains a NUL byte", path.display()),
+        )
+    })
+}
+
+/// Read a compiled seccomp filter, as written by libseccomp's `seccomp_export_bpf`: a classic
+/// BPF program of 8-byte instructions in native byte order.
+pub(crate) fn load_seccomp_filter(path: &Path) -> Result<Arc<[Instruction]>, io::Error> {
+    parse_filter(&fs::read(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a seccomp filter: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse_filter(bytes: &[u8]) -> Result<Arc<[Instruction]>, String> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(INSTRUCTION_SIZE) {
+        return Err(format!(
+            "expected a whole number of {}-byte instructions, found {} bytes",
+            INSTRUCTION_SIZE,
+            bytes.len()
+        ));
+    } else if bytes.len() / INSTRUCTION_SIZE > MAX_INSTRUCTIONS {
+        return Err(format!(
+            "expected at most {} instructions, found {}",
+            MAX_INSTRUCTIONS,
+            bytes.len() / INSTRUCTION_SIZE
+        ));
+    }
+
+    Ok(bytes
+        .chunks_exact(INSTRUCTION_SIZE)
+        .map(|chunk| Instruction {
+            code: u16::from_ne_bytes([chunk[0], chunk[1]]),
+            jt: chunk[2],
+            jf: chunk[3],
+            k: u32::from_ne_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `BPF_RET | BPF_K` returning `SECCOMP_RET_ALLOW`, a filter which allows every system call.
+    fn allow_all() -> Vec<u8> {
+        let mut bytes = 0x06u16.to_ne_bytes().to_vec();
+        bytes.extend([0, 0]);
+        bytes.extend(0x7fff_0000u32.to_ne_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(
+            &*parse_filter(&allow_all()).unwrap(),
+            [Instruction {
+                code: 0x06,
+                jt: 0,
+                jf: 0,
+                k: 0x7fff_0000
+            }]
+        );
+        assert!(parse_filter(&[]).is_err());
+        assert!(parse_filter(&allow_all()[..6]).is_err());
+        assert!(parse_filter(&allow_all().repeat(MAX_INSTRUCTIONS + 1)).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sandboxed_command() {
+        let sandbox = Sandbox {
+            seccomp_filter: Some(parse_filter(&allow_all()).unwrap()),
+            ..Sandbox::default()
+        };
+        let mut command = Command::new("grep");
+        command.args(["NoNewPrivs", "/proc/self/status"]);
+        sandbox.apply(&mut command).unwrap();
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).ends_with("\t1\n"));
+    }
+}
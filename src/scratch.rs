@@ -0,0 +1,82 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+
+/// A fresh, empty directory for a single attempt to work in, removed once the attempt is over
+/// unless it's kept, so that nothing one attempt leaves behind can trip up the next.
+#[derive(Debug)]
+pub(crate) struct ScratchDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl ScratchDir {
+    /// Create the directory for the given attempt in the system's temporary directory. Only its
+    /// owner can use it.
+    pub(crate) fn create(attempt: usize) -> Result<Self, io::Error> {
+        Self::create_named(&attempt.to_string())
+    }
+
+    /// Create a directory in the system's temporary directory with a name which says what it's
+    /// for, but which can't be predicted. Only its owner can use it.
+    pub(crate) fn create_named(name: &str) -> Result<Self, io::Error> {
+        let path = env::temp_dir().join(format!(
+            "attempt-{}-{}-{}",
+            std::process::id(),
+            name,
+            rand::random::<u64>()
+        ));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&path)?;
+
+        Ok(Self { path, keep: false })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the directory in place once the attempt is over, returning where it is.
+    pub(crate) fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            if let Err(e) = fs::remove_dir_all(&self.path) {
+                warn!(
+                    "Failed to remove the temporary directory {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scratch_dir() {
+        let scratch = ScratchDir::create(1).unwrap();
+        let path = scratch.path().to_path_buf();
+        fs::write(path.join("state"), "stale").unwrap();
+        drop(scratch);
+        assert!(!path.exists());
+
+        let scratch = ScratchDir::create(2).unwrap();
+        let path = scratch.keep();
+        assert!(path.is_dir());
+        fs::remove_dir(&path).unwrap();
+    }
+}
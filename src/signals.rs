@@ -0,0 +1,145 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::{logger::write_messages, pid_file::PidFile, status::SharedStatus};
+
+/// How long `attempt` has spent stopped by SIGTSTP in total, along with when the current stop
+/// began, if it hasn't been accounted for yet.
+static STOPPED: Mutex<(Duration, Option<Instant>)> = Mutex::new((Duration::ZERO, None));
+
+/// How long `attempt` has spent stopped by SIGTSTP in total, so that waits can be extended by the
+/// time they were paused for. Counts the current stop as well, so that other threads which
+/// resume before the signal handler has caught up don't see it as time spent waiting.
+pub(crate) fn stopped_for() -> Duration {
+    let (total, since) = *STOPPED.lock().unwrap();
+    total + since.map_or(Duration::ZERO, |since| since.elapsed())
+}
+
+/// Handle signals sent to `attempt` on a background thread.
+///
+/// SIGUSR1 prints the current status to stderr. SIGTSTP stops the running command along with
+/// `attempt`, and both carry on when `attempt` is continued, without counting the time stopped
+/// against the current wait. The first SIGINT sets `interrupted`, which stops any further
+/// retries without signalling the running command; a second SIGINT kills the command. This
+/// doesn't hold for Ctrl-C at a terminal, which also reaches the command directly, as it's left
+/// in our process group so that it can still read from the terminal. SIGTERM and SIGHUP end
+/// `attempt` immediately, passing the signal on to the running command unless `kill_on_exit` is
+/// false. The pid file is removed along with the command, and left in place if the command is
+/// left running. With a `reload_signal`, SIGHUP instead sends that signal to the running
+/// command, and `attempt` carries on.
+#[cfg(unix)]
+pub(crate) fn handle_signals(
+    status: SharedStatus,
+    interrupted: Arc<AtomicBool>,
+    kill_on_exit: bool,
+    pid_file: Option<PidFile>,
+    reload_signal: Option<i32>,
+) -> Result<(), io::Error> {
+    use crate::util::format_duration;
+    use signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGTERM, SIGTSTP, SIGUSR1},
+        iterator::Signals,
+        low_level::emulate_default_handler,
+    };
+
+    let mut signals = Signals::new([SIGINT, SIGUSR1, SIGTERM, SIGHUP, SIGTSTP])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGUSR1 => write_messages(&format!("{}\n", status.lock().unwrap())),
+                SIGTSTP => {
+                    let child = status.lock().unwrap().child_pid();
+                    if let Some(pid) = child {
+                        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTSTP) };
+                    }
+                    // Stop as we would have without a handler, returning once continued.
+                    STOPPED.lock().unwrap().1 = Some(Instant::now());
+                    let _ = emulate_default_handler(SIGTSTP);
+                    let paused = {
+                        let mut stopped = STOPPED.lock().unwrap();
+                        let paused = stopped
+                            .1
+                            .take()
+                            .map_or(Duration::ZERO, |since| since.elapsed());
+                        stopped.0 += paused;
+                        paused
+                    };
+                    status.lock().unwrap().paused(paused);
+                    if let Some(pid) = child {
+                        unsafe { libc::kill(pid as libc::pid_t, libc::SIGCONT) };
+                    }
+                    info!(
+                        "Continuing after being stopped for {}",
+                        format_duration(paused)
+                    );
+                }
+                SIGINT if !interrupted.swap(true, Ordering::SeqCst) => {
+                    if status.lock().unwrap().child_pid().is_some() {
+                        warn!("Interrupted, no further attempts will be made. Interrupt again to kill the command.");
+                    } else {
+                        warn!("Interrupted, no further attempts will be made.");
+                    }
+                }
+                SIGINT => {
+                    if let Some(pid) = status.lock().unwrap().child_pid() {
+                        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+                    }
+                }
+                SIGTERM | SIGHUP => {
+                    if let (SIGHUP, Some(reload_signal)) = (signal, reload_signal) {
+                        match status.lock().unwrap().child_pid() {
+                            Some(pid) => {
+                                info!(
+                                    "Received SIGHUP, sending signal {} to the command to reload it",
+                                    reload_signal
+                                );
+                                unsafe { libc::kill(pid as libc::pid_t, reload_signal) };
+                            }
+                            None => {
+                                info!("Received SIGHUP, but the command isn't running to reload")
+                            }
+                        }
+                        continue;
+                    }
+
+                    match status.lock().unwrap().child_pid() {
+                        Some(pid) if !kill_on_exit => {
+                            info!("Leaving the command running as pid {}", pid)
+                        }
+                        child => {
+                            if let Some(pid) = child {
+                                unsafe { libc::kill(pid as libc::pid_t, signal) };
+                            }
+                            if let Some(pid_file) = &pid_file {
+                                pid_file.remove();
+                            }
+                        }
+                    }
+                    std::process::exit(128 + signal);
+                }
+                _ => unreachable!(),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn handle_signals(
+    _status: SharedStatus,
+    _interrupted: Arc<AtomicBool>,
+    _kill_on_exit: bool,
+    _pid_file: Option<PidFile>,
+    _reload_signal: Option<i32>,
+) -> Result<(), io::Error> {
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    ops::Deref,
+    path::PathBuf,
+};
+
+use log::warn;
+
+/// Output held in memory until it grows past a threshold, after which all of it is moved to a
+/// temporary file so that very large outputs don't have to fit in memory.
+#[derive(Debug, Default)]
+pub(crate) struct SpillBuffer {
+    /// How many bytes to hold in memory before spilling, or `None` to never spill.
+    threshold: Option<u64>,
+    memory: Vec<u8>,
+    file: Option<SpillFile>,
+    /// Why output was lost, if writing it to the file failed.
+    lost: Option<String>,
+}
+
+impl SpillBuffer {
+    pub(crate) fn new(threshold: Option<u64>) -> Self {
+        Self {
+            threshold,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn append(&mut self, bytes: &[u8]) {
+        if let Some(file) = &mut self.file {
+            match file.file.write_all(bytes) {
+                Ok(()) => return,
+                Err(e) => {
+                    // Whatever already reached the file is lost, and decisions based on the
+                    // output can't be trusted, so this is reported rather than only logged.
+                    self.lost.get_or_insert(format!(
+                        "Failed to write output to {}: {}",
+                        file.path.display(),
+                        e
+                    ));
+                    self.file = None;
+                    self.threshold = None;
+                }
+            }
+        }
+
+        self.memory.extend_from_slice(bytes);
+        let spill = self
+            .threshold
+            .is_some_and(|threshold| self.memory.len() as u64 > threshold);
+        if spill {
+            match SpillFile::create().and_then(|mut file| {
+                file.file.write_all(&self.memory)?;
+                Ok(file)
+            }) {
+                Ok(file) => {
+                    self.file = Some(file);
+                    self.memory = Vec::new();
+                }
+                Err(e) => {
+                    warn!("Failed to spill output to a temporary file: {}", e);
+                    self.threshold = None;
+                }
+            }
+        }
+    }
+
+    /// Why some of the output was lost, if it was.
+    pub(crate) fn lost(&self) -> Option<&str> {
+        self.lost.as_deref()
+    }
+
+    /// Everything appended so far, leaving the buffer empty.
+    pub(crate) fn take(&mut self) -> Result<SpilledBytes, io::Error> {
+        match self.file.take() {
+            Some(file) => file.into_bytes(),
+            None => Ok(SpilledBytes::Memory(std::mem::take(&mut self.memory))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl SpillFile {
+    /// Create the file in the system's temporary directory with a name which can't be predicted,
+    /// so that no one else can create it first, and which only its owner can read.
+    fn create() -> Result<Self, io::Error> {
+        let path = env::temp_dir().join(format!(
+            "attempt-output-{}-{}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path)?;
+        // On Unix the file lives on until it's closed, so it can't be left behind.
+        #[cfg(unix)]
+        let _ = fs::remove_file(&path);
+
+        Ok(Self { file, path })
+    }
+
+    #[cfg(unix)]
+    fn into_bytes(self) -> Result<SpilledBytes, io::Error> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = self.file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(SpilledBytes::Memory(Vec::new()));
+        }
+        let address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(SpilledBytes::Mapped {
+            address: address.cast(),
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn into_bytes(self) -> Result<SpilledBytes, io::Error> {
+        drop(self.file);
+        let bytes = fs::read(&self.path);
+        let _ = fs::remove_file(&self.path);
+        bytes.map(SpilledBytes::Memory)
+    }
+}
+
+/// The output of an attempt, either in memory or mapped from the file it was spilled to.
+#[derive(Debug)]
+pub(crate) enum SpilledBytes {
+    Memory(Vec<u8>),
+    #[cfg(unix)]
+    Mapped {
+        address: *const u8,
+        len: usize,
+    },
+}
+
+// A mapping is private and read-only, and is only unmapped when its owner drops it, so it can be
+// handed to another thread and read from several at once like a `Vec`.
+#[cfg(unix)]
+unsafe impl Send for SpilledBytes {}
+#[cfg(unix)]
+unsafe impl Sync for SpilledBytes {}
+
+impl Default for SpilledBytes {
+    fn default() -> Self {
+        Self::Memory(Vec::new())
+    }
+}
+
+impl Deref for SpilledBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Memory(bytes) => bytes,
+            #[cfg(unix)]
+            Self::Mapped { address, len } => unsafe { std::slice::from_raw_parts(*address, *len) },
+        }
+    }
+}
+
+impl Drop for SpilledBytes {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Mapped { address, len } = *self {
+            unsafe { libc::munmap(address as *mut libc::c_void, len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memory() {
+        let mut buffer = SpillBuffer::new(None);
+        buffer.append(b"one ");
+        buffer.append(b"two");
+        let bytes = buffer.take().unwrap();
+        assert!(matches!(bytes, SpilledBytes::Memory(_)));
+        assert_eq!(&*bytes, b"one two");
+    }
+
+    #[test]
+    fn test_spill() {
+        let mut buffer = SpillBuffer::new(Some(4));
+        buffer.append(b"one ");
+        assert!(buffer.file.is_none());
+        buffer.append(b"two ");
+        assert!(buffer.file.is_some());
+        assert!(buffer.memory.is_empty());
+        buffer.append(b"three");
+        assert_eq!(&*buffer.take().unwrap(), b"one two three");
+        assert!(buffer.take().unwrap().is_empty());
+        assert_eq!(buffer.lost(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = SpillFile::create().unwrap();
+        let mode = file.file.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_ne!(file.path, SpillFile::create().unwrap().path);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_lost() {
+        let mut buffer = SpillBuffer::new(Some(0));
+        buffer.append(b"one ");
+        assert!(buffer.file.is_some());
+        // Replace the spill file with one which can't be written to.
+        buffer.file.as_mut().unwrap().file = File::open("/dev/null").unwrap();
+        buffer.append(b"two");
+        assert!(buffer
+            .lost()
+            .unwrap()
+            .starts_with("Failed to write output to "));
+        assert_eq!(&*buffer.take().unwrap(), b"two");
+    }
+}
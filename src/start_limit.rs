@@ -0,0 +1,92 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::util::{format_duration, parse_duration};
+
+/// A limit on how often the command may fail in a short time, given as "N:DURATION", like
+/// systemd's StartLimitBurst and StartLimitIntervalSec. A command which keeps failing that quickly
+/// is most likely broken for good, rather than flaky.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StartLimit {
+    pub failures: usize,
+    pub within: Duration,
+}
+
+impl StartLimit {
+    /// Whether enough of the failed attempts, which ended at the given times, ended within the
+    /// limit's interval before `now` to hit it.
+    pub(crate) fn hit(
+        &self,
+        failures: impl IntoIterator<Item = SystemTime>,
+        now: SystemTime,
+    ) -> bool {
+        failures
+            .into_iter()
+            .filter(|ended| {
+                now.duration_since(*ended)
+                    .map_or(true, |since| since <= self.within)
+            })
+            .count()
+            >= self.failures
+    }
+}
+
+impl FromStr for StartLimit {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (failures, within) = text
+            .split_once(':')
+            .ok_or_else(|| format!("{} is not of the form N:DURATION", text))?;
+        let failures = match failures.trim().parse() {
+            Ok(failures) if failures > 0 => failures,
+            _ => return Err(format!("{} is not a positive number of failures", failures)),
+        };
+        let within = match parse_duration(within.trim()) {
+            Some(within) if !within.is_zero() => within,
+            _ => return Err(format!("{} is not a positive duration", within)),
+        };
+
+        Ok(Self { failures, within })
+    }
+}
+
+impl fmt::Display for StartLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.failures, format_duration(self.within))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let limit: StartLimit = "5:10s".parse().unwrap();
+        assert_eq!(
+            limit,
+            StartLimit {
+                failures: 5,
+                within: Duration::from_secs(10)
+            }
+        );
+        assert_eq!("3:2m".parse::<StartLimit>().unwrap().to_string(), "3:2m 0s");
+        assert!("0:10s".parse::<StartLimit>().is_err());
+        assert!("5:0s".parse::<StartLimit>().is_err());
+        assert!("5/10s".parse::<StartLimit>().is_err());
+    }
+
+    #[test]
+    fn test_hit() {
+        let limit: StartLimit = "3:10s".parse().unwrap();
+        let now = SystemTime::now();
+        let ago = |secs| now - Duration::from_secs(secs);
+        assert!(limit.hit([ago(9), ago(5), ago(0)], now));
+        assert!(!limit.hit([ago(30), ago(5), ago(0)], now));
+        assert!(!limit.hit([ago(5), ago(0)], now));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    report::json_string,
+    util::{format_clock_time, format_duration},
+};
+
+/// A snapshot of where `attempt` is in its schedule, reported on request.
+#[derive(Debug)]
+pub(crate) struct Status {
+    attempt: usize,
+    attempts: Option<usize>,
+    started: Instant,
+    child: Option<(u32, Instant)>,
+    wait_until: Option<Instant>,
+    schedule: String,
+}
+
+pub(crate) type SharedStatus = Arc<Mutex<Status>>;
+
+impl Status {
+    pub(crate) fn new(attempts: Option<usize>, schedule: String) -> Self {
+        Self {
+            attempt: 0,
+            attempts,
+            started: Instant::now(),
+            child: None,
+            wait_until: None,
+            schedule,
+        }
+    }
+
+    pub(crate) fn attempt_started(&mut self) {
+        self.attempt += 1;
+        self.wait_until = None;
+    }
+
+    pub(crate) fn child_started(&mut self, pid: u32) {
+        self.child = Some((pid, Instant::now()));
+    }
+
+    pub(crate) fn child_pid(&self) -> Option<u32> {
+        self.child.map(|(pid, _)| pid)
+    }
+
+    pub(crate) fn child_exited(&mut self) {
+        self.child = None;
+    }
+
+    pub(crate) fn waiting(&mut self, duration: Duration) {
+        self.wait_until = Some(Instant::now() + duration);
+    }
+
+    /// The number of attempts allowed, or `None` if there's no limit.
+    pub(crate) fn attempt_limit(&self) -> Option<usize> {
+        self.attempts
+    }
+
+    /// Allow more attempts than the schedule did, returning the new limit, or `None` if there's
+    /// no limit to extend.
+    pub(crate) fn extend(&mut self, by: usize) -> Option<usize> {
+        let attempts = self.attempts.as_mut()?;
+        *attempts = attempts.saturating_add(by);
+        Some(*attempts)
+    }
+
+    pub(crate) fn is_waiting(&self) -> bool {
+        self.wait_until.is_some() && self.child.is_none()
+    }
+
+    /// The status as a list of JSON fields, without the enclosing braces.
+    pub(crate) fn to_json(&self) -> String {
+        let number = |number: Option<u64>| number.map_or(String::from("null"), |n| n.to_string());
+        let secs = |duration: Option<Duration>| {
+            duration.map_or(String::from("null"), |d| format!("{:.3}", d.as_secs_f64()))
+        };
+        format!(
+            "\"attempt\":{},\"attempts\":{},\"child_pid\":{},\"running_for_secs\":{},\"next_attempt_in_secs\":{},\"elapsed_secs\":{},\"schedule\":{}",
+            self.attempt,
+            number(self.attempts.map(|attempts| attempts as u64)),
+            number(self.child.map(|(pid, _)| pid.into())),
+            secs(self.child.map(|(_, started)| started.elapsed())),
+            secs(
+                self.wait_until
+                    .map(|until| until.saturating_duration_since(Instant::now()))
+            ),
+            secs(Some(self.started.elapsed())),
+            json_string(&self.schedule)
+        )
+    }
+
+    /// Push the end of the current wait back by the time `attempt` was stopped for.
+    pub(crate) fn paused(&mut self, duration: Duration) {
+        if let Some(wait_until) = &mut self.wait_until {
+            *wait_until += duration;
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.attempts {
+            Some(attempts) => writeln!(f, "attempt {} of {}", self.attempt, attempts)?,
+            None => writeln!(f, "attempt {}", self.attempt)?,
+        }
+        if let Some((pid, started)) = self.child {
+            writeln!(
+                f,
+                "child pid: {} (running for {})",
+                pid,
+                format_duration(started.elapsed())
+            )?;
+        }
+        if let Some(wait_until) = self.wait_until {
+            let remaining = wait_until.saturating_duration_since(Instant::now());
+            writeln!(
+                f,
+                "next attempt in: {} (at {})",
+                format_duration(remaining),
+                format_clock_time(SystemTime::now() + remaining)
+            )?;
+        }
+        writeln!(f, "elapsed: {}", format_duration(self.started.elapsed()))?;
+        write!(f, "schedule: {}", self.schedule)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report_while_running() {
+        let mut status = Status::new(Some(3), String::from("fixed"));
+        status.attempt_started();
+        status.child_started(1234);
+        let report = status.to_string();
+        assert!(report.starts_with("attempt 1 of 3\n"));
+        assert!(report.contains("child pid: 1234"));
+        assert!(!report.contains("next attempt in"));
+        assert!(report.ends_with("schedule: fixed"));
+    }
+
+    #[test]
+    fn test_report_while_waiting() {
+        let mut status = Status::new(Some(3), String::from("fixed"));
+        status.attempt_started();
+        status.child_started(1234);
+        status.child_exited();
+        status.waiting(Duration::from_secs(60));
+        let report = status.to_string();
+        assert!(!report.contains("child pid"));
+        assert!(report.contains("next attempt in: "));
+        assert!(report.contains(" (at "));
+    }
+
+    #[test]
+    fn test_paused() {
+        let mut status = Status::new(Some(3), String::from("fixed"));
+        status.waiting(Duration::from_secs(60));
+        status.paused(Duration::from_secs(3600));
+        assert!(status.to_string().contains("next attempt in: 1h"));
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut status = Status::new(Some(3), String::from("fixed"));
+        status.attempt_started();
+        status.child_started(1234);
+        assert_eq!(status.extend(2), Some(5));
+        assert_eq!(status.attempt_limit(), Some(5));
+        let json = status.to_json();
+        assert!(json.starts_with("\"attempt\":1,\"attempts\":5,\"child_pid\":1234,"));
+        assert!(json.contains("\"next_attempt_in_secs\":null,"));
+        assert!(json.ends_with("\"schedule\":\"fixed\""));
+
+        assert_eq!(Status::new(None, String::from("fixed")).extend(2), None);
+    }
+}
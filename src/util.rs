@@ -1,9 +1,69 @@
-use std::time::Duration;
+use std::{
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use rand::Rng;
 use rand_distr::{Distribution, Uniform};
 
 use crate::arguments::WaitParameters;
 
+/// The sysexits(3) code for a temporary failure which may succeed if retried.
+pub(crate) const EX_TEMPFAIL: i32 = 75;
+
+pub(crate) fn build_command(command: &[String]) -> Command {
+    let mut c = Command::new(&command[0]);
+    c.args(&command[1..]);
+
+    c
+}
+
+/// Replace `{name}` placeholders in `text` with their values, and `{{` and `}}` with literal
+/// braces. Placeholders without a value are left as they are.
+pub(crate) fn fill_placeholders(text: &str, values: &[(&str, String)]) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        filled.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            filled.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let value = rest.strip_prefix('{').and_then(|after| {
+            let (name, _) = after.split_once('}')?;
+            let (_, value) = values.iter().find(|(key, _)| *key == name)?;
+            Some((name.len() + 2, value))
+        });
+        match value {
+            Some((len, value)) => {
+                filled.push_str(value);
+                rest = &rest[len..];
+            }
+            None => {
+                filled.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+/// The arguments to run a command line through the platform's shell.
+#[cfg(unix)]
+pub(crate) fn shell_command(command: &str) -> Vec<String> {
+    vec![String::from("sh"), String::from("-c"), command.to_string()]
+}
+
+#[cfg(not(unix))]
+pub(crate) fn shell_command(command: &str) -> Vec<String> {
+    vec![String::from("cmd"), String::from("/C"), command.to_string()]
+}
+
 pub(crate) fn duration_from_f64(interval: f64) -> Option<Duration> {
     let millis = 1000.0 * interval;
     if millis >= 0.0 && millis < u64::MAX as f64 {
@@ -13,9 +73,288 @@ pub(crate) fn duration_from_f64(interval: f64) -> Option<Duration> {
     }
 }
 
-pub(crate) fn process_wait_params(interval: f64, params: WaitParameters) -> f64 {
+/// Parse a duration such as "120", "120s", "1.5 minutes" or "500ms". A bare number is taken to
+/// be seconds.
+pub(crate) fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let scale = match unit.trim() {
+        "" | "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+        "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => 0.001,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+        "d" | "day" | "days" => 86400.0,
+        _ => return None,
+    };
+
+    duration_from_f64(number.parse::<f64>().ok()? * scale)
+}
+
+/// Parse hex-encoded bytes such as "deadbeef" or "0x1b 5b", ignoring whitespace and case.
+pub(crate) fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    let digits: Vec<u8> = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    )
+}
+
+/// Format bytes as lowercase hex, the inverse of `parse_hex`.
+pub(crate) fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The offset of the first occurrence of `needle` in `haystack`.
+pub(crate) fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Parse a size in bytes such as "512", "100K", "1.5G" or "2GiB". Units are powers of 1024.
+pub(crate) fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let unit = unit.trim().to_ascii_uppercase();
+    let exponent = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return None,
+    };
+
+    let bytes = number.parse::<f64>().ok()? * 1024f64.powi(exponent);
+    (bytes >= 0.0 && bytes < u64::MAX as f64).then_some(bytes as u64)
+}
+
+/// Format a size in bytes for humans, e.g. "1.5 GiB" or "512 B".
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Format a duration for humans, e.g. "1h 2m 3s" or "250ms".
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds == 0 {
+        return format!("{}ms", duration.as_millis());
+    }
+
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// The current time in seconds since the Unix epoch.
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC, such as "2022-09-04T18:30:00.250Z".
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Parse a point in time given as an RFC 3339 timestamp such as "2022-09-04T18:30:00Z", or in
+/// seconds of Unix time, such as "1662316200". The seconds may be left out of a timestamp, and
+/// without a timezone it's taken to be local.
+pub(crate) fn parse_timestamp(text: &str) -> Option<SystemTime> {
+    if let Ok(seconds) = text.parse() {
+        return Some(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+
+    let (date, time) = text.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-').map(str::parse::<u32>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(i) if time[i..].eq_ignore_ascii_case("z") => (&time[..i], Some(0)),
+        Some(i) => {
+            let (hours, minutes) = time[i + 1..].split_once(':')?;
+            let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            (
+                &time[..i],
+                Some(if &time[i..=i] == "-" { -offset } else { offset }),
+            )
+        }
+        None => (time, None),
+    };
+    let mut time = time.splitn(3, ':').map(str::parse::<u32>);
+    let (hours, minutes) = (time.next()?.ok()?, time.next()?.ok()?);
+    let seconds = time.next().unwrap_or(Ok(0)).ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hours > 23
+        || minutes > 59
+        || seconds > 60
+    {
+        return None;
+    }
+
+    let as_utc = days_from_civil(i64::from(year), month, day) * 86400
+        + i64::from(hours * 3600 + minutes * 60 + seconds);
+    // The local offset is looked up at the time in UTC, which is only wrong within a few hours of
+    // a daylight saving change.
+    let since_epoch = as_utc - offset.unwrap_or_else(|| utc_offset(as_utc));
+    Some(UNIX_EPOCH + Duration::from_secs(since_epoch.try_into().ok()?))
+}
+
+/// The number of days since 1970-01-01 of the given date, the inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of the given number of days since 1970-01-01, using Howard
+/// Hinnant's algorithm for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Format the local time of day as HH:MM:SS.mmm.
+pub(crate) fn format_time_of_day(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "{}.{:03}",
+        format_clock_time(time),
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Format the local time of day to the second, as HH:MM:SS.
+pub(crate) fn format_clock_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() as i64 + utc_offset(since_epoch.as_secs() as i64);
+    let seconds = seconds.rem_euclid(24 * 60 * 60);
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The local timezone's offset from UTC in seconds at the given time.
+#[cfg(unix)]
+pub(crate) fn utc_offset(since_epoch: i64) -> i64 {
+    let time = since_epoch as libc::time_t;
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut local) }.is_null() {
+        0
+    } else {
+        local.tm_gmtoff as i64
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn utc_offset(_since_epoch: i64) -> i64 {
+    0
+}
+
+/// The host's name, or `None` if it can't be determined.
+#[cfg(unix)]
+pub(crate) fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8(buffer[..len].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// A delay within `[0, window]` derived from `key`, so that the same key always gets the same
+/// delay. The key is hashed with FNV-1a, which unlike the standard library's hasher is stable
+/// across Rust releases and platforms.
+pub(crate) fn stagger_offset(key: &str, window: Duration) -> Duration {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let hash = key.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    });
+
+    let window_millis = window.as_millis().min(u64::MAX as u128 - 1) as u64;
+    Duration::from_millis(hash % (window_millis + 1))
+}
+
+pub(crate) fn process_wait_params(
+    interval: f64,
+    params: WaitParameters,
+    rng: &mut impl Rng,
+) -> f64 {
     let jitter_seconds = match params.jitter {
-        Some(n) => Uniform::new_inclusive(-n, n).sample(&mut rand::thread_rng()),
+        Some(n) => Uniform::new_inclusive(-n, n).sample(rng),
         None => 0.0,
     };
     (interval + jitter_seconds)
@@ -23,13 +362,47 @@ pub(crate) fn process_wait_params(interval: f64, params: WaitParameters) -> f64
         .min(params.wait_max.unwrap_or(f64::MAX))
 }
 
-pub(crate) fn create_duration(interval: f64, wait_params: WaitParameters) -> Duration {
-    duration_from_f64(process_wait_params(interval, wait_params))
-        .expect("Failed to build a duration")
+/// The longest wait a schedule can produce, about 31 years, in seconds. Exponential schedules
+/// soon overflow to infinity without --wait-max, and a --wait-max may be too large for a
+/// `Duration`, so waits are clamped to this instead.
+const MAX_WAIT_SECS: f64 = 1e9;
+
+/// A wait of the given number of seconds, saturating at `MAX_WAIT_SECS` rather than failing for
+/// the infinite waits which overflowing schedules produce.
+fn saturating_duration(seconds: f64) -> Duration {
+    // Only NaN is out of range once clamped.
+    duration_from_f64(seconds.clamp(0.0, MAX_WAIT_SECS)).unwrap_or_default()
+}
+
+pub(crate) fn create_duration(
+    interval: f64,
+    wait_params: WaitParameters,
+    rng: &mut impl Rng,
+) -> Duration {
+    saturating_duration(process_wait_params(interval, wait_params, rng))
+}
+
+/// Scale a wait by how long the failed attempt ran: a command which failed straight away is
+/// unlikely to succeed soon, so wait up to twice as long, while one which ran for at least the
+/// wait got close, so wait as little as half as long. The wait is kept within the bounds.
+pub(crate) fn adapt_wait(wait: Duration, ran_for: Duration, params: WaitParameters) -> Duration {
+    let progress = if wait.is_zero() {
+        1.0
+    } else {
+        (ran_for.as_secs_f64() / wait.as_secs_f64()).min(1.0)
+    };
+    let adapted = wait.as_secs_f64() * (2.0 - 1.5 * progress);
+    saturating_duration(
+        adapted
+            .max(params.wait_min.unwrap_or(0.0))
+            .min(params.wait_max.unwrap_or(f64::MAX)),
+    )
 }
 
 #[cfg(test)]
 mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
     use super::*;
 
     #[test]
@@ -56,10 +429,137 @@ mod test {
         assert_eq!(maybe_duration.unwrap(), Duration::from_secs(1))
     }
 
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_duration(" 120 seconds "),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_duration("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2 hours"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("5 fortnights"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_fill_placeholders() {
+        let values = [
+            ("attempt", String::from("3")),
+            ("last_status", String::new()),
+        ];
+        assert_eq!(
+            fill_placeholders("--out=run-{attempt}.log", &values),
+            "--out=run-3.log"
+        );
+        assert_eq!(
+            fill_placeholders("{{attempt}} {attempt}{last_status}", &values),
+            "{attempt} 3"
+        );
+        assert_eq!(
+            fill_placeholders("{print $1} {} }{", &values),
+            "{print $1} {} }{"
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_millis(1_662_316_200_250)),
+            "2022-09-04T18:30:00.250Z"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_662_316_200);
+        assert_eq!(parse_timestamp("2022-09-04T18:30:00Z"), Some(time));
+        assert_eq!(parse_timestamp("2022-09-04 18:30z"), Some(time));
+        assert_eq!(parse_timestamp("2022-09-04T20:30:00+02:00"), Some(time));
+        assert_eq!(parse_timestamp("2022-09-04T13:00-05:30"), Some(time));
+        assert_eq!(parse_timestamp("1662316200"), Some(time));
+        assert_eq!(
+            parse_timestamp("2000-02-29T00:00:00Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(951_782_400))
+        );
+        assert!(parse_timestamp("2022-09-04").is_none());
+        assert!(parse_timestamp("2022-13-04T18:30Z").is_none());
+        assert!(parse_timestamp("2022-09-04T24:00Z").is_none());
+        assert!(parse_timestamp("tomorrow").is_none());
+    }
+
+    #[test]
+    fn test_format_clock_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_662_316_200_250);
+        let clock = format_clock_time(time);
+        assert_eq!(clock.len(), "18:30:00".len());
+        assert_eq!(format_time_of_day(time), format!("{}.250", clock));
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(parse_hex("deadBEEF"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(parse_hex("0x1b 5b"), Some(vec![0x1b, 0x5b]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+        assert_eq!(parse_hex(""), None);
+        assert_eq!(format_hex(&[0x00, 0xff, 0x1b]), "00ff1b");
+        assert_eq!(find_bytes(b"\x00\xffERR\x01", b"ERR"), Some(2));
+        assert_eq!(find_bytes(b"OK", b"ERR"), None);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("100K"), Some(100 * 1024));
+        assert_eq!(parse_size("1.5g"), Some(3 * 512 * 1024 * 1024));
+        assert_eq!(parse_size("2 GiB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("10MB"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("lots"), None);
+        assert_eq!(parse_size("5 furlongs"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_stagger_offset() {
+        let window = Duration::from_secs(60);
+        let offset = stagger_offset("web-01", window);
+        assert!(offset <= window);
+        assert_eq!(stagger_offset("web-01", window), offset);
+        assert_ne!(stagger_offset("web-02", window), offset);
+        assert_eq!(stagger_offset("web-01", Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_millis(5500)), "5s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 2m 3s");
+    }
+
     #[test]
     fn test_min_wait_is_respected() {
         assert_eq!(
-            process_wait_params(1.0, WaitParameters::new(None, Some(5.0), None)),
+            process_wait_params(
+                1.0,
+                WaitParameters::new(None, Some(5.0), None),
+                &mut rand::thread_rng()
+            ),
             5.0
         );
     }
@@ -67,15 +567,22 @@ mod test {
     #[test]
     fn test_max_wait_is_respected() {
         assert_eq!(
-            process_wait_params(10.0, WaitParameters::new(None, None, Some(5.0))),
+            process_wait_params(
+                10.0,
+                WaitParameters::new(None, None, Some(5.0)),
+                &mut rand::thread_rng()
+            ),
             5.0
         );
     }
 
     #[test]
     fn test_jitter() {
+        let mut rng = rand::thread_rng();
         let outputs = (0..3)
-            .map(|_| process_wait_params(10.0, WaitParameters::new(Some(1.0), None, None)))
+            .map(|_| {
+                process_wait_params(10.0, WaitParameters::new(Some(1.0), None, None), &mut rng)
+            })
             .collect::<Vec<_>>();
         assert!(outputs.iter().any(|n| *n != 10.0));
         assert!(outputs.iter().all(|n| *n >= 9.0 && *n <= 11.0));
@@ -83,9 +590,63 @@ mod test {
 
     #[test]
     fn test_jitter_with_min_max() {
+        let mut rng = rand::thread_rng();
         let outputs = (0..3)
-            .map(|_| process_wait_params(1.0, WaitParameters::new(Some(5.0), Some(0.5), Some(3.0))))
+            .map(|_| {
+                process_wait_params(
+                    1.0,
+                    WaitParameters::new(Some(5.0), Some(0.5), Some(3.0)),
+                    &mut rng,
+                )
+            })
             .collect::<Vec<_>>();
         assert!(outputs.iter().all(|n| *n >= 0.5 && *n <= 3.0));
     }
+
+    #[test]
+    fn test_seeded_jitter_is_reproducible() {
+        let params = WaitParameters::new(Some(1.0), None, None);
+        let jitter = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..5)
+                .map(|_| process_wait_params(10.0, params, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(jitter(42), jitter(42));
+        assert_ne!(jitter(42), jitter(43));
+    }
+
+    #[test]
+    fn test_adapt_wait() {
+        let wait = Duration::from_secs(10);
+        let params = WaitParameters::default();
+        assert_eq!(
+            adapt_wait(wait, Duration::ZERO, params),
+            Duration::from_secs(20)
+        );
+        assert_eq!(
+            adapt_wait(wait, Duration::from_secs(5), params),
+            Duration::from_millis(12500)
+        );
+        assert_eq!(
+            adapt_wait(wait, Duration::from_secs(60), params),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            adapt_wait(
+                wait,
+                Duration::ZERO,
+                WaitParameters::new(None, None, Some(15.0))
+            ),
+            Duration::from_secs(15)
+        );
+        assert_eq!(
+            adapt_wait(
+                wait,
+                Duration::from_secs(60),
+                WaitParameters::new(None, Some(8.0), None)
+            ),
+            Duration::from_secs(8)
+        );
+    }
 }
@@ -0,0 +1,378 @@
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{info, LevelFilter};
+
+use crate::{
+    heartbeat::HeartbeatFile,
+    logger::{holding, messages_are_terminal, write_messages},
+    signals::stopped_for,
+    util::{format_clock_time, format_duration},
+    watch::Watch,
+};
+
+/// How often a wait checks whether the user has asked to skip it.
+const SKIP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often to log that `attempt` is still waiting, so that long waits aren't mistaken for hangs.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// How often a wait checks whether any of the watched paths have changed.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Waits between attempts which the user can cut short to retry immediately, either by pressing
+/// Enter when stdin is a terminal or by sending a signal. Waits also end early once `attempt` has
+/// been interrupted, or when one of the watched paths changes.
+///
+/// When stderr is a terminal, a countdown to the next attempt is shown while waiting. Otherwise a
+/// heartbeat is logged periodically. The heartbeat file, if any, is touched every second.
+///
+/// When simulating, waits are sped up by the given factor and tracked on a simulated clock, while
+/// the countdown and heartbeat still show simulated time.
+pub(crate) struct Waiter {
+    skip_requested: Arc<AtomicBool>,
+    interrupted: Arc<AtomicBool>,
+    stdin_is_tty: bool,
+    show_countdown: bool,
+    attempts: Cell<Option<usize>>,
+    speedup: Option<f64>,
+    started: Instant,
+    /// How far the simulated clock has run ahead of real time.
+    skipped: Cell<Duration>,
+    watch: Option<RefCell<Watch>>,
+    heartbeat: Option<HeartbeatFile>,
+}
+
+impl Waiter {
+    pub(crate) fn new(
+        skip_signal: Option<i32>,
+        interrupted: Arc<AtomicBool>,
+        attempts: Option<usize>,
+        progress: bool,
+        speedup: Option<f64>,
+        watch: &[PathBuf],
+        heartbeat_file: Option<&Path>,
+    ) -> Result<Self, io::Error> {
+        let skip_requested = Arc::new(AtomicBool::new(false));
+        if let Some(signal) = skip_signal {
+            signal_hook::flag::register(signal, Arc::clone(&skip_requested))?;
+        }
+
+        Ok(Self {
+            skip_requested,
+            interrupted,
+            stdin_is_tty: io::stdin().is_terminal(),
+            // Held messages, as with --cronic, take the place of the countdown, so that nothing
+            // is shown unless the run fails.
+            show_countdown: progress
+                && messages_are_terminal()
+                && !holding()
+                && log::max_level() >= LevelFilter::Info,
+            attempts: Cell::new(attempts),
+            speedup,
+            started: Instant::now(),
+            skipped: Cell::new(Duration::ZERO),
+            watch: (!watch.is_empty()).then(|| RefCell::new(Watch::new(watch.to_vec()))),
+            heartbeat: heartbeat_file.map(|path| HeartbeatFile::new(path.to_path_buf())),
+        })
+    }
+
+    /// The flag which cuts the current wait short when set, for skipping waits by other means
+    /// than a signal or Enter.
+    pub(crate) fn skip_requests(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.skip_requested)
+    }
+
+    /// Change the number of attempts shown while waiting, once more have been allowed.
+    pub(crate) fn set_attempt_limit(&self, attempts: Option<usize>) {
+        self.attempts.set(attempts);
+    }
+
+    /// Forget any changes to the watched paths so far, so that only changes made from now on
+    /// cut the next wait short. Called as each attempt starts.
+    pub(crate) fn forget_changes(&self) {
+        if let Some(watch) = &self.watch {
+            watch.borrow_mut().changed();
+        }
+    }
+
+    /// Touch the heartbeat file, if there is one. Called as each attempt starts.
+    pub(crate) fn heartbeat(&self) {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.touch();
+        }
+    }
+
+    /// The time elapsed on the simulated clock, if simulating.
+    pub(crate) fn simulated_elapsed(&self) -> Option<Duration> {
+        self.speedup
+            .map(|_| self.started.elapsed() + self.skipped.get())
+    }
+
+    /// When a wait of the given duration starting now will end by the wall clock.
+    pub(crate) fn ends_at(&self, duration: Duration) -> SystemTime {
+        SystemTime::now() + duration.div_f64(self.speedup.unwrap_or(1.0))
+    }
+
+    /// Sleep for the given duration before `next_attempt`, or until the user asks to skip the
+    /// rest of the wait.
+    pub(crate) fn wait(&self, duration: Duration, next_attempt: usize) {
+        // Only skips requested during this wait count.
+        self.skip_requested.store(false, Ordering::SeqCst);
+
+        let speedup = self.speedup.unwrap_or(1.0);
+        let start = Instant::now();
+        let mut deadline = start + duration.div_f64(speedup);
+        // Time spent stopped by SIGTSTP doesn't count against the wait.
+        let stopped_before = stopped_for();
+        let mut paused = Duration::ZERO;
+        let mut next_heartbeat = start + HEARTBEAT_INTERVAL;
+        let mut next_watch_check = start;
+        let mut shown_countdown = String::new();
+        loop {
+            let now = Instant::now();
+            let stopped = stopped_for() - stopped_before;
+            deadline += stopped - paused;
+            paused = stopped;
+            if now >= deadline
+                || self.interrupted.load(Ordering::SeqCst)
+                || self.skip_requested.swap(false, Ordering::SeqCst)
+            {
+                break;
+            }
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.touch_if_stale();
+            }
+            if let Some(watch) = &self.watch {
+                if now >= next_watch_check {
+                    if watch.borrow_mut().changed() {
+                        info!("A watched path changed, retrying now");
+                        break;
+                    }
+                    next_watch_check = now + WATCH_POLL_INTERVAL;
+                }
+            }
+
+            if self.show_countdown {
+                let line = countdown(
+                    next_attempt,
+                    self.attempts.get(),
+                    (deadline - now).mul_f64(speedup),
+                    self.stdin_is_tty,
+                );
+                if line != shown_countdown {
+                    write_messages(&format!("\r\x1b[2K{}", line));
+                    shown_countdown = line;
+                }
+            } else if now >= next_heartbeat {
+                let of_attempts = self
+                    .attempts
+                    .get()
+                    .map_or(String::new(), |attempts| format!(" of {}", attempts));
+                info!(
+                    "Still waiting, retrying in {} at {} (attempt {}{})",
+                    format_duration((deadline - now).mul_f64(speedup)),
+                    format_clock_time(SystemTime::now() + (deadline - now)),
+                    next_attempt,
+                    of_attempts
+                );
+                next_heartbeat += HEARTBEAT_INTERVAL;
+            }
+
+            let remaining = (deadline - now).min(SKIP_POLL_INTERVAL);
+            if self.stdin_is_tty {
+                if enter_pressed(remaining) {
+                    break;
+                }
+            } else {
+                thread::sleep(remaining);
+            }
+        }
+
+        if !shown_countdown.is_empty() {
+            write_messages("\r\x1b[2K");
+        }
+
+        let waited = start.elapsed().saturating_sub(paused);
+        self.skipped
+            .set(self.skipped.get() + waited.mul_f64(speedup) - waited);
+    }
+}
+
+/// A single line counting down to the next attempt, with a bar showing how much of the attempt
+/// budget has been used if there's a limit.
+fn countdown(
+    next_attempt: usize,
+    attempts: Option<usize>,
+    remaining: Duration,
+    can_skip: bool,
+) -> String {
+    const WIDTH: usize = 20;
+    let used = match attempts {
+        Some(attempts) => {
+            let used = (next_attempt - 1).min(attempts);
+            let filled = used * WIDTH / attempts.max(1);
+            format!(
+                "[{}{}] {}/{}",
+                "#".repeat(filled),
+                "-".repeat(WIDTH - filled),
+                used,
+                attempts
+            )
+        }
+        None => (next_attempt - 1).to_string(),
+    };
+    // Round up, so the countdown reads 1s rather than 0s during the final second.
+    let remaining = Duration::from_secs((remaining.as_millis() as u64).div_ceil(1000));
+
+    format!(
+        "{} attempts used, next attempt in {}{}",
+        used,
+        format_duration(remaining),
+        if can_skip {
+            " (press Enter to retry now)"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Wait up to `timeout` for a line of input on stdin, consuming it if one arrives.
+#[cfg(unix)]
+fn enter_pressed(timeout: Duration) -> bool {
+    let mut stdin = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ready = unsafe { libc::poll(&mut stdin, 1, timeout.as_millis() as libc::c_int) };
+    if ready > 0 && stdin.revents & libc::POLLIN != 0 {
+        // Read the line ourselves so it isn't handed to the next attempt.
+        let mut buffer = [0u8; 1024];
+        unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len()) };
+        true
+    } else {
+        if ready > 0 {
+            // The terminal hung up; poll would return immediately from now on.
+            thread::sleep(timeout);
+        }
+        false
+    }
+}
+
+#[cfg(not(unix))]
+fn enter_pressed(timeout: Duration) -> bool {
+    thread::sleep(timeout);
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn waiter() -> Waiter {
+        Waiter {
+            skip_requested: Arc::new(AtomicBool::new(false)),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            stdin_is_tty: false,
+            show_countdown: false,
+            attempts: Cell::new(Some(3)),
+            speedup: None,
+            started: Instant::now(),
+            skipped: Cell::new(Duration::ZERO),
+            watch: None,
+            heartbeat: None,
+        }
+    }
+
+    #[test]
+    fn test_countdown() {
+        assert_eq!(
+            countdown(2, Some(4), Duration::from_millis(4200), false),
+            "[#####---------------] 1/4 attempts used, next attempt in 5s"
+        );
+        assert_eq!(
+            countdown(4, Some(4), Duration::from_secs(90), true),
+            "[###############-----] 3/4 attempts used, next attempt in 1m 30s (press Enter to retry now)"
+        );
+        assert_eq!(
+            countdown(12, None, Duration::from_secs(60), false),
+            "11 attempts used, next attempt in 1m 0s"
+        );
+    }
+
+    #[test]
+    fn test_wait_runs_to_completion() {
+        let start = Instant::now();
+        waiter().wait(Duration::from_millis(250), 2);
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_skip_ends_wait_early() {
+        let waiter = waiter();
+        let skip_requested = Arc::clone(&waiter.skip_requested);
+        let skipper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            skip_requested.store(true, Ordering::SeqCst);
+        });
+
+        let start = Instant::now();
+        waiter.wait(Duration::from_secs(30), 2);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        skipper.join().unwrap();
+    }
+
+    #[test]
+    fn test_simulated_wait() {
+        let waiter = Waiter {
+            speedup: Some(100.0),
+            ..waiter()
+        };
+        let start = Instant::now();
+        waiter.wait(Duration::from_secs(10), 2);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(waiter.simulated_elapsed().unwrap() >= Duration::from_secs(10));
+        assert_eq!(self::waiter().simulated_elapsed(), None);
+    }
+
+    #[test]
+    fn test_watched_change_ends_wait_early() {
+        let path = std::env::temp_dir().join(format!("attempt-wait-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let waiter = Waiter {
+            watch: Some(RefCell::new(Watch::new(vec![path.clone()]))),
+            ..waiter()
+        };
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                std::fs::write(path, "changed").unwrap();
+            })
+        };
+
+        let start = Instant::now();
+        waiter.wait(Duration::from_secs(30), 2);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        writer.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_interrupt_ends_wait_early() {
+        let waiter = waiter();
+        waiter.interrupted.store(true, Ordering::SeqCst);
+
+        let start = Instant::now();
+        waiter.wait(Duration::from_secs(30), 2);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
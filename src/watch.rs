@@ -0,0 +1,89 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+/// Paths whose changes cut a wait short. Changes are found by comparing a fingerprint of the
+/// paths, and everything under them, to the one taken last time.
+#[derive(Debug)]
+pub(crate) struct Watch {
+    paths: Vec<PathBuf>,
+    fingerprint: u64,
+}
+
+impl Watch {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> Self {
+        let fingerprint = fingerprint(&paths);
+        Self { paths, fingerprint }
+    }
+
+    /// Whether any of the paths has changed since this was last called. Creating, removing or
+    /// modifying a file under a watched directory counts as a change.
+    pub(crate) fn changed(&mut self) -> bool {
+        let fingerprint = fingerprint(&self.paths);
+        let changed = fingerprint != self.fingerprint;
+        self.fingerprint = fingerprint;
+        changed
+    }
+}
+
+/// A hash of the name, size and modification time of each path and everything under it. Paths
+/// which don't exist are skipped, so creating one changes the fingerprint.
+fn fingerprint(paths: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        visit(path, &mut hasher);
+    }
+
+    hasher.finish()
+}
+
+fn visit(path: &Path, hasher: &mut DefaultHasher) {
+    // Symlinks aren't followed, so that a link to a parent directory can't loop forever.
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return;
+    };
+    path.hash(hasher);
+    metadata.len().hash(hasher);
+    metadata.modified().ok().hash(hasher);
+
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+        entries.sort();
+        for entry in entries {
+            visit(&entry, hasher);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_changed() {
+        let dir = std::env::temp_dir().join(format!("attempt-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut watch = Watch::new(vec![dir.clone()]);
+        assert!(!watch.changed());
+
+        fs::create_dir(&dir).unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+        assert!(watch.changed());
+        fs::write(dir.join("main.rs"), "fn main() { todo!() }").unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        fs::remove_file(dir.join("main.rs")).unwrap();
+        assert!(watch.changed());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::util::utc_offset;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A daily window of time, such as "22:00-06:00", outside of which no attempts are started. The
+/// window may span midnight. Times are local unless a timezone is given after the window, as
+/// either "UTC" or an offset such as "+02:00".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeWindow {
+    /// The minute of the day the window opens.
+    start: u32,
+    /// The minute of the day the window closes.
+    end: u32,
+    /// The timezone's offset from UTC in seconds, or `None` for the local timezone.
+    offset: Option<i32>,
+}
+
+impl TimeWindow {
+    /// How long until the window opens, in minutes, or `None` if it's open at `time`.
+    pub(crate) fn opens_in(&self, time: SystemTime) -> Option<u32> {
+        let minute = self.minute_of_day(time);
+        let open = if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        };
+        (!open).then(|| (self.start + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY)
+    }
+
+    fn minute_of_day(&self, time: SystemTime) -> u32 {
+        let since_epoch = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let offset = self
+            .offset
+            .map_or_else(|| utc_offset(since_epoch), i64::from);
+        ((since_epoch + offset).rem_euclid(24 * 60 * 60) / 60) as u32
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (window, zone) = match text.trim().split_once(' ') {
+            Some((window, zone)) => (window, Some(zone.trim())),
+            None => (text.trim(), None),
+        };
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| format!("{} is not of the form HH:MM-HH:MM", window))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(format!("{} is an empty window", window));
+        }
+
+        Ok(Self {
+            start,
+            end,
+            offset: zone.map(parse_zone).transpose()?,
+        })
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )?;
+        match self.offset {
+            Some(0) => write!(f, " UTC"),
+            Some(offset) => write!(
+                f,
+                " {}{:02}:{:02}",
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 3600,
+                offset.abs() / 60 % 60
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a time of day given as HH:MM, returning the minute of the day.
+fn parse_time(text: &str) -> Result<u32, String> {
+    let invalid = || format!("{} is not a time of day (HH:MM)", text);
+    let (hours, minutes) = text
+        .split_once(':')
+        .filter(|(hours, minutes)| hours.len() <= 2 && minutes.len() == 2)
+        .ok_or_else(invalid)?;
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse a timezone given as "UTC" (or "Z") or an offset such as "+02:00", returning its offset
+/// from UTC in seconds.
+fn parse_zone(text: &str) -> Result<i32, String> {
+    if text.eq_ignore_ascii_case("utc") || text == "Z" {
+        return Ok(0);
+    }
+    let invalid = || format!("{} is not UTC or an offset such as +02:00", text);
+    let (sign, offset) = match text.split_at_checked(1) {
+        Some(("+", offset)) => (1, offset),
+        Some(("-", offset)) => (-1, offset),
+        _ => return Err(invalid()),
+    };
+    let minutes = parse_time(offset).map_err(|_| invalid())?;
+    Ok(sign * minutes as i32 * 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    /// The given time of day on 2022-09-04, UTC.
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_662_249_600 + hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn test_parse() {
+        let window: TimeWindow = "22:00-06:30 UTC".parse().unwrap();
+        assert_eq!(window.to_string(), "22:00-06:30 UTC");
+        let window: TimeWindow = "1:05-2:00 -05:30".parse().unwrap();
+        assert_eq!(window.to_string(), "01:05-02:00 -05:30");
+        assert_eq!(
+            "09:00-17:00".parse::<TimeWindow>().unwrap().to_string(),
+            "09:00-17:00"
+        );
+        assert!("09:00-09:00".parse::<TimeWindow>().is_err());
+        assert!("24:00-06:00".parse::<TimeWindow>().is_err());
+        assert!("22:00".parse::<TimeWindow>().is_err());
+        assert!("22:00-06:00 CEST".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn test_opens_in() {
+        let nightly: TimeWindow = "22:00-06:00 UTC".parse().unwrap();
+        assert_eq!(nightly.opens_in(at(23, 0)), None);
+        assert_eq!(nightly.opens_in(at(5, 59)), None);
+        assert_eq!(nightly.opens_in(at(6, 0)), Some(16 * 60));
+        assert_eq!(nightly.opens_in(at(21, 30)), Some(30));
+
+        let office: TimeWindow = "09:00-17:00 +02:00".parse().unwrap();
+        assert_eq!(office.opens_in(at(7, 0)), None);
+        assert_eq!(office.opens_in(at(15, 0)), Some(16 * 60));
+    }
+}